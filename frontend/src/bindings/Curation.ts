@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Collection } from "./Collection";
+
+export interface Curation { featured: Array<string>, priorities: Record<string, number>, collections: Array<Collection>, }
\ No newline at end of file
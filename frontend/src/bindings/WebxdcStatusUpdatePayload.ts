@@ -1,4 +1,6 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { AppInfo } from "./AppInfo";
+import type { Channel } from "./Channel";
+import type { Curation } from "./Curation";
 
-export type WebxdcStatusUpdatePayload = { type: "UpdateWebxdc", serial: number, } | { type: "Outdated", critical: boolean, tag_name: string, } | { type: "UpdateSent" } | { type: "UpdateRequest", serial: number, apps: Array<[string, string]>, } | { type: "Download", app_id: string, } | { type: "DownloadOkay", app_id: string, name: string, data: string, } | { type: "DownloadError", app_id: string, error: string, } | { type: "Update", app_infos: Record<string, (Partial<AppInfo> & {app_id: string} | null)>, serial: number, old_serial: number, updating: Array<string>, } | { type: "Init", app_infos: Array<AppInfo>, serial: number, };
\ No newline at end of file
+export type WebxdcStatusUpdatePayload = { type: "UpdateWebxdc", serial: number, } | { type: "Outdated", critical: boolean, tag_name: string, } | { type: "UpdateSent" } | { type: "UpdateRequest", serial: number, apps: Array<[string, string]>, } | { type: "Download", app_id: string, } | { type: "DownloadOkay", app_id: string, name: string, data: string, } | { type: "DownloadError", app_id: string, error: string, } | { type: "Subscribe", app_id: string, } | { type: "Unsubscribe", app_id: string, } | { type: "SetChannel", channel: Channel, } | { type: "GetIcons", hashes: Array<string>, } | { type: "Icons", icons: Record<string, string>, } | { type: "GetScreenshots", app_id: string, } | { type: "Screenshots", app_id: string, offset: number, screenshots: Array<string>, } | { type: "Subscription", app_id: string, subscribed: boolean, } | { type: "Update", app_infos: Record<string, (Partial<AppInfo> & {app_id: string} | null)>, serial: number, old_serial: number, updating: Array<string>, curation: Curation, } | { type: "Init", app_infos: Array<AppInfo>, serial: number, curation: Curation, };
\ No newline at end of file
@@ -1,9 +1,13 @@
 import { type Component, Show, createSignal } from 'solid-js'
 import type { WebxdcStatusUpdatePayload } from '../bindings/WebxdcStatusUpdatePayload'
+import { sendRequest } from '../utils'
 
 interface OutdatedViewProps {
   updated_received: boolean
   serial: number
+  // If false, the outdated version still works and the upgrade is only suggested.
+  critical: boolean
+  onDismiss: () => void
 }
 
 type UpdateRequest = Extract<WebxdcStatusUpdatePayload, { type: 'UpdateWebxdc' }>
@@ -12,13 +16,15 @@ const AppInfoPreview: Component<OutdatedViewProps> = (props) => {
   const [buttonUsed, setButtonUsed] = createSignal(false)
 
   const update_req = () => {
-    window.webxdc.sendUpdate({ payload: { type: 'UpdateWebxdc', serial: props.serial } as UpdateRequest }, '')
+    sendRequest({ type: 'UpdateWebxdc', serial: props.serial } as UpdateRequest)
     setButtonUsed(true)
   }
   return (
-    <div class="absolute right-0 top-0 grid h-screen w-screen place-content-center p-2">
-      <div class="flex flex-col gap-2 border border-red-700 rounded-xl bg-white p-4">
-        <h1 class='text-center font-bold text-red-700'> Outdated Version </h1>
+    <div class="absolute right-0 top-0 grid w-screen place-content-center p-2" classList={{ 'h-screen': props.critical, 'pointer-events-none': !props.critical }}>
+      <div class="pointer-events-auto flex flex-col gap-2 border rounded-xl bg-white p-4" classList={{ 'border-red-700': props.critical, 'border-blue-500': !props.critical }}>
+        <Show when={props.critical} fallback={<h1 class='text-center font-bold text-blue-500'> Update Available </h1>}>
+          <h1 class='text-center font-bold text-red-700'> Outdated Version </h1>
+        </Show>
         <p> A newer version of the store is available. </p>
         <Show when={!buttonUsed()} fallback={
           <Show when={props.updated_received} fallback={
@@ -32,7 +38,12 @@ const AppInfoPreview: Component<OutdatedViewProps> = (props) => {
             </p>
           </Show>
         }>
-          <button class="self-center btn" onclick={update_req}> Download </button>
+          <div class="flex justify-center gap-2">
+            <button class="btn" onclick={update_req}> Download </button>
+            <Show when={!props.critical}>
+              <button class="btn unimportant" onclick={() => props.onDismiss()}> Later </button>
+            </Show>
+          </div>
         </Show>
       </div>
     </div>
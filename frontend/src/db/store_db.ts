@@ -14,13 +14,15 @@ export class AppInfoDB {
     if (this.db)
       return Promise.resolve(this.db)
     return new Promise((resolve, reject) => {
-      const request = indexedDB.open(this.dbName, 3)
+      const request = indexedDB.open(this.dbName, 4)
       request.onerror = () => reject(request.error)
       request.onsuccess = () => resolve((this.db = request.result))
       request.onupgradeneeded = () => {
         const db = request.result
-        db.createObjectStore('appInfo', { keyPath: 'app_id' })
-        db.createObjectStore('apps')
+        for (const name of ['appInfo', 'apps', 'icons']) {
+          if (!db.objectStoreNames.contains(name))
+            db.createObjectStore(name, name === 'appInfo' ? { keyPath: 'app_id' } : undefined)
+        }
       }
     })
   }
@@ -131,6 +133,40 @@ export class AppInfoDB {
     })
   }
 
+  // Add icons encoded as data URLs by their hashes.
+  async add_icons(icons: Record<string, string>): Promise<void> {
+    const db = await this.open()
+    return new Promise((resolve, reject) => {
+      const transaction = db.transaction('icons', 'readwrite')
+      transaction.onerror = () => reject(transaction.error)
+      const store = transaction.objectStore('icons')
+      Object.entries(icons).forEach(([hash, data]) => store.put(data, hash))
+      transaction.oncomplete = () => resolve()
+    })
+  }
+
+  // Get all cached icons by their hashes.
+  async get_all_icons(): Promise<Record<string, string>> {
+    const db = await this.open()
+    return new Promise((resolve, reject) => {
+      const transaction = db.transaction('icons', 'readonly')
+      transaction.onerror = () => reject(transaction.error)
+      const store = transaction.objectStore('icons')
+      const request = store.openCursor()
+      const result: Record<string, string> = {}
+      request.onsuccess = () => {
+        const cursor = request.result
+        if (cursor) {
+          result[cursor.key as string] = cursor.value
+          cursor.continue()
+        }
+        else {
+          resolve(result)
+        }
+      }
+    })
+  }
+
   async remove_webxdc(id: string): Promise<void> {
     const db = await this.open()
     return new Promise((resolve, reject) => {
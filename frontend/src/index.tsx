@@ -10,13 +10,15 @@ import Fuse from 'fuse.js'
 import { formatDistanceToNow } from 'date-fns'
 import OutdatedView from './components/Outdated'
 import type { WebxdcStatusUpdatePayload } from '~/bindings/WebxdcStatusUpdatePayload'
+import type { Curation } from '~/bindings/Curation'
 
 import { AppInfoDB } from '~/db/store_db'
 import { to_app_infos_by_id, updateHandler } from '~/store-logic'
 import { AppState } from '~/types'
 import type { AppInfoWithState, AppInfosById } from '~/types'
-import mock from '~/mock'
+import mock, { mockIcons } from '~/mock'
 import type { ReceivedStatusUpdate } from '~/webxdc'
+import { sendRequest } from '~/utils'
 
 const fuse_options = {
   keys: [
@@ -30,21 +32,30 @@ const fuse_options = {
 type DownloadResponseOkay = Extract<WebxdcStatusUpdatePayload, { type: 'DownloadOkay' }>
 type UpdateResponse = Extract<WebxdcStatusUpdatePayload, { type: 'Update' }>
 
-function AppInfoModal(item: AppInfoWithState, onDownload: () => void, onForward: () => void, onRemove: () => void, onDragStart?: (ev: DragEvent) => void) {
+function AppInfoModal(item: AppInfoWithState, image: () => string | undefined, screenshots: () => string[] | undefined, onExpand: () => void, onDownload: () => void, onForward: () => void, onRemove: () => void, onDragStart?: (ev: DragEvent) => void, featured = false) {
   const [isExpanded, setIsExpanded] = createSignal(false)
   const summary = item.description.split('\n')[0]
   const description = item.description.slice(summary.length + 1)
   return (
     <li class="w-full p-3">
-      <div class="flex cursor-pointer items-center justify-between gap-2" onClick={() => setIsExpanded(!isExpanded())}>
+      <div class="flex cursor-pointer items-center justify-between gap-2" onClick={() => {
+        if (!isExpanded() && screenshots() === undefined)
+          onExpand()
+        setIsExpanded(!isExpanded())
+      }}>
         <img
-          src={`${item.image}`}
+          src={image() ?? ''}
           alt={item.name}
           class="h-16 w-16 rounded-xl object-cover"
           ondragstart={onDragStart}
           draggable={onDragStart && (item.state === AppState.Received || item.state === AppState.Updating)} />
         <div class="flex-grow-1 overflow-hidden">
-          <h2 class="text-xl font-semibold">{item.name}</h2>
+          <div class="flex items-center gap-1">
+            <h2 class="text-xl font-semibold">{item.name}</h2>
+            <Show when={featured}>
+              <div class="i-material-symbols:star text-yellow-500" title="Featured"></div>
+            </Show>
+          </div>
           <p class="max-width-text truncate text-gray-600">{summary}</p>
           <button class="text-blue-700">
             {isExpanded() ? 'Less' : 'More'}
@@ -82,6 +93,13 @@ function AppInfoModal(item: AppInfoWithState, onDownload: () => void, onForward:
       <Show when={isExpanded()}>
         <div class="flex flex-col">
           <p class="my-2 text-gray-600">{description}</p>
+          <Show when={(screenshots() ?? []).length > 0}>
+            <div class="my-2 flex gap-2 overflow-x-auto">
+              <For each={screenshots()}>
+                {screenshot => <img src={screenshot} alt={`Screenshot of ${item.name}`} class="max-h-80 rounded-xl" />}
+              </For>
+            </div>
+          </Show>
           <div class="my-2">
             <p class="text-sm text-gray-600"><span class="font-bold"> Date: </span>{new Date(Number(item.date) * 1000).toLocaleDateString()} ({item.tag_name})</p>
             <div class="flex items-center gap-1">
@@ -102,6 +120,10 @@ function AppInfoModal(item: AppInfoWithState, onDownload: () => void, onForward:
 interface AppListProps {
   items: AppInfoWithState[]
   search_query: string
+  featured: string[]
+  icons: Record<string, string>
+  screenshots: Record<string, string[]>
+  onExpand: (id: string) => void
   onDownload: (id: string) => void
   onForward: (id: string) => void
   onRemove: (id: string) => void
@@ -130,7 +152,7 @@ const AppList: Component<AppListProps> = (props) => {
       <For each={filtered_items() || props.items}>
         {(item, index) => (
           <>
-            {AppInfoModal(item, () => props.onDownload(item.app_id), () => { props.onForward(item.app_id) }, () => props.onRemove(item.app_id), event => props.onDragStart(event, item))}
+            {AppInfoModal(item, () => props.icons[item.icon], () => props.screenshots[item.app_id], () => props.onExpand(item.app_id), () => props.onDownload(item.app_id), () => { props.onForward(item.app_id) }, () => props.onRemove(item.app_id), event => props.onDragStart(event, item), props.featured.includes(item.app_id))}
             {index() !== filtered_items().length - 1 && <hr />}
           </>
         )
@@ -142,16 +164,36 @@ const AppList: Component<AppListProps> = (props) => {
 
 const Store: Component = () => {
   const [appInfo, setAppInfo] = createStore({} as AppInfosById)
+  const [icons, setIcons] = createStore({} as Record<string, string>) // Icon data URLs by hash
+  const [iconsLoaded, setIconsLoaded] = createSignal(false)
+  const [screenshots, setScreenshots] = createStore({} as Record<string, string[]>) // Screenshot data URLs by app_id
   const [lastSerial, setlastSerial] = useStorage('last-serial', 0) // Last store-serial
   const [updateNeeded, setUpdateNeeded] = useStorage('update-needed', false) // Flag if the frontend is outdated
   const [updateReceived, setUpdateReceived] = useStorage('update-received', false)
+  const [upgradeSuggested, setUpgradeSuggested] = createSignal(false) // Flag if a compatible newer frontend is available
   const [lastUpdateSerial, setlastUpdateSerial] = useStorage('last-update-serial', 0) // Last serial to initialize updateListener
   const [lastUpdate, setlastUpdate] = useStorage('last-update', new Date())
+  const [curation, setCuration] = useStorage<Curation>('curation', { featured: [], priorities: {}, collections: [] })
+  const [collection, setCollection] = useStorage<string | null>('collection', null) // Selected collection
   const [isUpdating, setIsUpdating] = createSignal(false)
   const [query, setSearch] = createSignal('')
   const [showCommit, setShowCommit] = createSignal(false)
   const cached = createMemo(() => Object.values(appInfo).filter(app_info => app_info.state !== AppState.Initial))
 
+  // Apps of the selected collection in the curated order,
+  // or all apps with featured apps first, then by priority and date.
+  const sortedApps = createMemo(() => {
+    const { featured, priorities, collections } = curation()
+    const selected = collections.find(c => c.name === collection())
+    if (selected !== undefined) {
+      return selected.app_ids.filter(app_id => appInfo[app_id] !== undefined).map(app_id => appInfo[app_id])
+    }
+    return Object.values(appInfo).sort((a, b) =>
+      Number(featured.includes(b.app_id)) - Number(featured.includes(a.app_id))
+      || (priorities[b.app_id] ?? 0) - (priorities[a.app_id] ?? 0)
+      || Number(b.date - a.date))
+  })
+
   // automatically update the app list
   const past_time = Math.abs(new Date().getTime() - lastUpdate().getTime()) / 1000
   if (appInfo === undefined || (past_time > 60 * 60)) {
@@ -170,25 +212,45 @@ const Store: Component = () => {
       setlastSerial(1)
     }
   })
+  db.get_all_icons().then((cached_icons) => {
+    setIcons(cached_icons)
+    if (import.meta.env.DEV) {
+      setIcons(mockIcons)
+    }
+    setIconsLoaded(true)
+  })
+
+  // Request the icons which are not cached yet.
+  const requestedIcons = new Set<string>()
+  createEffect(() => {
+    if (!iconsLoaded())
+      return
+    const missing = [...new Set(Object.values(appInfo).map(app_info => app_info.icon))]
+      .filter(hash => hash && icons[hash] === undefined && !requestedIcons.has(hash))
+    if (missing.length > 0) {
+      missing.forEach(hash => requestedIcons.add(hash))
+      sendRequest({ type: 'GetIcons', hashes: missing } as WebxdcStatusUpdatePayload)
+    }
+  })
 
   window.webxdc.setUpdateListener(async (resp: ReceivedStatusUpdate<UpdateResponse | DownloadResponseOkay>) => {
-    updateHandler(resp.payload, db, appInfo, lastSerial, setAppInfo, setlastUpdateSerial, setIsUpdating, setlastUpdate, setUpdateNeeded, setUpdateReceived)
+    updateHandler(resp.payload, db, appInfo, lastSerial, setAppInfo, setlastUpdateSerial, setIsUpdating, setlastUpdate, setUpdateNeeded, setUpdateReceived, setCuration, setIcons, setScreenshots, setUpgradeSuggested)
     setlastSerial(resp.serial)
   }, lastSerial())
 
   async function update() {
     setIsUpdating(true)
     const cached_apps = cached().map(app_info => ([app_info.app_id, app_info.tag_name] as [string, string]))
-    window.webxdc.sendUpdate({
-      payload: { type: 'UpdateRequest', serial: lastUpdateSerial(), apps: cached_apps } as WebxdcStatusUpdatePayload,
-    }, '')
+    sendRequest({ type: 'UpdateRequest', serial: lastUpdateSerial(), apps: cached_apps } as WebxdcStatusUpdatePayload)
+  }
+
+  async function handleExpand(app_id: string) {
+    sendRequest({ type: 'GetScreenshots', app_id } as WebxdcStatusUpdatePayload)
   }
 
   async function handleDownload(app_id: string) {
     setAppInfo(app_id, 'state', AppState.Downloading)
-    window.webxdc.sendUpdate({
-      payload: { type: 'Download', app_id } as WebxdcStatusUpdatePayload,
-    }, '')
+    sendRequest({ type: 'Download', app_id } as WebxdcStatusUpdatePayload)
   }
 
   async function handleForward(app_id: string) {
@@ -216,7 +278,7 @@ const Store: Component = () => {
         console.error('Only base64 files are supported for drag-sending')
         return
       }
-      (window as any).webxdc_custom?.desktopDragFileOut?.(file.name, (file as any).base64, item.image)
+      (window as any).webxdc_custom?.desktopDragFileOut?.(file.name, (file as any).base64, icons[item.icon])
     }
   }
 
@@ -234,6 +296,20 @@ const Store: Component = () => {
                 <div class="i-carbon-search text-blue-700" />
               </button>
             </div>
+            <Show when={curation().collections.length > 0}>
+              <div class="flex flex-wrap justify-center gap-2 px-2 pb-4">
+                <button class="border-2 rounded-2xl px-3 py-1 text-sm" classList={{ 'bg-blue-500 text-white': !curation().collections.some(c => c.name === collection()) }} onClick={() => setCollection(null)}>
+                  All
+                </button>
+                <For each={curation().collections}>
+                  {c => (
+                    <button class="border-2 rounded-2xl px-3 py-1 text-sm" classList={{ 'bg-blue-500 text-white': collection() === c.name }} onClick={() => setCollection(c.name)}>
+                      {c.name}
+                    </button>
+                  )}
+                </For>
+              </div>
+            </Show>
             <hr />
             <Show when={!(lastSerial() === 0)} fallback={<div class="text-center unimportant mt-5">
               <p class="mb-2">
@@ -246,7 +322,11 @@ const Store: Component = () => {
             </div>}>
               <ul class="w-full flex flex-grow flex-col gap-1 p-2">
                 <AppList
-                  items={Object.values(appInfo).sort((a, b) => Number(b.date - a.date))} search_query={query()}
+                  items={sortedApps()} search_query={query()}
+                  featured={curation().featured}
+                  icons={icons}
+                  screenshots={screenshots}
+                  onExpand={handleExpand}
                   onDownload={handleDownload}
                   onForward={handleForward}
                   onRemove={handleRemove}
@@ -269,8 +349,8 @@ const Store: Component = () => {
         </div >
       </div>
       {/* modals */}
-      <Show when={updateNeeded()}>
-        <OutdatedView updated_received={updateReceived()} serial={lastUpdateSerial()} />
+      <Show when={updateNeeded() || upgradeSuggested()}>
+        <OutdatedView updated_received={updateReceived()} serial={lastUpdateSerial()} critical={updateNeeded()} onDismiss={() => setUpgradeSuggested(false)} />
       </Show>
       {showCommit() && <p class="text-small mr-1 text-right text-sm text-gray-300"> {import.meta.env.VITE_COMMIT} </p>}
     </div>
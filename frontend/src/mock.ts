@@ -1,6 +1,14 @@
 import { AppState } from './types'
 import type { AppInfosById } from './types'
 
+export const mockIcons: Record<string, string> = {
+  icon_12: 'data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAIAAAACACAMAAAD04JH5AAAC/VBMVEVaXFlRWM1aV85iVc9nVstuVMxoV8xwVc1xVs51Vsh7VMqCU8t8VcuGU8ZkZmONUsmHVMiOU8qIVcmSU8WYUcePVMuJVsqTVMaZUsijUMSaU8mCWtGdU8OkUcWlUsetUMKmU8iuUcOpU8K1T8aJXM64T8FsbmuvUsSPW9DCTr+wU8a5UcLAT8SUXMy6UsObW87DUMDJTsLMTr59ZdfEUcGlWsufXMqCZdOmW8zIUr7GU8OJZdSQY9bJVL+xW8rMVLq3WcyUZNHKVcDNVbx0d3S7WsjBWMqcY9TOVr2gZNCnY9HKWrzBXcbNW7ixY8/OXLlieunEX8LPXbrSXbV8fnu3Zc3BY8PLYbrPYra7Z8rFZcDSY7LJZrzTZLO9asbUZbS4bMvDasLXZrA0kv7Ha77Saa/Ua7HLbbvXbK3Zba6IioeVf9/Uca7Lc7jXcaqIhudjkfbPdbXZc6xXlfjUdavbdKeOh+SOkI2Ji+WlhNXYeKjceqTVfKmPjePSfbTfe6CSlZHRgLCVj99hnfrcgKKRk+HVg63ggZ6WmZbhg6BsoPiBnOXkhJuYlt/jhKHdhqDWiKuTmeCanZnhh5zai6jlipmZnN3XjqifoZ7ijprkj5XbkaWgn9uipKHmkJedotl0rP3fk6LpkpPjlZjmlZPcl6OmqKWFrfvfmqDqmJCoq6jtmYyIsP7nnJLhnp3qnY23qMmsrquPsvvsn4/koZruoIqvsa6Kt/7ipZzrpIzlp5jvpojoqZS0t7PyqITmrZbvrIWWvv+3ureevf/yroHqsJO6vLnetJ6iv/z1sIO8vrv3sX7otJXztH+pwu3rtpC+wb7ut4z4t3vGwcCtxPzCxMH1u333vHisyv/6vnrGyMX8wHX6xHbKzcn9xnK+zvz8y2681P/Q09DU19TD2f/K1//Y29fL3v7b3tre4N3g49/V5P7d5O3k5uPb6Pzn6ubf7P/r7urm7/7y7ezu8O3x8/Dw9//29v/1+PT1+v34+/f8+v75/f/7/vr9//wPQv4NAAAK9ElEQVR42sXbDXAUZxkA4K/AhL9ASDLA5JoOHDjEgRgBqXbAGGVCbcVI+SmihbZWQYP/VsRitdFWzFQtEPxJW9NqxaNEUM/KKdajOOJartaO1UPYdtXk4uZu8bLd85YeS7Lj97e/t7t3Ry57LwOZScjk4X2//d59dz/Atm133LFjx1337Ny5a9fHYHR+CsXn9uzZs2/fl1A88BCMb8B45JHDKL4L49FHH3sCxlNHYBxDceLnMJ45efLUyVOnnkVx+vSZM2fOnj0H40UUL6M4/w8YFy++AuPVf5L4N9hGBTt1wSeoYF+e4MCBUgSnvQWUADZvKyEHBwrnAAJKEoDNm/NzUKTgMWeBLQdnvQUQYBbstFZBFzzgWIVyCMCm4gS2HBy2CY54VQELXnARgA2bNhmCu/JWokMOvmUTHHmqqBy4CECHXUAInZ2GYJ+eg4ewwHQ5YsGPbYJnnHNgVOE8FlxEgFdBhy740Mc/bY4voPiiKb5si6/B+Lolvq3HoUOH0O/vWeIHNJ588hcv6QII6NiwAQnufnBo5DV/YmTg4T9RwStgPRW8/zMZ1b8Y+eZ5KgC3rEeADZvu/LXqZ/zwr3QdgLZb1q9Hgjv/4Cvg+Ev0YgBtRNBxt9+Al89rgLY2KKgAgGwIYG0byYHfgD/TLQm0UkElAEgAWqngAz4D/ki3ZQggAt8BdFtGgNa1kKADon2xRKgv3d8tqqn+HrG/W4j2snyoV1FjfYyqhns5LhSGnxbDPQlVDnUL5DNjJQOIAAOQ4HYN0C7MjsZ3M91MXOUifcwWNrwkvSQW25tWd6eAqoLUlt7EkkQXw8zm96rSFi4ChPbQYLtcOgALwIoVRGAA5NnRwW4m/LeYysXCTNdguD23JMb1COruLATMzm7pFdoTIZZpT0PA3kRkttweSm0pFfAcbc9gFRXogJSSEuW0JP5PUmVJlNKyCD8jyWJOFZQk+rIg5lKymJWEXFpV4JeT8BtyglIqgN4ggFVQsBIKbvd5ET537hwSvAgBJAcVAGABWL6K5KASgHMYQAS+A36HJ4YXEIAI3uc7gMwsCIAF7gCZJx/wn1JWSCWknCyNF/BbOjWBZirQAZFoLMyGZCHEhbgwo46pfExO8Bzbx7HcoMqGGTnKREUhPW4AERDAchMg1Btiutjk4GAXE+rvVQeVBDs2GGVisb5wPKbGolFByHG9qcT4AVgAWppJDnSAmIa7T1aWZUESRfTvTPOKIqUFURDgP5sTU1lFkYWEPF4Amd/PgpYWkgMNcOX4L73iKP3yUbe/8K+iAWRmAcuQoNkAvP7h8cXzRQJ+Q6cmsAwKWqDAfwARQAAR+A34FZ0cEQBVQQeMjowvMiUAEAEDUA783gkJ4FkKgIL3+A2gAzwFLPMd8DP6CAEsXUwEDgAZ32iSu82xXFZVFCVXRgB5jAKamhZbAXDHj/Mx+JOyMVbK8izLsSKrJONsfzgxxpYPQB8pQgDJgQ7o6+mLdsWSYlJIxXM9YSkcYqIRmY1L3CCTiJcRQAQg2ERyoAN4nhcG07DdKqyk8llW5OPpuMIL8ZyYTnPlA9DHaaCJCjTA1SvO34GrryjlA9AHejADQVwFDXD5sj9XwdP0kSIIBlEKli6uAAALwIIFCNDUdGsFAEgAFi0I4ipogNGrPgHoY1WwcBHMATTc6vNO+DR9rApuoAK/AT85duzET6EA3EAFFQAcO0YAWLDg3QUAyhjpCkoZASdOEAAW6IBkSuDFhIRvexlRkUU+xSVllWUYJh4ZlLixMgHI83UCQAId0NPVG+qKwCFATqiCOBYN8WGmL57jhHAqkmDi470f1wA/ok/4QQMV6AA4ACXiSTaJBjJRVjkpxnNMUmFjbIzLCnFOKVcGiAA0UIEGGP2Pc/yFfvxvmdbA4/RFDwJgwruKvCvOlAtA33aBxoYGnATfAfR9G2hsrBSACEBjgORAA1z9u3e8Xj4AFoAAEegAv3bCx+mrXxAINOIq+A34Pn3nCAFE4DuAvHN8AgGwwG/Ad+hbTwxAAg2gMNG0xEtSis/GJYnPKqKiiFFxAgBEAOYTQeDt+iMalg3FQxE+x0Tj/dHuUFdKzsVTEwHAAlBPBIE1GkDiGY6JRAU1IjJcLMLCDphkhLIDDtJ3z6CeCta4rYGxiVkDB+nbbwgggjU+L8KD9P07AmBBBQBYAGqpoBKAwxhQOxcLNMDo5VEyHV6Gv0YnGAAJoIYK9AwMDAyPZDLDV4YyQ8MDI8OjEwU4YABq50KCDsgMDQ0NDw2pmecHXrtwYWCCThUc30+Pw4C6GkioNwFGM5dGLl3KqKOXMsOZS0MTlYH99EAOqKubh5JQ/zafF+F+ehwGAoigAgB8LAoBsMB3AD0SBOYgAVwHfgO+Sg4lQcAckoMKALAAAkgO3lIBABKAWbOIwHcAPR8HZmLBHN8B99MTemAmFvgOOPoVej4OVFPB9Q+P+vjzrzx4Pz2hB6o1wW0X/BNc+f192jlJCKCCutvuPXrcnzh67336SU0E0HJQd/0btHgjjDeReDOKt6K48aYbb8LxDiPeuY7EzTevW/deHBs3bty6desHcWzfvv0jMD6K45M4Povi88ZJTVA9wyTQGoN2oxgIaLOr9ihJe7Ssvekhbz3x6+/VK+mBHHw0zOGkpv2sKCaAGTNsAtqedUGjXdBkEzTrglZ6KMlNsMsQ6AenIaDaQYAJHoImQ9Ci5WAFOQ7TSg+nGYJtDgL96DbKwIxq5yrUz3euQtBahRZbFWgS9LOieYJOcw4wgJbBENR65yAYtOagWa8CyQE5nWYSoCoYh8c7O005ANNNgpnmHNSbcqA9x9EFaCkuXmwIljsJ1ltz4CwA062CWbYczPfIwdJCAnJalQqcD9DvA9OmO+WgBq/EueaLwZ4D01J0rQI6K7rBfHg8XwCmTnPJgV0QuDZBhyHY4SQAU6dOpYJq28VQa1kH5oWwcBEtQuEqFMwBAkxzFtiuRvPlmL8OlnmsRKvA9l85EMDIwYy8HFirYBYEg/YNoXiBaVsmAJiEPMEch23ZWIvGOrBsSc15grYCAgowlqJVMK/WoTFoSzHo0Jp0wWp7DsyN4R5jWwZVVoGtCjX25mhdikTgvCm2ercmug46dYBTDurccuDeHHXBCtqeXVsTFYApVVUFqlCoObq2phXaiV3aGDY5bcugqipPgMpgbs90W57vti1DgiUHhmCly7ZsWgcoA/lVsDbHmkLt2SZY7rAptuW3JgTYBQFTnHJQbWmOjoK85ris+NZkas8QMGVKleM6qM5fiZbWZMuBpTG4bcv57RlMJgLnKuitqabW4WIw5yAYLLU5kirsBJNdBLYNYZ7jtuyRg2avWxTTpggBk72q4C2wt2fzpmgXrHVsjhigCapcq4AItpskZLC2pibX5rjaozVhwGRLClxb07waSw4CAfsNwrU0RwKwrQMPwdy5HjcI7oK8bVkXgElmgVdjmGPaELQcGJfjQvepieTATQAmTZpkroK9OTrMLPWlTI76UlzpMjkiwCSPHLjNrvVFNkdjbjNmV0trwgCdUGUVTC9ibnOaWTxaE74azQIzYHLBdeAwNQUKCqzTs2lyxAIKsAiqvJtjwdnVdr++vNmrNWkAs2CqW2PwqkKDrTEsLdwc8basAyxLsUBrmldwbgsWPTWZAF6CAjOL4w2C89S00iYwA0oV2CfHvCoUMb93gOvsgGtsz/gGoaF0AbjOTaBfC9OKmxxtA4PH3GZpjv8Hd8p2TPfbtiYAAAAASUVORK5CYII=',
+  icon_13: 'data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAIAAAACABAMAAAAxEHz4AAAAHlBMVEXtwwDwzjHv1VHy22704In145r26bL38dX39eX69fMbIa03AAAACXBIWXMAAAsTAAALEwEAmpwYAAACQElEQVRo3u1Vv1fbQAy2nV+wpS0PyOa2C9kCLGULr0PLRqc+Npa+V2909JZseANKcr7/Fukk2WefnTJTfUNiWbrP0nfSXRQpFAqFQqFQKBQKxX+B9+dnU8/4NO2Mij/7Yb7jt7W2/MHGTzDMovaOrX12D4McPZcdBJlFlEtnXDjD1F+6YoIkdx67CNZPyEFhAzYexZuI54Q9f3sSAOBXZ/xcivdYCHLxtGWIwWN+oQy3FGa+fgPjmt05E0Bq5Wn0AcxlO4OhLVOXx4oSXjjjgZwjKQ60fKKE7oIajtbws+fWQNiGZOFSLzyCO/pbhdswpTVAsE/yQb5bqg8qKhzBZBcBYt8RzDhDURGWrYlgRNTHJFUHaOmc1ctpT1CMlAhi1xyxOELcOHmvuFEyihuiFkSAAZvpd2nLsJ2pD4Ag5Wj8n2PCTDDGHujsRCl2Wy9koriwJhKC6Ivroz8985iRRjdcIpWCEtYEh45g2b0e+yUNCDL3smhMiUn7JHyO2iUMqJ2YAF6Z87xHxJHk1hBxRntKBIkLGfaomAlxYxtzlFAIJjQLJ52dOK54vUZyO3sPgN27X2E6t9QZT2EP5NVbbuUYWzm2NR5wqlLyhCIc1UfYnjdMfQSb9vqkqAfEH+eAYEFaBhmAy3x8h2geKE2CGTXhJNQgqcO6jjTZBSjO0JG22kkwbx+qQjCoopY7CYb8uG4TVIf3NtpJwDPnXSxCINTX/yAIrrZqGg8K7wZ89U37qstVoVAoFAqFQqFQvDG8AKSlmPH5RxokAAAAAElFTkSuQmCC',
+  icon_14: 'data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAHEAAABvCAMAAADsfN8JAAAAwFBMVEX/////+/r49PLs8fT16Obm6u3o6uf23tvs1tPa3dvwzsvJzdDuuLLjvLm8v7/zo6b5oKHhp6Ozt7nroJzzm5zykpDVnZrokozjkZDtjYbki4HshoHXjoqdoaTngHbhgHroenPRf3jnc2rYcmribV+Kj5HiZl3baGDgXVLGY1t+gH7fVk/cUkfcS0S/U0vZRzxvcnHZPzreNjfYNjdzXWdgY2LbLTJ8RlZsTF1TVVVAQ0I1NzYoKigZGxkQEQ4AAAAXqOeEAAAFUUlEQVR42uSPgaq7NhyFz5GAIsom7mrbijEqidLR93+8nTh7t7912wXYHbCvmJye/pKv4qfvBj9/N/+Ld/zlu/kPjB8/0r646Pn4O9rjKW2H+pQ3Y9V8cvn4Mt3Xp3H9kZafoLp+mRKsvziKY0F8Uv87xvuBD5WGuFzF/W6tqn3VJu6nMRpfX/b9vp88gv4EGW9baJrK9r2tmrrX1pV52dmYXKPYuP7Vumj8Pau38YTW/n5pmrY/gOEEGe0ekmnob2A+DFMOwVxFlxD6JE5tQ7VJFo1ThQizSQcZb8iJtj9cfmYcd6MCZBysPONQAmmREeUwGZq6LsB0HByBvDAA2+FGmkKZ3VCBlf5OEkcOYDwhGuMeDJMwjptRp42aXG89EZlbQ1Vfx1ACxThOhmzHa5HbceqIauw1EsYtHjkzhpSY/mScotERpXOuAp1qMC27NcRRDmMUs9W2rq7JEDU5aYOW/t04z+O8Pa9VmGiMIZVxnqNx7vCiHtsEIJh0QfI4EapoDF1GgJqY55ao1gS5n4/AnxDfcQ9J8N7K6FsiKzasn0OdJQQ5yUhNRONtdqApWkfU3s+GmSNb/8Y/GBHvc5SxJ0qv7FYfVme973PoQi3O+2dO3rwWHes2oy/AjMl8YlxOkDH4GHKie67y5otPSeX4TpZIn89nCfbx1c36bADaOO2ezwyol8UPBFAoLAf+0riFmkRCk8i4WJKJHl1XKqXKapeMAGlIG6cZaxb7JZyWd/A4ITfGb2GpCOYhNYW+9CkJ08a+NupNscSRgkgKa0y/PCqS2ZSadFF/0YT2N/D4deehT1x39rj45Y9uCf71iw9eBxQecWRvFb02oZ86oPqNt6rIQhiKgYE1p8At5E2x+9+M+Z1fl10JMgr1tvmpygnt7nko+SGWorb4kpbBtzSxdhWIKjk+dmtbHXOhThIxX2TKx+xmNDKPQESUAIQ7BXkCQFlrvLK8tlArqEL0XTq43kr079QwLDN+m0oMzzyZDMXQRtuShDhqO7PhH1vx6U3R571Gv9zG+abAvv8e+7Vnxjp6gzAAprEcCSExsDOyZGIoUiEA7/9Wre6GEl/IvximfOMN9/0G2zjwZzXi92qEW42wqxFmNUKtRsjFoNhXI3ZYjIDXeMO2gVRaK7lv2wLjttuQa/ui5mB3Mdf4S8XaLtQgt4lGGdsNYZ9mtLXdUswko29DjinG0B7wE4y+PXKwG237gGU2yvrJWCWvMZIidNbRykysRnXds+8CxOOqNJzGcL9j+hLmKRiNdZCVpvVoJiP5xwV6jt4Y+Iy+D3GDntInFJ/xpEs3CFLzGMk24kMWey4jDreR/JrEZZR9CdCZ4KQbyW7ciDG1DrnCGHuj4TFib8yPRscU4z7OHLKqgcVIynwnxtwbI5cxjascW885o8t52nJ7MpdR9zWHNPyOMvu0oiEyGv2g6GS5GiubUdbbqtOlzTKSafW0EmDXsbV5RvwRDf0DtxF0o8w1jodyzlyluFFcbB2Agvefj0dk7HIUdTZKdZeuk5iNgI5kaFIImfm0otj0f22juemADEYKaudD8E7/HEmanWJ8KlS1wugmTI/P0OKYb6SpympEQJDq4bvD8hlRaet8TLnS7Djo5QOTMQ0D6VtCQj6jH82kji4ql9H0a6fg/uqhAKMR748kVVrHMe8GKWv8ytyj0tOY0WjahRwOT++sLK8RzvaBCLxGNO2ZLJmN9EKXUhSwGyE/RahgglGOlafEGUaQaSA8EGGKEbAvwH66mvimIz39fIsa575bIRifyneoJXkDxMdlpFap/iER3xfP1/gaWfkLhZm0Vwu0XLoAAAAASUVORK5CYII=',
+  icon_15: 'data:image/jpeg;base64,/9j/4AAQSkZJRgABAQAAAQABAAD//gAfQ29tcHJlc3NlZCBieSBqcGVnLXJlY29tcHJlc3P/2wCEAAQEBAQEBAQEBAQGBgUGBggHBwcHCAwJCQkJCQwTDA4MDA4MExEUEA8QFBEeFxUVFx4iHRsdIiolJSo0MjRERFwBBAQEBAQEBAQEBAYGBQYGCAcHBwcIDAkJCQkJDBMMDgwMDgwTERQQDxAUER4XFRUXHiIdGx0iKiUlKjQyNEREXP/CABEIALwAvAMBIgACEQEDEQH/xAAdAAEAAgIDAQEAAAAAAAAAAAAABwgDBgIEBQEJ/9oACAEBAAAAALvAAAAAABomLce+AAAQN4UazfnmoAADRvN09lnYAAABUIAAAFvQAAAAAAGowlZsAAAKk22AAAAIJisAADtXAKrQ4AABy/Qoh6OQAAOzZ403WwAAMkpEb6qAAAnI1XwgAAOciENRyAABmtKVwioAABeQizQwAAM1iSIY+AAAz2YK9RgAAB9umRlpAAAGefQAAAAAAAH/xAAcAQEAAQUBAQAAAAAAAAAAAAAAAQIDBAYHBQj/2gAIAQIQAAAAuAAAIiZpABUAAADC18Ana/F54AV9a03hIBc+o72QAMPP2YAp0z3+lAFPGYAB/8QAGwEBAAEFAQAAAAAAAAAAAAAAAAECAwUGBwj/2gAIAQMQAAAAsgAAJmIqABSAJgAJjbOzAFvznmOggFHKMzvoBTyvUeLAFfpG1jACczktpAI0aAAf/8QAJxAAAgICAgEDBAMBAAAAAAAAAAYFFwQHAxYCARBACBM2UBQxNTL/2gAIAQEAAQgA/UyuzUODkMmKlsPamvc/z5vDDi5SPmsDGlIv4u9Vhc8UBsYPHgWFzE0VyzmLGMW10/Wiy34Ek8z1q69X8RfcWDP3S5J+V8PZSxnuSTNrcZ0yUqnoxMatYJDUEOgcLlrNszZZJaVJB1w4QGwJ90aPlVFvsqLfZUW+yot9lRb7Ki32VFvsqLfZUW+yot9lRb7Ki32VFvsqLfZUW+yot9lRb7Ki32VFvsqLfZUW+yot9lRb7Ki33+tdppkgYT1zlT6cnlydvPY3I5fH+mD/AFt0fM2q9sK7LYMTCWq+lqvxar8Wq/Fqvxar8Wq/Fqvxar8Wq/Fqvxar8Wq/Fqvxar8Wq/Fqvxar8Wq+mFtl34crg5Mn33f+W4PxPH/rxPT32NreQbpHElIyj2oo9qKPaij2oo9qKPaij2oo9qKPaij2oo9qKPaij2oo9qKPaij2oo9qKPaij2ow9HsH8rh9c73ZJnOwMnhxsPsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0cTNMeHJ4eXn7t/8Ap8PyJ6B55Tn4snG6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6hJnEn53ryeH3vfZDlOQMphxsRZLqWS6lkupZLqWS6lkupZLqWS6lkupZLqWS6lkupZLqWS6lkupZLqWS6lkupZLqY+zXDi5+Lk5vfb35Lh/IfEPPZ5DFkY6oWYqFmKhZioWYqFmKhZioWYqFmKhZioWYqFmKhZioWYqFmKhZioWYqFmKhZioWYx9Pz3rzcXple+w2+ag5LDjoqw3EsNxLDcSw3EsNxLDcSw3EsNxLDcSw3EsNxLDcSw3EsNxLDcSw3EsNxLDcSw3ExtjtnFz8Xnze+2PyPD+J6f36Hp7vCNmsudi5+BUzEVMxFTMRUzEVMxFTMRUzEVMxFTMRUzEVMxFTMRUzEVMxFTMRUzEVMxFTMRUzEYuppn7/ABfy/wBl/8QAPBAAAAYBAgMFBQUFCQAAAAAAAAECAwQF0pOkBxESkqOl0dMGEyAiQBUhUFJVQWFxsrMUJDEzQlFUVoH/2gAIAQEACT8A/CfaaLGmxzJLrK+vqQZkSv2EPamI6bLDj7nLr+VpoupSj5kJSJMKQk1NPI59KyIzLmX01FAK3/uZ/wBuKOgpHM5DaD+cUUBq0c9k1qXMQwhL6jcZ+YzWLiub9n2HCitVpsJWtxs3FfO6pSefzK/KoSUs01zTnMkxjbQszWbbyy+cy6v9BCaldNArEPxmPdII0OGTH39ZF1H/AJh/SOsNS5hRybXIUpLRe6fQ6fUaEqP/AASH4v2l9hfZ/vepfuPe9HTz6unq6RMrytIi2lLdW44Uc+hxS/uUSDULSvZuqKA3DWiZ1+5WSCMuaTJKvzGLetnKs642VnF60LJ5Smlcug0ERIT0Gkvq+J/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjh+G+yivaGxN9DZQkykReSFEfNw1rIy5JExS5MK3Q23G5J6InV19TKOn9ifqP+zH/O79ZJRFSqImS477tDilmtakdPzkZERdIvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvttHwFqmQySy62lR2UktP8UJI/g/Smf6rn0n+5fBNjtOtxijrbfNRJMkqNRGRpJX5hYVeq76YsKvVd9MWFXqu+mLCr1XfTFhV6rvpiwq9V30xYVeq76YsKvVd9MWFXqu+mLCr1XfTFhV6rvpiwq9V30xYVeq76YsKvVd9MWFXqu+mLCr1XfTFhV6rvpiwq9V30xYVeq76Ysq9MclkbhtKcUvp/cRoL4Fk2RtE4pXSSjPmZly+8Te7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5CSTiSP70GhBEZf8AhfB/xk/zK+odbSpLZNqS5zIuRGZ8y5EYfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIejdpWIkME3z+Y0mo1cv3cy+CQmOk4pPrc92halGpak8vnIyIi6RdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAWaXm0qI1NqYaIlF/FKSP4P0xr+q59RKjoWiOTC0PmpJckqNRGRpJX5hMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3AT4CWOous21uKXy/cRoL4H0sJOMT63OhK1KNSlJ5fORkRF0i427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAsUvNkouptTLREov4pSR/B+nN/1F/USmG3EMEytDxqIjIlGojI0kf5hNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzAT4RMEouv3alqVy7Jfif/8QAMREAAAMECAYCAgIDAAAAAAAAAAECAwQF0QYRFlFTVZGSEhQXUlShITEQEwdAIiMw/9oACAECAQE/AP6h/QqHz+KzuFZ3Cs7hWdwrO4VncKzuFZ3Cs7hWdwrO4Vnd/ZiC1odlKQo0nWXyQ5l4x17hzLxjr3DmXjHXuHMvGOvcOZeMde4cy8Y69w5l4x17hzLxjr3DmXjHXuCXl44i/wBy/u8F9EKQRRxg8MaPsQStTFK0pqZlWozUY6h0V8J+0TMdQ6K+E/aJmOodFfCftEzHUOivhP2iZjqHRXwn7RMx1Dor4T9omY6h0V8J+0TMdQ6K+E/aJmOodFfCftEzDP8AkKiqmiE8o+lWoirMimEmRpIy+jIU8hr7FKPNnVwYKbNv3Ml8CfsyIxYulGSvGhTFi6UZK8aFMWLpRkrxoUxYulGSvGhTFi6UZK8aFMWLpRkrxoUxYulGSvGhTFi6UZK8aFMWLpRkrxoUwyoXSc2rOuDty/yL5OqYQRkhBH9kkgyIjV8jhT2kOFPaQ4U9pDhT2kOFPaQ4U9pDhT2kOFPaQ4U9pDhT2l+Ia4vERe0OrsaSaKIzrWdRVELHxjGdtypCx8YxnbcqQsfGMZ23KkLHxjGdtypCx8YxnbcqQsfGMZ23KkLHxjGdtypCx8YxnbcqQsfGMZ23KkFUQjCUqV+12Ooq6iUcgYo09u7lFWTd5aEhmSFkajvMhaOC5gz9yFo4LmDP3IWjguYM/chaOC5gz9yFo4LmDP3IWjguYM/chaOC5gz9yFo4LmDP3IWjguYM/cgukcF4F1P6D+DvkD+z/wCv/8QALhEAAAQFAwMDBAIDAAAAAAAAAAECBAMFERWTUVRVBhfREiExEyIkMDJhEEBD/9oACAEDAQE/AP8AUL5FR7f4oWooWooWooWooWooWooWooWooWooWooWooWv66e1f3dFtW7yewYLqCiLD+lEV6VlUqkQsMl4priSLDJeKa4kiwyXimuJIsMl4priSLDJeKa4kiwyXimuJIsMl4priSLDJeKa4kiwyXimuJIiyGSnCiFa2pfafxCToFeylF/ZiQRJhCmUNctWhDgkqoayqmlPcXDrLeM8YuHWW8Z4xcOst4zxi4dZbxnjFw6y3jPGLh1lvGeMXDrLeM8YuHWW8Z4xcOst4zxiJMOsThrI3jShpP4QDrU6/NRIHMBrMURXEQkI9CiqepkL3Kt7DF7lW9hi9yrewxe5VvYYvcq3sMXuVb2GL3Kt7DF7lW9hi9yrewwqdyr0q/Mh/AV7qUf9jrZ05ZyKLGaxlwon1YafUg6HQzF9nXKusqhfZ1yrrKoX2dcq6yqF9nXKusqhfZ1yrrKoX2dcq6yqF9nXKusqhfZ1yrrKoX2dcq6yqEKfTookM7o6/kX/AFUEHVCDPQg5kcDqGEctcJrDUZLP7jT/ABHaOUaLzK8DtHKNF5leB2jlGi8yvA7RyjReZXgdo5RovMrwO0co0XmV4HaOUaLzK8DtHKNF5leB2jlGi8yvA7TSlBkskKP0+9Pqq8Cnp+3T2EqjQ4DtK4qySn0mVTFyY7lAuTHcoFyY7lAuTHcoFyY7lAuTHcoFyY7lAuTHcoFyY7lAVMmRJV+Qj4B/J/t//9k=',
+  icon_16: 'data:image/png;base64,/9j/4AAQSkZJRgABAQAASABIAAD/4QCMRXhpZgAATU0AKgAAAAgABQESAAMAAAABAAEAAAEaAAUAAAABAAAASgEbAAUAAAABAAAAUgEoAAMAAAABAAIAAIdpAAQAAAABAAAAWgAAAAAAAABIAAAAAQAAAEgAAAABAAOgAQADAAAAAQABAACgAgAEAAAAAQAAASygAwAEAAAAAQAAASwAAAAA/+0AOFBob3Rvc2hvcCAzLjAAOEJJTQQEAAAAAAAAOEJJTQQlAAAAAAAQ1B2M2Y8AsgTpgAmY7PhCfv/AABEIASwBLAMBIgACEQEDEQH/xAAfAAABBQEBAQEBAQAAAAAAAAAAAQIDBAUGBwgJCgv/xAC1EAACAQMDAgQDBQUEBAAAAX0BAgMABBEFEiExQQYTUWEHInEUMoGRoQgjQrHBFVLR8CQzYnKCCQoWFxgZGiUmJygpKjQ1Njc4OTpDREVGR0hJSlNUVVZXWFlaY2RlZmdoaWpzdHV2d3h5eoOEhYaHiImKkpOUlZaXmJmaoqOkpaanqKmqsrO0tba3uLm6wsPExcbHyMnK0tPU1dbX2Nna4eLj5OXm5+jp6vHy8/T19vf4+fr/xAAfAQADAQEBAQEBAQEBAAAAAAAAAQIDBAUGBwgJCgv/xAC1EQACAQIEBAMEBwUEBAABAncAAQIDEQQFITEGEkFRB2FxEyIygQgUQpGhscEJIzNS8BVictEKFiQ04SXxFxgZGiYnKCkqNTY3ODk6Q0RFRkdISUpTVFVWV1hZWmNkZWZnaGlqc3R1dnd4eXqCg4SFhoeIiYqSk5SVlpeYmZqio6Slpqeoqaqys7S1tre4ubrCw8TFxsfIycrS09TV1tfY2dri4+Tl5ufo6ery8/T19vf4+fr/2wBDAAICAgICAgQCAgQGBAQEBggGBgYGCAoICAgICAoMCgoKCgoKDAwMDAwMDAwODg4ODg4QEBAQEBISEhISEhISEhL/2wBDAQMDAwUEBQgEBAgTDQsNExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExP/3QAEABP/2gAMAwEAAhEDEQA/APZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//Q9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9H2Siiiv53P30KKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooA//0vZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//T9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9T2Siiiv53P30KKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooA//1fZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//W9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKK5nxj4v0HwH4buvFniaVobGzAaV1RnIDMFHyqCTye1XCEpyUIK7ZM5qEXObskdNRXgPg79pz4PePPEtr4S8M38019eMyxI9tNGCVUucsygD5VJ5r36tcRhK2GajiIOLfdWMsPiqWITlQmpJdncKKK8U+IX7Qfwt+F2ur4b8Z3stvdtCk4VLeWUbHLBTuRSOSp49qWHw1XES9nQi5PstR18RToR560lFeZ7XRXC/D34j+EvijoTeJPBk73FokrQFnjeI70AJG1wDxkc13VRVpTpSdOorNdGXTqRqRVSm7p9UFFFFZlhRRRQAUUUUAFFFFABRRRQAUUUUAFFeX/En4x+AfhKtm/jm6kthflxD5cMkufLxuzsBx1HWo/hr8aPh98W3vY/A11JcmwEZm8yGSLAlLBMbwM52N06Yrq+o1/ZfWfZvk7207bnN9coe1+r865+19e+x6pRRRXKdIUUUUAf/X9kooor+dz99Ciij60AFLtb0NfkR+0N+1p411/wAT3nhn4e3smmaRaSPAJLchZrgodpkMq5ZVLA7FUqcYJPOB8vQ/E74pWEkeqQa3qcbMcrJ9qn+baecEvhsHqOR619rheCsRVpqpVmot9N/vPkMVxhQpVHTpwckuu33H9C9FfBv7JX7S2u/Ee/k+H3j6UXGoJE01rdYVWlVD88cgGAXUEMGAG4ZyMjJ+h/j58XYPg18P5fEqxrNfTv8AZ7KJwSjTMC2XxzsRQWbkZAwDk14GJyXEUcWsA1eT27O57mHzahWwzxqdorfuj2wAnoKCCOvFfgL4l+OPxf8AHWrtdX+t30jy8LDBK8UYGScLFCVHH0JwOSa0vA37RXxh8AasLmy1m5mRSA9veyPcQMB2ZJCWX32FT+WK+jfAtfkuqq5u2v5/8A+fXGlDns6b5e//AAP+CfvPS4PpXBfC/wAf6X8UPBGneNtJUxx3q/PG3WOVCUkQ+u1gRnuMHvX4z/Ez4w/FTTPiFr2m2XiPU4oItQvI0RLqVQiieRVC4bgKMYA6dq8XKuH6uOq1KHNyuG9z18zz2lgqdOty8yntY/dXBr53/auBH7P/AIjz/wA8Y/8A0alflZ8Sf2jPib8Sr+OV7+fT7OGNY47WCd44/lABZ2BQyOSMktnGcADv9fWeoX2qfsEX95qM8lzKTOPMkcyMQt5gDcxJIA4HPSvW/wBWqmXSoYmrNN88VZevc8v/AFip4+NbD0oO3JJ3fkj5Q/ZKOf2hPD3/AF3m/wDSaav3LHSvw0/ZK/5OE8Pf9fE3/pNNX6I/td/GmT4ZeB18O6BO0Os60GSN4yVeGBcCSUMPuschEPBycjpXZxXgp4zMaGHpbuP6u7+RycMYuGEwFbEVdlL9F+Z9d4Poa/Hz9vM/8Xjh/wCwZa/+hz12f7JcPxd+KvjA6/4j8Q6pJoekMrzK9zLtmmPMcPUZH8Tj+7gHhq4v9vP/AJLHD/2DLX/0OejIct/s/NPq7mpPld7dNgzzMfr2W+3UHFcytfrufWv7CeT8FZv+wncf+gx19nkEdRivwI0D4y/ETR/h+Phv4QuJrG2E8t3M9ozrNIX2/edPmVF29FIznk9q0fh1+0T8U/AHiCLULTVLm8hDDzLW5meaKVc5KkSFtpPZlwQeeRxWuZ8I18VXrYmE0m22kZ5dxVRw1Glh5wbsrNn7y0deBWD4X8R6f4u8NWHirSyfsuo28dxFu4IWRQwz7joa/J/9oH9rbxt4k8S3fh34fX0umaPbSPAr25CzXBQ7TIZFyyqSDsVSvGCTk4HyWV5HXx9aVCGnLvfp/wAE+pzLOaGBpRrT15tkuv8AwD9f9rehpK/noh+JvxS06WPU4Nc1KJnJZJPtU/zbTzglyGx36j1r9I/2Sv2l9e+I9/J8P/H0guNQSJprW6wqtKqY3xyBcAuoIIYAbhnIyMn1My4Rr4Si8RCaklv0Z5mXcVUcXVVCcXFvbr/kfelKAT0FeR/Gz4rWHwd8A3Hi65QTzllgtYSdokmfOAT12qAWbHO0HFfjV4k+Ofxm+IWstPdazfSPMSEt7WSSKMDk4SKEjgD13HHUmuXJuG62YwdZSUY92dWb8Q0cvmqTi5SfRH74HjrRX4T/AA9/aS+L3w61pJk1W4vbdXHnWd7I00bgZBX94S0Z91IweoPQ/tb4B8ZaR8QvCWm+M9EJ+zajEsqq2NyE8MjYyNyNlTz1FZ5zw9Wy3lnNqUX1X6o0yjPaOYuUIK0l0/yOswfSjBPSvwg8f/GH4q2HjLVrCz8SanFCl5dIqrdSgKomkUAYbgAcDHTtR8Sf2iPib8S9XN7cahPY2a/LDaQTtFEigAclShkY4yzNk5PGBxXsw4Hry5X7VWZ5EuMqMbr2buj938HOKACegzXxF8J/irc+Af2QLb4ia00l/dQG4SJZXZ2kme5eOJSzEnbkjPoo4r82/E3xy+LPi/XJNV1DXb4yO+5UinkhjT0CRxMqqB24J9STXFgeFK2KqVYqaUYNxv3a8v8AgnXjOJ6WGhSk4NuaTt2T8z7f/wCChef7P8L/AO/d/wDoKVnf8E8f9d4r/wCuVj/6HcV8Y+Pvi1438deHNM8KeN5XupdHeQxTzZ8/bIqgpISMtjGQzfNjrnrX2d/wTwP77xX/ANcrH/0O4r6jG4GeCyOeGqO7Xb/EfOYPGwxmdQxFNWT7/wCE/TKiiivyw/SgooooA//Q9kooor+dz99CsHxTdS2PhjUr2A4eG0ndT6FY2I/Wt6uZ8a/8ibq//Xlcf+imrbD/AMSPqjOv/Dl6M/ny8HaVaa34v03SL4Ew3VxbQuAcHbI6I3P0Y1+5fxs+Gtr4z+D2qeB9EsI5Zo7bGnwrtjCTRYMWwkqqdMdQMcHivxG+G3/JQtE/6/bL/wBHRV/RI+d7fU/zr9B4zxM6FbD1Kb2u18rHwnCOGhXo4inNb2X5n5U/AT9mb40/D/4v6J4t13S0gsrWdzNIlzC22NonU/KGyQSQOBn2rpf+ChmoXIHhbSlbERW7mI9WBiQfoTX6X4NfmF/wUO41Lwt/1wvP/Q4a4MnzWpmWa0atdK6TWl+z7nbm2V08uyutSoN2bT1t3XYm/wCCfPhzTZrjxD4onjR7mBYLeFiuWRX3O5BPTdhQcdgK8w/bv8P6ZpHxattQ0+JYn1KxjnmCgDdIrvHu47soGT7Cvb/+Ce//ACBPEv8A12tv/QGryv8A4KBf8lL0f/sGD/0c9evh6s/9YJxb05f0TPKr0o/2FCSWvN+rR9HfsE6hdXXwnv7OdtyWuqMIx6CSKOQ/+PE1+X/xZAPxS8Qbun9p3ufp9okr9M/2Af8AkmOr/wDYVH/pNDX5m/Fn/kqPiL/sJX3/AKUSV1ZOrZpjLeRz5q28swl/M/Yf9nT4NeC/BPwy0q7FhBPqWo2sdxd3Msau7NKN+wEjhEztUDsM9TUX7TGj6ToP7OfiPT9DtYrO3CK/lQoETc8yszbVAGWJJPqea9h+GX/JONA/7B1t/wCilrzD9q3/AJN/8Sf9cY//AEalfCU8VVrZjF1JN3mvzPtKmGpUsul7OKXufoflL+zLreleG/jbo+u63MtvaWb3E0sjHAVEtZiT/nvUfi7X/Fv7SfxkMunx77nVJ1gs4GOFihXPloxGcBE3O5HfdjtXgx3eadvqa63wh4h1nwD4vsfEVgGivNPnSZFOVJKnO0jrh1JU+xr9dq4Re0liofHy2X5/8OfllPFPkWGn8HNd/l+Wx++vw0+H+jfC/wAFWXgvQ8tFar88h+9LK3Mkje7N+QwK/Lj9vT/kscP/AGDLX/0Oev1l8LeI9N8YeG7DxVo7b7XUIEuIj0O1wDgjsR0Ir8mv29P+Sxw/9gy1/wDQ56/OOE5VJZnOVb4rO/rdXP0HiiMI5dGNH4bq3pZn1F+wh4c0qx+FV54jjiU3l9fSRySFRu8uFVVEzjO0Ek49Sa/Pz9pfQdL8M/HLxDpejRCGBLveqDopljjlYAdhuc4A6V+kH7Dv/JD/APuIXP8A7LX57fta/wDJwXiL/r4i/wDSaGvbyarOWdYqLeln+DSPFzelGOUYZpa6fimz9KPgTqdxbfso6dqeSz2+l3RXP/TMyhfyAFfjn4K0q01/xppeiXo/c3dzawOASPllkRG5GDyCa/Xz4Jf8mf23/YKvf/Qpa/JX4X/8lK0D/sIWX/o+Otcg92eOkv5n+plnivDBRf8AKv0P2Y/aX8JaBqPwE12ylto1j0u1+0WoVQPKe3wU2ccDHynHUEjvX5WfsxTvYfH/AMOtbnB+3eX/AMBeORD+hr9eP2iP+SHeLP8AsGzfyr8gf2cB/wAX+8O/9hJf5PXFwzOU8txKm7/F/wCko7OI4RhmOHcVb4fzP1p+PHwRi+OOiWGiXGpPp0dlO05KxiTeWQoBgkAYBNYvwE/Zu0f4HT6jfW14dTuL9Y4xK8IRokQsSqkE8MWBPToKP2lfjmPgp4Qhl0xUl1fU2eO0V+VQIMvKy8FguQAO7EZwM1+Xmha7+0T8cNfuI9CvdU1e5CeZIsc7RRovQZCvFEgJ6DjOOAcGvMynLsbi8C4yrKFHz9fl18z0c0x+DwuNUo0nOt5enz6eR6D+3NpOm6b8aHuLCFYnurG1mlKjG6RjKpY+5VFB+lfav7DdzPcfBNEncsI9RuFQHsCVYj8yT+NflP8AFTwb8QfBHiJdJ+IySJfGCOQCSbzz5blgvzbm7q3GePxr9Uf2Ff8Akiv/AHErj/2Svb4ipKnlFOmpc1nHXueRw/UdTNalRx5b82nY/KD4jAN8QdYzyBfXZ/KeQ1+0nwL+C/gjwD8PtOjgsLe4vru2jmu7qSNWeV5FDkZYHCLnCqOB9a/Fz4i/8lA1n/r8vP8A0dLX9AHg/wD5FHSv+vK3/wDRa1lxliKlPDUIQk0nvY04SoU6mIrTnG7W3zPjT9uC1svC3wR07RvDkEdjazaugaKBAicxzSk7VwMlxuPqea+Wf2GtD0zWPjSJ9RiEpsLG4uYdwyBIGijDY9QJGx6Gvqz9v/8A5JHpP/YYj/8ASeevmj9gX/ksV5/2Cbn/ANHW9LLZy/sGrO+r5tR5hCP9t0oNae6ep/8ABQTw1piL4f8AFcMapdSefayuFG50UCRNx77SCB/vGof+CeH+u8V/9crH/wBDuK6f/goKR/wjHh0f9PM//oquY/4J4f67xZ/1zsf/AEO4qITlLh2XM/65i5QUc/XKv65T9MqKKK/OT74KKKKAP//R9kooor+dz99CsXxJZS6l4d1DT4fvz200a/V0YD9TW1R05qoS5ZKS6EzjzJxfU/nI8NasvhvxVY6vcxs32KeCVk6MfJdHK89/lI+tfsb8bv2gPANp8G9R1jwnr8El/qVsUsBazL5/mSEDcACGXy85cnG3GOtfOv7Qn7GfiPVPEV34x+FKR3MV67TzWLyCORJXOW8ov8jIxJbaWUqTgZGAPmGP9lL4/wBxIsC+HLlSxAy7Qqv1Leb0Ffq9d5dm3scXOsly62bXlo7+aPzGgsflftsLCk3zaXSfnqrep6V+zT8Uvir4y+N2h6Lqeu6he2hmkkmikmZkaNInJ3joRnHXjNexf8FCtGupbXwxr0YJhjN1bucdGYJIv6I35V7N+zF+zPJ8G1m8T+KpY59buYzCqwktHbxE5IDEDc74G5sAAAAdyfcPjB8LdH+MHge58H6swhdiJbafaHME6g7HCnGRyQwyMqSMivAxedYanm1OvQS5IKzaXe938rnu4XJ8TPK6lCtfnk7pN9rWXzsfnx+wp8SvCnhbUNb8L+Jr2Cxa/WGa3edgiu8e5WTcxAzgqQOp5x0rzX9tLx94b8d/FKE+F7qO9t9Os0tmmhbcjyF2d9rDghdwGRkZz6GszxN+x18ctA1aS0sNK/tK3/gns5I3RhnusjI6+uCD9TWn4I/Yu+M3iPVRFr9ouiWikbri7ZGIH+xFGzFiOwJUe9fSxWXQxcs29urtbXX/AA584/r88LHK/Yuye9n5/LfqfZv7B+kXWn/CK6v7j7t9qcjx/SKOOI/+PKa/Lj4s/wDJUfEX/YSvv/SiSv3y8DeDtE+H3haw8H+Ho/LtLCMRpnG5jnLO2MAs7Esx9TX5O+Pv2TPjt4g8cazrum6OjW93fXU0RNzACySTOyHBfjIYHB59cV4uQ5tQnjsVias1FSta7S7nsZ3llaGCw2HpwcnG97Jvsfqn8Mv+ScaB/wBg62/9FLXmH7Vv/Jv/AIk/64x/+jUr13wLpt7o3grSNI1JPLuLWygilXIO10QKwyMg4I7Vw3x/8Ja945+EOteFPDEIuL68jRYoyyoCRIrH5mIA4BPJr47DVIrHxqN6c+/zPrMRTk8DKCWvJt8j8cP2ePCWjeOPjNonhrxAnm2dxct5qdnWOOSXaf8AZYoA3qCR3r3v9uj4cjw38Qbbxvp0Wy11yL59oOFuIAEYf3Ruj2EDj7pNdP8As+/szfGXwB8YtF8WeJtLSGwtZpXmkW4hcoGgkQfKrFjlmA4GfwzX2v8AtI/C+6+K/wAK7zQdJiWXU7ZkurIMQMypwU3EqB5iFkySAM8193j89p0s0oSp1E4Ws7PRXfX7kfF4LJalXLa0Z02p3urrV2X/AAWfPH7CHxMXWPC978Mr9x5+mMbq1UkZMErfvFA6/JIc9OA4r58/b0/5LHD/ANgy1/8AQ566P4K/s+/tFfCf4kaZ4vTRle3hl8u5VbuDLW8vyS8b+cD5wPVRXov7WP7P/wAVfin8TI9e8F6ct1ZJYW8JlaeKP94jylhtdgeAw5xjnjPOIpVMJQzh4qFWPJOL1ut7r89x1YYqvlSw06UuaMl0e2v5F79if4reB9I+G994T8R6na6ddWd1JcgXEqxh4ZEVi4L4B2kENgnHB718M/tA+LNI8d/GbXPEOgP5trc3eInHIdY0SIMPZihI9iK9Zv8A9ir4zWnhSDWre0Sa9LyLcWAkiMqhWwkkbB/LdWXkjcrL6HOAfDz9jD4teJPEMcPi2ybRdOBzNPO0Zk29xGiM5L+hbAHXnGD6OGlluGxFbMo11726uvnbq72/yOHERzDEYejl8qD93bR/K/pf/M+//gPol1P+yzpejSKRJd6XcbR3xN5jJ+YYV+MvhXVovDfi3TtbmUuLG4t52QdT5Lo7Lzjn5SPrX9EejaTp+gaTa6FpMYhtbKJIYUXoqIAqj8AK/Nn9oH9jLxJqHiK68X/ChI7mC8dppLFpBHJHI53N5Rf5GQkkhSylScDIwB4HDmc4dYjEQxD5VUd1f56fce5xBlFd0KE6C5nTVnb5HvX7RXxw+Gdx8DNVTRdYtL+bW7Y29tDDKrSN5pAZioyQIxktnGCMda/Or9lqxn1f4/eHhbqflu2nbuAsccjEn07D6ketPi/ZR+P1zKsC+HblSxAzI0KL9Sxl6Cv0P/Zi/Zlf4Oed4p8VSxz63cxmFVhYtHbxMcsAxA3O+BuOAABgdyfQqVMDk+Bq0aFVTlK9tU3qrdOxwQp43NsbSrVqTio26NLTXr3Pn3/goQtyNe8OOxxC1pcBef4hLHu/pWp+wR4z8JaZp+u+GdRnhtL+eSK4jMrqhljVNpCkkZ2MCSP9rNfWn7RHwXj+Nfgb+xrSVLfU7OTz7OWTOzdgq0bkZIVwcZAO04ODjFflfP8Asm/H20vXtE8PTSlWKhkeB4m9CGMgGPcge4FY5TWwuPyr+z61Xka80ut09TbNKOJwWZ/XqNNzT9X0s1obX7ZPjfw940+ME8vhydbqGztoLRpoyGRpIjIz7SOoBfbn1Br7s/YV/wCSK/8AcSuP/ZK+PPFn7EPxW0+000eHYE1G5mgZ70rNEkUMm7CRoXKs/wAvJbGM9McCvvf9lP4feLPhj8Mv+Eb8aWwtbz7dNNsEiSDY23B3ISOx461GfYnCf2XDDYWqpcrS3V9NNisjw+K/tKeJxNNx5k3s7a67n42/EX/koGs/9fl5/wCjpa/oB8H/APIo6V/152//AKLWvyO8Zfsl/HrV/FOqazYaKrwz3N1JHm6gBZJJXZTgvxkMDgn64r9efDdpcaf4d0+wu12ywW0MbrkHDKgBGRxwR2rl4vxdGvRoRozUmr3s0+h08KYStRrV3Wg0na1011PkT9u/SbrUfg3aXNuMrZ6pDI/0eOSIf+POK+Jf2NvHXhvwH8XBd+KLqOztr2zntPOlYLGju0bqWY8AHyyMnjJFfrz8RfAuj/EvwXqHgnXMrBfR7Q6/ejdTuSRfdWANfkB4n/Y8+OOha1NZafpLajbhv3dxaPGUcH2d1ZfcEHHTJ610cNYzDV8BUy7Ez5d93bR9r9jDiHCYmjjoY/DwctvPVf5nsv7dfxM8KeJrjRfCfhq9hv5LLzp7h4GDrGzgIiFl43EbiRnIwPWt7/gnh/rvFf8A1zsf/Q7ivHJ/2JfjKnhW11RLeOXUZ5irWSyxDyIQhO+SRmC7i/GxN2ByTzx9a/sdfBn4h/CSfxB/wnditmt9HaCArLHLuMTTF/uMcY3jr6/WunH1cFQyieCw1ZSa81d+8r6GGBpYytmsMXiKTin5Oy07n3BRRRX5mfoYUUUUAf/S9kooor+dz99CiiigA69aTApaKBhRRRQFwooooAKKKKBBRRRQMKKKKYgooopDCiiigQUUUUAFFFFABSYHpS0UDuFFFFABRRRTuAUUUUgD2ooooAKKKKBBRRRQB//T9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9T2Siiiv53P30KKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooA//1fZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//W9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9f2Siiiv53P30KKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooA//0PZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//R9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9k=',
+}
+
 export default {
   app_12: {
     app_id: 'app_12',
@@ -9,7 +17,7 @@ export default {
     date: BigInt(1689152046004),
     size: BigInt(9500),
     source_code_url: 'https://example.com?t=xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx',
-    image: 'data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAIAAAACACAMAAAD04JH5AAAC/VBMVEVaXFlRWM1aV85iVc9nVstuVMxoV8xwVc1xVs51Vsh7VMqCU8t8VcuGU8ZkZmONUsmHVMiOU8qIVcmSU8WYUcePVMuJVsqTVMaZUsijUMSaU8mCWtGdU8OkUcWlUsetUMKmU8iuUcOpU8K1T8aJXM64T8FsbmuvUsSPW9DCTr+wU8a5UcLAT8SUXMy6UsObW87DUMDJTsLMTr59ZdfEUcGlWsufXMqCZdOmW8zIUr7GU8OJZdSQY9bJVL+xW8rMVLq3WcyUZNHKVcDNVbx0d3S7WsjBWMqcY9TOVr2gZNCnY9HKWrzBXcbNW7ixY8/OXLlieunEX8LPXbrSXbV8fnu3Zc3BY8PLYbrPYra7Z8rFZcDSY7LJZrzTZLO9asbUZbS4bMvDasLXZrA0kv7Ha77Saa/Ua7HLbbvXbK3Zba6IioeVf9/Uca7Lc7jXcaqIhudjkfbPdbXZc6xXlfjUdavbdKeOh+SOkI2Ji+WlhNXYeKjceqTVfKmPjePSfbTfe6CSlZHRgLCVj99hnfrcgKKRk+HVg63ggZ6WmZbhg6BsoPiBnOXkhJuYlt/jhKHdhqDWiKuTmeCanZnhh5zai6jlipmZnN3XjqifoZ7ijprkj5XbkaWgn9uipKHmkJedotl0rP3fk6LpkpPjlZjmlZPcl6OmqKWFrfvfmqDqmJCoq6jtmYyIsP7nnJLhnp3qnY23qMmsrquPsvvsn4/koZruoIqvsa6Kt/7ipZzrpIzlp5jvpojoqZS0t7PyqITmrZbvrIWWvv+3ureevf/yroHqsJO6vLnetJ6iv/z1sIO8vrv3sX7otJXztH+pwu3rtpC+wb7ut4z4t3vGwcCtxPzCxMH1u333vHisyv/6vnrGyMX8wHX6xHbKzcn9xnK+zvz8y2681P/Q09DU19TD2f/K1//Y29fL3v7b3tre4N3g49/V5P7d5O3k5uPb6Pzn6ubf7P/r7urm7/7y7ezu8O3x8/Dw9//29v/1+PT1+v34+/f8+v75/f/7/vr9//wPQv4NAAAK9ElEQVR42sXbDXAUZxkA4K/AhL9ASDLA5JoOHDjEgRgBqXbAGGVCbcVI+SmihbZWQYP/VsRitdFWzFQtEPxJW9NqxaNEUM/KKdajOOJartaO1UPYdtXk4uZu8bLd85YeS7Lj97e/t7t3Ry57LwOZScjk4X2//d59dz/Atm133LFjx1337Ny5a9fHYHR+CsXn9uzZs2/fl1A88BCMb8B45JHDKL4L49FHH3sCxlNHYBxDceLnMJ45efLUyVOnnkVx+vSZM2fOnj0H40UUL6M4/w8YFy++AuPVf5L4N9hGBTt1wSeoYF+e4MCBUgSnvQWUADZvKyEHBwrnAAJKEoDNm/NzUKTgMWeBLQdnvQUQYBbstFZBFzzgWIVyCMCm4gS2HBy2CY54VQELXnARgA2bNhmCu/JWokMOvmUTHHmqqBy4CECHXUAInZ2GYJ+eg4ewwHQ5YsGPbYJnnHNgVOE8FlxEgFdBhy740Mc/bY4voPiiKb5si6/B+Lolvq3HoUOH0O/vWeIHNJ588hcv6QII6NiwAQnufnBo5DV/YmTg4T9RwStgPRW8/zMZ1b8Y+eZ5KgC3rEeADZvu/LXqZ/zwr3QdgLZb1q9Hgjv/4Cvg+Ev0YgBtRNBxt9+Al89rgLY2KKgAgGwIYG0byYHfgD/TLQm0UkElAEgAWqngAz4D/ki3ZQggAt8BdFtGgNa1kKADon2xRKgv3d8tqqn+HrG/W4j2snyoV1FjfYyqhns5LhSGnxbDPQlVDnUL5DNjJQOIAAOQ4HYN0C7MjsZ3M91MXOUifcwWNrwkvSQW25tWd6eAqoLUlt7EkkQXw8zm96rSFi4ChPbQYLtcOgALwIoVRGAA5NnRwW4m/LeYysXCTNdguD23JMb1COruLATMzm7pFdoTIZZpT0PA3kRkttweSm0pFfAcbc9gFRXogJSSEuW0JP5PUmVJlNKyCD8jyWJOFZQk+rIg5lKymJWEXFpV4JeT8BtyglIqgN4ggFVQsBIKbvd5ET537hwSvAgBJAcVAGABWL6K5KASgHMYQAS+A36HJ4YXEIAI3uc7gMwsCIAF7gCZJx/wn1JWSCWknCyNF/BbOjWBZirQAZFoLMyGZCHEhbgwo46pfExO8Bzbx7HcoMqGGTnKREUhPW4AERDAchMg1Btiutjk4GAXE+rvVQeVBDs2GGVisb5wPKbGolFByHG9qcT4AVgAWppJDnSAmIa7T1aWZUESRfTvTPOKIqUFURDgP5sTU1lFkYWEPF4Amd/PgpYWkgMNcOX4L73iKP3yUbe/8K+iAWRmAcuQoNkAvP7h8cXzRQJ+Q6cmsAwKWqDAfwARQAAR+A34FZ0cEQBVQQeMjowvMiUAEAEDUA783gkJ4FkKgIL3+A2gAzwFLPMd8DP6CAEsXUwEDgAZ32iSu82xXFZVFCVXRgB5jAKamhZbAXDHj/Mx+JOyMVbK8izLsSKrJONsfzgxxpYPQB8pQgDJgQ7o6+mLdsWSYlJIxXM9YSkcYqIRmY1L3CCTiJcRQAQg2ERyoAN4nhcG07DdKqyk8llW5OPpuMIL8ZyYTnPlA9DHaaCJCjTA1SvO34GrryjlA9AHejADQVwFDXD5sj9XwdP0kSIIBlEKli6uAAALwIIFCNDUdGsFAEgAFi0I4ipogNGrPgHoY1WwcBHMATTc6vNO+DR9rApuoAK/AT85duzET6EA3EAFFQAcO0YAWLDg3QUAyhjpCkoZASdOEAAW6IBkSuDFhIRvexlRkUU+xSVllWUYJh4ZlLixMgHI83UCQAId0NPVG+qKwCFATqiCOBYN8WGmL57jhHAqkmDi470f1wA/ok/4QQMV6AA4ACXiSTaJBjJRVjkpxnNMUmFjbIzLCnFOKVcGiAA0UIEGGP2Pc/yFfvxvmdbA4/RFDwJgwruKvCvOlAtA33aBxoYGnATfAfR9G2hsrBSACEBjgORAA1z9u3e8Xj4AFoAAEegAv3bCx+mrXxAINOIq+A34Pn3nCAFE4DuAvHN8AgGwwG/Ad+hbTwxAAg2gMNG0xEtSis/GJYnPKqKiiFFxAgBEAOYTQeDt+iMalg3FQxE+x0Tj/dHuUFdKzsVTEwHAAlBPBIE1GkDiGY6JRAU1IjJcLMLCDphkhLIDDtJ3z6CeCta4rYGxiVkDB+nbbwgggjU+L8KD9P07AmBBBQBYAGqpoBKAwxhQOxcLNMDo5VEyHV6Gv0YnGAAJoIYK9AwMDAyPZDLDV4YyQ8MDI8OjEwU4YABq50KCDsgMDQ0NDw2pmecHXrtwYWCCThUc30+Pw4C6GkioNwFGM5dGLl3KqKOXMsOZS0MTlYH99EAOqKubh5JQ/zafF+F+ehwGAoigAgB8LAoBsMB3AD0SBOYgAVwHfgO+Sg4lQcAckoMKALAAAkgO3lIBABKAWbOIwHcAPR8HZmLBHN8B99MTemAmFvgOOPoVej4OVFPB9Q+P+vjzrzx4Pz2hB6o1wW0X/BNc+f192jlJCKCCutvuPXrcnzh67336SU0E0HJQd/0btHgjjDeReDOKt6K48aYbb8LxDiPeuY7EzTevW/deHBs3bty6desHcWzfvv0jMD6K45M4Povi88ZJTVA9wyTQGoN2oxgIaLOr9ihJe7Ssvekhbz3x6+/VK+mBHHw0zOGkpv2sKCaAGTNsAtqedUGjXdBkEzTrglZ6KMlNsMsQ6AenIaDaQYAJHoImQ9Ci5WAFOQ7TSg+nGYJtDgL96DbKwIxq5yrUz3euQtBahRZbFWgS9LOieYJOcw4wgJbBENR65yAYtOagWa8CyQE5nWYSoCoYh8c7O005ANNNgpnmHNSbcqA9x9EFaCkuXmwIljsJ1ltz4CwA062CWbYczPfIwdJCAnJalQqcD9DvA9OmO+WgBq/EueaLwZ4D01J0rQI6K7rBfHg8XwCmTnPJgV0QuDZBhyHY4SQAU6dOpYJq28VQa1kH5oWwcBEtQuEqFMwBAkxzFtiuRvPlmL8OlnmsRKvA9l85EMDIwYy8HFirYBYEg/YNoXiBaVsmAJiEPMEch23ZWIvGOrBsSc15grYCAgowlqJVMK/WoTFoSzHo0Jp0wWp7DsyN4R5jWwZVVoGtCjX25mhdikTgvCm2ercmug46dYBTDurccuDeHHXBCtqeXVsTFYApVVUFqlCoObq2phXaiV3aGDY5bcugqipPgMpgbs90W57vti1DgiUHhmCly7ZsWgcoA/lVsDbHmkLt2SZY7rAptuW3JgTYBQFTnHJQbWmOjoK85ris+NZkas8QMGVKleM6qM5fiZbWZMuBpTG4bcv57RlMJgLnKuitqabW4WIw5yAYLLU5kirsBJNdBLYNYZ7jtuyRg2avWxTTpggBk72q4C2wt2fzpmgXrHVsjhigCapcq4AItpskZLC2pibX5rjaozVhwGRLClxb07waSw4CAfsNwrU0RwKwrQMPwdy5HjcI7oK8bVkXgElmgVdjmGPaELQcGJfjQvepieTATQAmTZpkroK9OTrMLPWlTI76UlzpMjkiwCSPHLjNrvVFNkdjbjNmV0trwgCdUGUVTC9ibnOaWTxaE74azQIzYHLBdeAwNQUKCqzTs2lyxAIKsAiqvJtjwdnVdr++vNmrNWkAs2CqW2PwqkKDrTEsLdwc8basAyxLsUBrmldwbgsWPTWZAF6CAjOL4w2C89S00iYwA0oV2CfHvCoUMb93gOvsgGtsz/gGoaF0AbjOTaBfC9OKmxxtA4PH3GZpjv8Hd8p2TPfbtiYAAAAASUVORK5CYII=',
+    icon: 'icon_12',
     tag_name: 'v1',
     state: AppState.Initial,
     rights_obtained: false,
@@ -21,7 +29,7 @@ export default {
     date: BigInt(1689152046004),
     size: BigInt(500000),
     source_code_url: 'https://mycompany.com/the/code',
-    image: 'data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAIAAAACABAMAAAAxEHz4AAAAHlBMVEXtwwDwzjHv1VHy22704In145r26bL38dX39eX69fMbIa03AAAACXBIWXMAAAsTAAALEwEAmpwYAAACQElEQVRo3u1Vv1fbQAy2nV+wpS0PyOa2C9kCLGULr0PLRqc+Npa+V2909JZseANKcr7/Fukk2WefnTJTfUNiWbrP0nfSXRQpFAqFQqFQKBQKxX+B9+dnU8/4NO2Mij/7Yb7jt7W2/MHGTzDMovaOrX12D4McPZcdBJlFlEtnXDjD1F+6YoIkdx67CNZPyEFhAzYexZuI54Q9f3sSAOBXZ/xcivdYCHLxtGWIwWN+oQy3FGa+fgPjmt05E0Bq5Wn0AcxlO4OhLVOXx4oSXjjjgZwjKQ60fKKE7oIajtbws+fWQNiGZOFSLzyCO/pbhdswpTVAsE/yQb5bqg8qKhzBZBcBYt8RzDhDURGWrYlgRNTHJFUHaOmc1ctpT1CMlAhi1xyxOELcOHmvuFEyihuiFkSAAZvpd2nLsJ2pD4Ag5Wj8n2PCTDDGHujsRCl2Wy9koriwJhKC6Ivroz8985iRRjdcIpWCEtYEh45g2b0e+yUNCDL3smhMiUn7JHyO2iUMqJ2YAF6Z87xHxJHk1hBxRntKBIkLGfaomAlxYxtzlFAIJjQLJ52dOK54vUZyO3sPgN27X2E6t9QZT2EP5NVbbuUYWzm2NR5wqlLyhCIc1UfYnjdMfQSb9vqkqAfEH+eAYEFaBhmAy3x8h2geKE2CGTXhJNQgqcO6jjTZBSjO0JG22kkwbx+qQjCoopY7CYb8uG4TVIf3NtpJwDPnXSxCINTX/yAIrrZqGg8K7wZ89U37qstVoVAoFAqFQqFQvDG8AKSlmPH5RxokAAAAAElFTkSuQmCC',
+    icon: 'icon_13',
     tag_name: 'v1',
     state: AppState.Downloading,
   },
@@ -32,7 +40,7 @@ export default {
     date: BigInt(1688152046004),
     size: BigInt(11556),
     source_code_url: 'https://foo.org/',
-    image: 'data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAHEAAABvCAMAAADsfN8JAAAAwFBMVEX/////+/r49PLs8fT16Obm6u3o6uf23tvs1tPa3dvwzsvJzdDuuLLjvLm8v7/zo6b5oKHhp6Ozt7nroJzzm5zykpDVnZrokozjkZDtjYbki4HshoHXjoqdoaTngHbhgHroenPRf3jnc2rYcmribV+Kj5HiZl3baGDgXVLGY1t+gH7fVk/cUkfcS0S/U0vZRzxvcnHZPzreNjfYNjdzXWdgY2LbLTJ8RlZsTF1TVVVAQ0I1NzYoKigZGxkQEQ4AAAAXqOeEAAAFUUlEQVR42uSPgaq7NhyFz5GAIsom7mrbijEqidLR93+8nTh7t7912wXYHbCvmJye/pKv4qfvBj9/N/+Ld/zlu/kPjB8/0r646Pn4O9rjKW2H+pQ3Y9V8cvn4Mt3Xp3H9kZafoLp+mRKsvziKY0F8Uv87xvuBD5WGuFzF/W6tqn3VJu6nMRpfX/b9vp88gv4EGW9baJrK9r2tmrrX1pV52dmYXKPYuP7Vumj8Pau38YTW/n5pmrY/gOEEGe0ekmnob2A+DFMOwVxFlxD6JE5tQ7VJFo1ThQizSQcZb8iJtj9cfmYcd6MCZBysPONQAmmREeUwGZq6LsB0HByBvDAA2+FGmkKZ3VCBlf5OEkcOYDwhGuMeDJMwjptRp42aXG89EZlbQ1Vfx1ACxThOhmzHa5HbceqIauw1EsYtHjkzhpSY/mScotERpXOuAp1qMC27NcRRDmMUs9W2rq7JEDU5aYOW/t04z+O8Pa9VmGiMIZVxnqNx7vCiHtsEIJh0QfI4EapoDF1GgJqY55ao1gS5n4/AnxDfcQ9J8N7K6FsiKzasn0OdJQQ5yUhNRONtdqApWkfU3s+GmSNb/8Y/GBHvc5SxJ0qv7FYfVme973PoQi3O+2dO3rwWHes2oy/AjMl8YlxOkDH4GHKie67y5otPSeX4TpZIn89nCfbx1c36bADaOO2ezwyol8UPBFAoLAf+0riFmkRCk8i4WJKJHl1XKqXKapeMAGlIG6cZaxb7JZyWd/A4ITfGb2GpCOYhNYW+9CkJ08a+NupNscSRgkgKa0y/PCqS2ZSadFF/0YT2N/D4deehT1x39rj45Y9uCf71iw9eBxQecWRvFb02oZ86oPqNt6rIQhiKgYE1p8At5E2x+9+M+Z1fl10JMgr1tvmpygnt7nko+SGWorb4kpbBtzSxdhWIKjk+dmtbHXOhThIxX2TKx+xmNDKPQESUAIQ7BXkCQFlrvLK8tlArqEL0XTq43kr079QwLDN+m0oMzzyZDMXQRtuShDhqO7PhH1vx6U3R571Gv9zG+abAvv8e+7Vnxjp6gzAAprEcCSExsDOyZGIoUiEA7/9Wre6GEl/IvximfOMN9/0G2zjwZzXi92qEW42wqxFmNUKtRsjFoNhXI3ZYjIDXeMO2gVRaK7lv2wLjttuQa/ui5mB3Mdf4S8XaLtQgt4lGGdsNYZ9mtLXdUswko29DjinG0B7wE4y+PXKwG237gGU2yvrJWCWvMZIidNbRykysRnXds+8CxOOqNJzGcL9j+hLmKRiNdZCVpvVoJiP5xwV6jt4Y+Iy+D3GDntInFJ/xpEs3CFLzGMk24kMWey4jDreR/JrEZZR9CdCZ4KQbyW7ciDG1DrnCGHuj4TFib8yPRscU4z7OHLKqgcVIynwnxtwbI5cxjascW885o8t52nJ7MpdR9zWHNPyOMvu0oiEyGv2g6GS5GiubUdbbqtOlzTKSafW0EmDXsbV5RvwRDf0DtxF0o8w1jodyzlyluFFcbB2Agvefj0dk7HIUdTZKdZeuk5iNgI5kaFIImfm0otj0f22juemADEYKaudD8E7/HEmanWJ8KlS1wugmTI/P0OKYb6SpympEQJDq4bvD8hlRaet8TLnS7Djo5QOTMQ0D6VtCQj6jH82kji4ql9H0a6fg/uqhAKMR748kVVrHMe8GKWv8ytyj0tOY0WjahRwOT++sLK8RzvaBCLxGNO2ZLJmN9EKXUhSwGyE/RahgglGOlafEGUaQaSA8EGGKEbAvwH66mvimIz39fIsa575bIRifyneoJXkDxMdlpFap/iER3xfP1/gaWfkLhZm0Vwu0XLoAAAAASUVORK5CYII=',
+    icon: 'icon_14',
     tag_name: 'v1',
     state: AppState.DownloadCancelled,
   },
@@ -43,7 +51,7 @@ export default {
     date: BigInt(1689112046004),
     size: BigInt(3012),
     source_code_url: 'https://corp.com/',
-    image: 'data:image/jpeg;base64,/9j/4AAQSkZJRgABAQAAAQABAAD//gAfQ29tcHJlc3NlZCBieSBqcGVnLXJlY29tcHJlc3P/2wCEAAQEBAQEBAQEBAQGBgUGBggHBwcHCAwJCQkJCQwTDA4MDA4MExEUEA8QFBEeFxUVFx4iHRsdIiolJSo0MjRERFwBBAQEBAQEBAQEBAYGBQYGCAcHBwcIDAkJCQkJDBMMDgwMDgwTERQQDxAUER4XFRUXHiIdGx0iKiUlKjQyNEREXP/CABEIALwAvAMBIgACEQEDEQH/xAAdAAEAAgIDAQEAAAAAAAAAAAAABwgDBgIEBQEJ/9oACAEBAAAAALvAAAAAABomLce+AAAQN4UazfnmoAADRvN09lnYAAABUIAAAFvQAAAAAAGowlZsAAAKk22AAAAIJisAADtXAKrQ4AABy/Qoh6OQAAOzZ403WwAAMkpEb6qAAAnI1XwgAAOciENRyAABmtKVwioAABeQizQwAAM1iSIY+AAAz2YK9RgAAB9umRlpAAAGefQAAAAAAAH/xAAcAQEAAQUBAQAAAAAAAAAAAAAAAQIDBAYHBQj/2gAIAQIQAAAAuAAAIiZpABUAAADC18Ana/F54AV9a03hIBc+o72QAMPP2YAp0z3+lAFPGYAB/8QAGwEBAAEFAQAAAAAAAAAAAAAAAAECAwUGBwj/2gAIAQMQAAAAsgAAJmIqABSAJgAJjbOzAFvznmOggFHKMzvoBTyvUeLAFfpG1jACczktpAI0aAAf/8QAJxAAAgICAgEDBAMBAAAAAAAAAAYFFwQHAxYCARBACBM2UBQxNTL/2gAIAQEAAQgA/UyuzUODkMmKlsPamvc/z5vDDi5SPmsDGlIv4u9Vhc8UBsYPHgWFzE0VyzmLGMW10/Wiy34Ek8z1q69X8RfcWDP3S5J+V8PZSxnuSTNrcZ0yUqnoxMatYJDUEOgcLlrNszZZJaVJB1w4QGwJ90aPlVFvsqLfZUW+yot9lRb7Ki32VFvsqLfZUW+yot9lRb7Ki32VFvsqLfZUW+yot9lRb7Ki32VFvsqLfZUW+yot9lRb7Ki33+tdppkgYT1zlT6cnlydvPY3I5fH+mD/AFt0fM2q9sK7LYMTCWq+lqvxar8Wq/Fqvxar8Wq/Fqvxar8Wq/Fqvxar8Wq/Fqvxar8Wq/Fqvxar8Wq+mFtl34crg5Mn33f+W4PxPH/rxPT32NreQbpHElIyj2oo9qKPaij2oo9qKPaij2oo9qKPaij2oo9qKPaij2oo9qKPaij2oo9qKPaij2ow9HsH8rh9c73ZJnOwMnhxsPsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0dkmjsk0cTNMeHJ4eXn7t/8Ap8PyJ6B55Tn4snG6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6fJnT5M6hJnEn53ryeH3vfZDlOQMphxsRZLqWS6lkupZLqWS6lkupZLqWS6lkupZLqWS6lkupZLqWS6lkupZLqWS6lkupZLqY+zXDi5+Lk5vfb35Lh/IfEPPZ5DFkY6oWYqFmKhZioWYqFmKhZioWYqFmKhZioWYqFmKhZioWYqFmKhZioWYqFmKhZioWYx9Pz3rzcXple+w2+ag5LDjoqw3EsNxLDcSw3EsNxLDcSw3EsNxLDcSw3EsNxLDcSw3EsNxLDcSw3EsNxLDcSw3ExtjtnFz8Xnze+2PyPD+J6f36Hp7vCNmsudi5+BUzEVMxFTMRUzEVMxFTMRUzEVMxFTMRUzEVMxFTMRUzEVMxFTMRUzEVMxFTMRUzEYuppn7/ABfy/wBl/8QAPBAAAAYBAgMFBQUFCQAAAAAAAAECAwQF0pOkBxESkqOl0dMGEyAiQBUhUFJVQWFxsrMUJDEzQlFUVoH/2gAIAQEACT8A/CfaaLGmxzJLrK+vqQZkSv2EPamI6bLDj7nLr+VpoupSj5kJSJMKQk1NPI59KyIzLmX01FAK3/uZ/wBuKOgpHM5DaD+cUUBq0c9k1qXMQwhL6jcZ+YzWLiub9n2HCitVpsJWtxs3FfO6pSefzK/KoSUs01zTnMkxjbQszWbbyy+cy6v9BCaldNArEPxmPdII0OGTH39ZF1H/AJh/SOsNS5hRybXIUpLRe6fQ6fUaEqP/AASH4v2l9hfZ/vepfuPe9HTz6unq6RMrytIi2lLdW44Uc+hxS/uUSDULSvZuqKA3DWiZ1+5WSCMuaTJKvzGLetnKs642VnF60LJ5Smlcug0ERIT0Gkvq+J/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjgOJ/jVjh+G+yivaGxN9DZQkykReSFEfNw1rIy5JExS5MK3Q23G5J6InV19TKOn9ifqP+zH/O79ZJRFSqImS477tDilmtakdPzkZERdIvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvtrHwF9tY+AvttHwFqmQySy62lR2UktP8UJI/g/Smf6rn0n+5fBNjtOtxijrbfNRJMkqNRGRpJX5hYVeq76YsKvVd9MWFXqu+mLCr1XfTFhV6rvpiwq9V30xYVeq76YsKvVd9MWFXqu+mLCr1XfTFhV6rvpiwq9V30xYVeq76YsKvVd9MWFXqu+mLCr1XfTFhV6rvpiwq9V30xYVeq76Ysq9MclkbhtKcUvp/cRoL4Fk2RtE4pXSSjPmZly+8Te7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5Cb3aPITe7R5CSTiSP70GhBEZf8AhfB/xk/zK+odbSpLZNqS5zIuRGZ8y5EYfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIfjdpWIejdpWIkME3z+Y0mo1cv3cy+CQmOk4pPrc92halGpak8vnIyIi6RdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAXW3YwF1t2MBdbdjAWaXm0qI1NqYaIlF/FKSP4P0xr+q59RKjoWiOTC0PmpJckqNRGRpJX5hMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3ATKzVdwEys1XcBMrNV3AT4CWOous21uKXy/cRoL4H0sJOMT63OhK1KNSlJ5fORkRF0i427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAuNuxgLjbsYC427GAsUvNkouptTLREov4pSR/B+nN/1F/USmG3EMEytDxqIjIlGojI0kf5hNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzATa/UcwE2v1HMBNr9RzAT4RMEouv3alqVy7Jfif/8QAMREAAAMECAYCAgIDAAAAAAAAAAECAwQF0QYRFlFTVZGSEhQXUlShITEQEwdAIiMw/9oACAECAQE/AP6h/QqHz+KzuFZ3Cs7hWdwrO4VncKzuFZ3Cs7hWdwrO4Vnd/ZiC1odlKQo0nWXyQ5l4x17hzLxjr3DmXjHXuHMvGOvcOZeMde4cy8Y69w5l4x17hzLxjr3DmXjHXuCXl44i/wBy/u8F9EKQRRxg8MaPsQStTFK0pqZlWozUY6h0V8J+0TMdQ6K+E/aJmOodFfCftEzHUOivhP2iZjqHRXwn7RMx1Dor4T9omY6h0V8J+0TMdQ6K+E/aJmOodFfCftEzDP8AkKiqmiE8o+lWoirMimEmRpIy+jIU8hr7FKPNnVwYKbNv3Ml8CfsyIxYulGSvGhTFi6UZK8aFMWLpRkrxoUxYulGSvGhTFi6UZK8aFMWLpRkrxoUxYulGSvGhTFi6UZK8aFMWLpRkrxoUwyoXSc2rOuDty/yL5OqYQRkhBH9kkgyIjV8jhT2kOFPaQ4U9pDhT2kOFPaQ4U9pDhT2kOFPaQ4U9pDhT2l+Ia4vERe0OrsaSaKIzrWdRVELHxjGdtypCx8YxnbcqQsfGMZ23KkLHxjGdtypCx8YxnbcqQsfGMZ23KkLHxjGdtypCx8YxnbcqQsfGMZ23KkFUQjCUqV+12Ooq6iUcgYo09u7lFWTd5aEhmSFkajvMhaOC5gz9yFo4LmDP3IWjguYM/chaOC5gz9yFo4LmDP3IWjguYM/chaOC5gz9yFo4LmDP3IWjguYM/cgukcF4F1P6D+DvkD+z/wCv/8QALhEAAAQFAwMDBAIDAAAAAAAAAAECBAMFERWTUVRVBhfREiExEyIkMDJhEEBD/9oACAEDAQE/AP8AUL5FR7f4oWooWooWooWooWooWooWooWooWooWooWooWv66e1f3dFtW7yewYLqCiLD+lEV6VlUqkQsMl4priSLDJeKa4kiwyXimuJIsMl4priSLDJeKa4kiwyXimuJIsMl4priSLDJeKa4kiwyXimuJIiyGSnCiFa2pfafxCToFeylF/ZiQRJhCmUNctWhDgkqoayqmlPcXDrLeM8YuHWW8Z4xcOst4zxi4dZbxnjFw6y3jPGLh1lvGeMXDrLeM8YuHWW8Z4xcOst4zxiJMOsThrI3jShpP4QDrU6/NRIHMBrMURXEQkI9CiqepkL3Kt7DF7lW9hi9yrewxe5VvYYvcq3sMXuVb2GL3Kt7DF7lW9hi9yrewwqdyr0q/Mh/AV7qUf9jrZ05ZyKLGaxlwon1YafUg6HQzF9nXKusqhfZ1yrrKoX2dcq6yqF9nXKusqhfZ1yrrKoX2dcq6yqF9nXKusqhfZ1yrrKoX2dcq6yqEKfTookM7o6/kX/AFUEHVCDPQg5kcDqGEctcJrDUZLP7jT/ABHaOUaLzK8DtHKNF5leB2jlGi8yvA7RyjReZXgdo5RovMrwO0co0XmV4HaOUaLzK8DtHKNF5leB2jlGi8yvA7TSlBkskKP0+9Pqq8Cnp+3T2EqjQ4DtK4qySn0mVTFyY7lAuTHcoFyY7lAuTHcoFyY7lAuTHcoFyY7lAuTHcoFyY7lAVMmRJV+Qj4B/J/t//9k=',
+    icon: 'icon_15',
     tag_name: 'v1',
     state: AppState.Received,
   },
@@ -54,7 +62,7 @@ export default {
     date: BigInt(1629152046004),
     size: BigInt(9111),
     source_code_url: 'https://artc.com/',
-    image: 'data:image/png;base64,/9j/4AAQSkZJRgABAQAASABIAAD/4QCMRXhpZgAATU0AKgAAAAgABQESAAMAAAABAAEAAAEaAAUAAAABAAAASgEbAAUAAAABAAAAUgEoAAMAAAABAAIAAIdpAAQAAAABAAAAWgAAAAAAAABIAAAAAQAAAEgAAAABAAOgAQADAAAAAQABAACgAgAEAAAAAQAAASygAwAEAAAAAQAAASwAAAAA/+0AOFBob3Rvc2hvcCAzLjAAOEJJTQQEAAAAAAAAOEJJTQQlAAAAAAAQ1B2M2Y8AsgTpgAmY7PhCfv/AABEIASwBLAMBIgACEQEDEQH/xAAfAAABBQEBAQEBAQAAAAAAAAAAAQIDBAUGBwgJCgv/xAC1EAACAQMDAgQDBQUEBAAAAX0BAgMABBEFEiExQQYTUWEHInEUMoGRoQgjQrHBFVLR8CQzYnKCCQoWFxgZGiUmJygpKjQ1Njc4OTpDREVGR0hJSlNUVVZXWFlaY2RlZmdoaWpzdHV2d3h5eoOEhYaHiImKkpOUlZaXmJmaoqOkpaanqKmqsrO0tba3uLm6wsPExcbHyMnK0tPU1dbX2Nna4eLj5OXm5+jp6vHy8/T19vf4+fr/xAAfAQADAQEBAQEBAQEBAAAAAAAAAQIDBAUGBwgJCgv/xAC1EQACAQIEBAMEBwUEBAABAncAAQIDEQQFITEGEkFRB2FxEyIygQgUQpGhscEJIzNS8BVictEKFiQ04SXxFxgZGiYnKCkqNTY3ODk6Q0RFRkdISUpTVFVWV1hZWmNkZWZnaGlqc3R1dnd4eXqCg4SFhoeIiYqSk5SVlpeYmZqio6Slpqeoqaqys7S1tre4ubrCw8TFxsfIycrS09TV1tfY2dri4+Tl5ufo6ery8/T19vf4+fr/2wBDAAICAgICAgQCAgQGBAQEBggGBgYGCAoICAgICAoMCgoKCgoKDAwMDAwMDAwODg4ODg4QEBAQEBISEhISEhISEhL/2wBDAQMDAwUEBQgEBAgTDQsNExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExMTExP/3QAEABP/2gAMAwEAAhEDEQA/APZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//Q9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9H2Siiiv53P30KKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooA//0vZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//T9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9T2Siiiv53P30KKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooA//1fZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//W9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKK5nxj4v0HwH4buvFniaVobGzAaV1RnIDMFHyqCTye1XCEpyUIK7ZM5qEXObskdNRXgPg79pz4PePPEtr4S8M38019eMyxI9tNGCVUucsygD5VJ5r36tcRhK2GajiIOLfdWMsPiqWITlQmpJdncKKK8U+IX7Qfwt+F2ur4b8Z3stvdtCk4VLeWUbHLBTuRSOSp49qWHw1XES9nQi5PstR18RToR560lFeZ7XRXC/D34j+EvijoTeJPBk73FokrQFnjeI70AJG1wDxkc13VRVpTpSdOorNdGXTqRqRVSm7p9UFFFFZlhRRRQAUUUUAFFFFABRRRQAUUUUAFFeX/En4x+AfhKtm/jm6kthflxD5cMkufLxuzsBx1HWo/hr8aPh98W3vY/A11JcmwEZm8yGSLAlLBMbwM52N06Yrq+o1/ZfWfZvk7207bnN9coe1+r865+19e+x6pRRRXKdIUUUUAf/X9kooor+dz99Ciij60AFLtb0NfkR+0N+1p411/wAT3nhn4e3smmaRaSPAJLchZrgodpkMq5ZVLA7FUqcYJPOB8vQ/E74pWEkeqQa3qcbMcrJ9qn+baecEvhsHqOR619rheCsRVpqpVmot9N/vPkMVxhQpVHTpwckuu33H9C9FfBv7JX7S2u/Ee/k+H3j6UXGoJE01rdYVWlVD88cgGAXUEMGAG4ZyMjJ+h/j58XYPg18P5fEqxrNfTv8AZ7KJwSjTMC2XxzsRQWbkZAwDk14GJyXEUcWsA1eT27O57mHzahWwzxqdorfuj2wAnoKCCOvFfgL4l+OPxf8AHWrtdX+t30jy8LDBK8UYGScLFCVHH0JwOSa0vA37RXxh8AasLmy1m5mRSA9veyPcQMB2ZJCWX32FT+WK+jfAtfkuqq5u2v5/8A+fXGlDns6b5e//AAP+CfvPS4PpXBfC/wAf6X8UPBGneNtJUxx3q/PG3WOVCUkQ+u1gRnuMHvX4z/Ez4w/FTTPiFr2m2XiPU4oItQvI0RLqVQiieRVC4bgKMYA6dq8XKuH6uOq1KHNyuG9z18zz2lgqdOty8yntY/dXBr53/auBH7P/AIjz/wA8Y/8A0alflZ8Sf2jPib8Sr+OV7+fT7OGNY47WCd44/lABZ2BQyOSMktnGcADv9fWeoX2qfsEX95qM8lzKTOPMkcyMQt5gDcxJIA4HPSvW/wBWqmXSoYmrNN88VZevc8v/AFip4+NbD0oO3JJ3fkj5Q/ZKOf2hPD3/AF3m/wDSaav3LHSvw0/ZK/5OE8Pf9fE3/pNNX6I/td/GmT4ZeB18O6BO0Os60GSN4yVeGBcCSUMPuschEPBycjpXZxXgp4zMaGHpbuP6u7+RycMYuGEwFbEVdlL9F+Z9d4Poa/Hz9vM/8Xjh/wCwZa/+hz12f7JcPxd+KvjA6/4j8Q6pJoekMrzK9zLtmmPMcPUZH8Tj+7gHhq4v9vP/AJLHD/2DLX/0OejIct/s/NPq7mpPld7dNgzzMfr2W+3UHFcytfrufWv7CeT8FZv+wncf+gx19nkEdRivwI0D4y/ETR/h+Phv4QuJrG2E8t3M9ozrNIX2/edPmVF29FIznk9q0fh1+0T8U/AHiCLULTVLm8hDDzLW5meaKVc5KkSFtpPZlwQeeRxWuZ8I18VXrYmE0m22kZ5dxVRw1Glh5wbsrNn7y0deBWD4X8R6f4u8NWHirSyfsuo28dxFu4IWRQwz7joa/J/9oH9rbxt4k8S3fh34fX0umaPbSPAr25CzXBQ7TIZFyyqSDsVSvGCTk4HyWV5HXx9aVCGnLvfp/wAE+pzLOaGBpRrT15tkuv8AwD9f9rehpK/noh+JvxS06WPU4Nc1KJnJZJPtU/zbTzglyGx36j1r9I/2Sv2l9e+I9/J8P/H0guNQSJprW6wqtKqY3xyBcAuoIIYAbhnIyMn1My4Rr4Si8RCaklv0Z5mXcVUcXVVCcXFvbr/kfelKAT0FeR/Gz4rWHwd8A3Hi65QTzllgtYSdokmfOAT12qAWbHO0HFfjV4k+Ofxm+IWstPdazfSPMSEt7WSSKMDk4SKEjgD13HHUmuXJuG62YwdZSUY92dWb8Q0cvmqTi5SfRH74HjrRX4T/AA9/aS+L3w61pJk1W4vbdXHnWd7I00bgZBX94S0Z91IweoPQ/tb4B8ZaR8QvCWm+M9EJ+zajEsqq2NyE8MjYyNyNlTz1FZ5zw9Wy3lnNqUX1X6o0yjPaOYuUIK0l0/yOswfSjBPSvwg8f/GH4q2HjLVrCz8SanFCl5dIqrdSgKomkUAYbgAcDHTtR8Sf2iPib8S9XN7cahPY2a/LDaQTtFEigAclShkY4yzNk5PGBxXsw4Hry5X7VWZ5EuMqMbr2buj938HOKACegzXxF8J/irc+Af2QLb4ia00l/dQG4SJZXZ2kme5eOJSzEnbkjPoo4r82/E3xy+LPi/XJNV1DXb4yO+5UinkhjT0CRxMqqB24J9STXFgeFK2KqVYqaUYNxv3a8v8AgnXjOJ6WGhSk4NuaTt2T8z7f/wCChef7P8L/AO/d/wDoKVnf8E8f9d4r/wCuVj/6HcV8Y+Pvi1438deHNM8KeN5XupdHeQxTzZ8/bIqgpISMtjGQzfNjrnrX2d/wTwP77xX/ANcrH/0O4r6jG4GeCyOeGqO7Xb/EfOYPGwxmdQxFNWT7/wCE/TKiiivyw/SgooooA//Q9kooor+dz99CsHxTdS2PhjUr2A4eG0ndT6FY2I/Wt6uZ8a/8ibq//Xlcf+imrbD/AMSPqjOv/Dl6M/ny8HaVaa34v03SL4Ew3VxbQuAcHbI6I3P0Y1+5fxs+Gtr4z+D2qeB9EsI5Zo7bGnwrtjCTRYMWwkqqdMdQMcHivxG+G3/JQtE/6/bL/wBHRV/RI+d7fU/zr9B4zxM6FbD1Kb2u18rHwnCOGhXo4inNb2X5n5U/AT9mb40/D/4v6J4t13S0gsrWdzNIlzC22NonU/KGyQSQOBn2rpf+ChmoXIHhbSlbERW7mI9WBiQfoTX6X4NfmF/wUO41Lwt/1wvP/Q4a4MnzWpmWa0atdK6TWl+z7nbm2V08uyutSoN2bT1t3XYm/wCCfPhzTZrjxD4onjR7mBYLeFiuWRX3O5BPTdhQcdgK8w/bv8P6ZpHxattQ0+JYn1KxjnmCgDdIrvHu47soGT7Cvb/+Ce//ACBPEv8A12tv/QGryv8A4KBf8lL0f/sGD/0c9evh6s/9YJxb05f0TPKr0o/2FCSWvN+rR9HfsE6hdXXwnv7OdtyWuqMIx6CSKOQ/+PE1+X/xZAPxS8Qbun9p3ufp9okr9M/2Af8AkmOr/wDYVH/pNDX5m/Fn/kqPiL/sJX3/AKUSV1ZOrZpjLeRz5q28swl/M/Yf9nT4NeC/BPwy0q7FhBPqWo2sdxd3Msau7NKN+wEjhEztUDsM9TUX7TGj6ToP7OfiPT9DtYrO3CK/lQoETc8yszbVAGWJJPqea9h+GX/JONA/7B1t/wCilrzD9q3/AJN/8Sf9cY//AEalfCU8VVrZjF1JN3mvzPtKmGpUsul7OKXufoflL+zLreleG/jbo+u63MtvaWb3E0sjHAVEtZiT/nvUfi7X/Fv7SfxkMunx77nVJ1gs4GOFihXPloxGcBE3O5HfdjtXgx3eadvqa63wh4h1nwD4vsfEVgGivNPnSZFOVJKnO0jrh1JU+xr9dq4Re0liofHy2X5/8OfllPFPkWGn8HNd/l+Wx++vw0+H+jfC/wAFWXgvQ8tFar88h+9LK3Mkje7N+QwK/Lj9vT/kscP/AGDLX/0Oev1l8LeI9N8YeG7DxVo7b7XUIEuIj0O1wDgjsR0Ir8mv29P+Sxw/9gy1/wDQ56/OOE5VJZnOVb4rO/rdXP0HiiMI5dGNH4bq3pZn1F+wh4c0qx+FV54jjiU3l9fSRySFRu8uFVVEzjO0Ek49Sa/Pz9pfQdL8M/HLxDpejRCGBLveqDopljjlYAdhuc4A6V+kH7Dv/JD/APuIXP8A7LX57fta/wDJwXiL/r4i/wDSaGvbyarOWdYqLeln+DSPFzelGOUYZpa6fimz9KPgTqdxbfso6dqeSz2+l3RXP/TMyhfyAFfjn4K0q01/xppeiXo/c3dzawOASPllkRG5GDyCa/Xz4Jf8mf23/YKvf/Qpa/JX4X/8lK0D/sIWX/o+Otcg92eOkv5n+plnivDBRf8AKv0P2Y/aX8JaBqPwE12ylto1j0u1+0WoVQPKe3wU2ccDHynHUEjvX5WfsxTvYfH/AMOtbnB+3eX/AMBeORD+hr9eP2iP+SHeLP8AsGzfyr8gf2cB/wAX+8O/9hJf5PXFwzOU8txKm7/F/wCko7OI4RhmOHcVb4fzP1p+PHwRi+OOiWGiXGpPp0dlO05KxiTeWQoBgkAYBNYvwE/Zu0f4HT6jfW14dTuL9Y4xK8IRokQsSqkE8MWBPToKP2lfjmPgp4Qhl0xUl1fU2eO0V+VQIMvKy8FguQAO7EZwM1+Xmha7+0T8cNfuI9CvdU1e5CeZIsc7RRovQZCvFEgJ6DjOOAcGvMynLsbi8C4yrKFHz9fl18z0c0x+DwuNUo0nOt5enz6eR6D+3NpOm6b8aHuLCFYnurG1mlKjG6RjKpY+5VFB+lfav7DdzPcfBNEncsI9RuFQHsCVYj8yT+NflP8AFTwb8QfBHiJdJ+IySJfGCOQCSbzz5blgvzbm7q3GePxr9Uf2Ff8Akiv/AHErj/2Svb4ipKnlFOmpc1nHXueRw/UdTNalRx5b82nY/KD4jAN8QdYzyBfXZ/KeQ1+0nwL+C/gjwD8PtOjgsLe4vru2jmu7qSNWeV5FDkZYHCLnCqOB9a/Fz4i/8lA1n/r8vP8A0dLX9AHg/wD5FHSv+vK3/wDRa1lxliKlPDUIQk0nvY04SoU6mIrTnG7W3zPjT9uC1svC3wR07RvDkEdjazaugaKBAicxzSk7VwMlxuPqea+Wf2GtD0zWPjSJ9RiEpsLG4uYdwyBIGijDY9QJGx6Gvqz9v/8A5JHpP/YYj/8ASeevmj9gX/ksV5/2Cbn/ANHW9LLZy/sGrO+r5tR5hCP9t0oNae6ep/8ABQTw1piL4f8AFcMapdSefayuFG50UCRNx77SCB/vGof+CeH+u8V/9crH/wBDuK6f/goKR/wjHh0f9PM//oquY/4J4f67xZ/1zsf/AEO4qITlLh2XM/65i5QUc/XKv65T9MqKKK/OT74KKKKAP//R9kooor+dz99CsXxJZS6l4d1DT4fvz200a/V0YD9TW1R05qoS5ZKS6EzjzJxfU/nI8NasvhvxVY6vcxs32KeCVk6MfJdHK89/lI+tfsb8bv2gPANp8G9R1jwnr8El/qVsUsBazL5/mSEDcACGXy85cnG3GOtfOv7Qn7GfiPVPEV34x+FKR3MV67TzWLyCORJXOW8ov8jIxJbaWUqTgZGAPmGP9lL4/wBxIsC+HLlSxAy7Qqv1Leb0Ffq9d5dm3scXOsly62bXlo7+aPzGgsflftsLCk3zaXSfnqrep6V+zT8Uvir4y+N2h6Lqeu6he2hmkkmikmZkaNInJ3joRnHXjNexf8FCtGupbXwxr0YJhjN1bucdGYJIv6I35V7N+zF+zPJ8G1m8T+KpY59buYzCqwktHbxE5IDEDc74G5sAAAAdyfcPjB8LdH+MHge58H6swhdiJbafaHME6g7HCnGRyQwyMqSMivAxedYanm1OvQS5IKzaXe938rnu4XJ8TPK6lCtfnk7pN9rWXzsfnx+wp8SvCnhbUNb8L+Jr2Cxa/WGa3edgiu8e5WTcxAzgqQOp5x0rzX9tLx94b8d/FKE+F7qO9t9Os0tmmhbcjyF2d9rDghdwGRkZz6GszxN+x18ctA1aS0sNK/tK3/gns5I3RhnusjI6+uCD9TWn4I/Yu+M3iPVRFr9ouiWikbri7ZGIH+xFGzFiOwJUe9fSxWXQxcs29urtbXX/AA584/r88LHK/Yuye9n5/LfqfZv7B+kXWn/CK6v7j7t9qcjx/SKOOI/+PKa/Lj4s/wDJUfEX/YSvv/SiSv3y8DeDtE+H3haw8H+Ho/LtLCMRpnG5jnLO2MAs7Esx9TX5O+Pv2TPjt4g8cazrum6OjW93fXU0RNzACySTOyHBfjIYHB59cV4uQ5tQnjsVias1FSta7S7nsZ3llaGCw2HpwcnG97Jvsfqn8Mv+ScaB/wBg62/9FLXmH7Vv/Jv/AIk/64x/+jUr13wLpt7o3grSNI1JPLuLWygilXIO10QKwyMg4I7Vw3x/8Ja945+EOteFPDEIuL68jRYoyyoCRIrH5mIA4BPJr47DVIrHxqN6c+/zPrMRTk8DKCWvJt8j8cP2ePCWjeOPjNonhrxAnm2dxct5qdnWOOSXaf8AZYoA3qCR3r3v9uj4cjw38Qbbxvp0Wy11yL59oOFuIAEYf3Ruj2EDj7pNdP8As+/szfGXwB8YtF8WeJtLSGwtZpXmkW4hcoGgkQfKrFjlmA4GfwzX2v8AtI/C+6+K/wAK7zQdJiWXU7ZkurIMQMypwU3EqB5iFkySAM8193j89p0s0oSp1E4Ws7PRXfX7kfF4LJalXLa0Z02p3urrV2X/AAWfPH7CHxMXWPC978Mr9x5+mMbq1UkZMErfvFA6/JIc9OA4r58/b0/5LHD/ANgy1/8AQ566P4K/s+/tFfCf4kaZ4vTRle3hl8u5VbuDLW8vyS8b+cD5wPVRXov7WP7P/wAVfin8TI9e8F6ct1ZJYW8JlaeKP94jylhtdgeAw5xjnjPOIpVMJQzh4qFWPJOL1ut7r89x1YYqvlSw06UuaMl0e2v5F79if4reB9I+G994T8R6na6ddWd1JcgXEqxh4ZEVi4L4B2kENgnHB718M/tA+LNI8d/GbXPEOgP5trc3eInHIdY0SIMPZihI9iK9Zv8A9ir4zWnhSDWre0Sa9LyLcWAkiMqhWwkkbB/LdWXkjcrL6HOAfDz9jD4teJPEMcPi2ybRdOBzNPO0Zk29xGiM5L+hbAHXnGD6OGlluGxFbMo11726uvnbq72/yOHERzDEYejl8qD93bR/K/pf/M+//gPol1P+yzpejSKRJd6XcbR3xN5jJ+YYV+MvhXVovDfi3TtbmUuLG4t52QdT5Lo7Lzjn5SPrX9EejaTp+gaTa6FpMYhtbKJIYUXoqIAqj8AK/Nn9oH9jLxJqHiK68X/ChI7mC8dppLFpBHJHI53N5Rf5GQkkhSylScDIwB4HDmc4dYjEQxD5VUd1f56fce5xBlFd0KE6C5nTVnb5HvX7RXxw+Gdx8DNVTRdYtL+bW7Y29tDDKrSN5pAZioyQIxktnGCMda/Or9lqxn1f4/eHhbqflu2nbuAsccjEn07D6ketPi/ZR+P1zKsC+HblSxAzI0KL9Sxl6Cv0P/Zi/Zlf4Oed4p8VSxz63cxmFVhYtHbxMcsAxA3O+BuOAABgdyfQqVMDk+Bq0aFVTlK9tU3qrdOxwQp43NsbSrVqTio26NLTXr3Pn3/goQtyNe8OOxxC1pcBef4hLHu/pWp+wR4z8JaZp+u+GdRnhtL+eSK4jMrqhljVNpCkkZ2MCSP9rNfWn7RHwXj+Nfgb+xrSVLfU7OTz7OWTOzdgq0bkZIVwcZAO04ODjFflfP8Asm/H20vXtE8PTSlWKhkeB4m9CGMgGPcge4FY5TWwuPyr+z61Xka80ut09TbNKOJwWZ/XqNNzT9X0s1obX7ZPjfw940+ME8vhydbqGztoLRpoyGRpIjIz7SOoBfbn1Br7s/YV/wCSK/8AcSuP/ZK+PPFn7EPxW0+000eHYE1G5mgZ70rNEkUMm7CRoXKs/wAvJbGM9McCvvf9lP4feLPhj8Mv+Eb8aWwtbz7dNNsEiSDY23B3ISOx461GfYnCf2XDDYWqpcrS3V9NNisjw+K/tKeJxNNx5k3s7a67n42/EX/koGs/9fl5/wCjpa/oB8H/APIo6V/152//AKLWvyO8Zfsl/HrV/FOqazYaKrwz3N1JHm6gBZJJXZTgvxkMDgn64r9efDdpcaf4d0+wu12ywW0MbrkHDKgBGRxwR2rl4vxdGvRoRozUmr3s0+h08KYStRrV3Wg0na1011PkT9u/SbrUfg3aXNuMrZ6pDI/0eOSIf+POK+Jf2NvHXhvwH8XBd+KLqOztr2zntPOlYLGju0bqWY8AHyyMnjJFfrz8RfAuj/EvwXqHgnXMrBfR7Q6/ejdTuSRfdWANfkB4n/Y8+OOha1NZafpLajbhv3dxaPGUcH2d1ZfcEHHTJ610cNYzDV8BUy7Ez5d93bR9r9jDiHCYmjjoY/DwctvPVf5nsv7dfxM8KeJrjRfCfhq9hv5LLzp7h4GDrGzgIiFl43EbiRnIwPWt7/gnh/rvFf8A1zsf/Q7ivHJ/2JfjKnhW11RLeOXUZ5irWSyxDyIQhO+SRmC7i/GxN2ByTzx9a/sdfBn4h/CSfxB/wnditmt9HaCArLHLuMTTF/uMcY3jr6/WunH1cFQyieCw1ZSa81d+8r6GGBpYytmsMXiKTin5Oy07n3BRRRX5mfoYUUUUAf/S9kooor+dz99CiiigA69aTApaKBhRRRQFwooooAKKKKBBRRRQMKKKKYgooopDCiiigQUUUUAFFFFABSYHpS0UDuFFFFABRRRTuAUUUUgD2ooooAKKKKBBRRRQB//T9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9T2Siiiv53P30KKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooA//1fZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//W9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9f2Siiiv53P30KKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooA//0PZKKKK/nc/fQooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigD//R9kooor+dz99CiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKACiiigAooooAKKKKAP/9k=',
+    icon: 'icon_16',
     tag_name: 'v1',
     state: AppState.Updating,
   },
@@ -4,6 +4,7 @@ import { produce } from 'solid-js/store'
 import type { AppInfoWithState, AppInfosById } from './types'
 import { AppState } from './types'
 import type { WebxdcStatusUpdatePayload } from './bindings/WebxdcStatusUpdatePayload'
+import type { Curation } from './bindings/Curation'
 import type { AppInfoDB } from './db/store_db'
 import { isOutdatedResponse, isUpdateSendResponse as isUpdateSentResponse } from './utils'
 
@@ -11,6 +12,8 @@ export type DownloadResponseOkay = Extract<WebxdcStatusUpdatePayload, { type: 'D
 export type DownloadResponseError = Extract<WebxdcStatusUpdatePayload, { type: 'DownloadError' }>
 export type UpdateResponse = Extract<WebxdcStatusUpdatePayload, { type: 'Update' }>
 export type InitResponse = Extract<WebxdcStatusUpdatePayload, { type: 'Init' }>
+export type IconsResponse = Extract<WebxdcStatusUpdatePayload, { type: 'Icons' }>
+export type ScreenshotsResponse = Extract<WebxdcStatusUpdatePayload, { type: 'Screenshots' }>
 
 function isDownloadResponseOkay(p: any): p is DownloadResponseOkay {
   return p.type === 'DownloadOkay'
@@ -28,6 +31,14 @@ function isInit(p: any): p is InitResponse {
   return p.type === 'Init'
 }
 
+function isIconsResponse(p: any): p is IconsResponse {
+  return p.type === 'Icons'
+}
+
+function isScreenshotsResponse(p: any): p is ScreenshotsResponse {
+  return p.type === 'Screenshots'
+}
+
 export function to_app_infos_by_id<T extends { app_id: string }>(app_infos: T[]): Record<string, T> {
   return app_infos.reduce((acc, appinfo) => {
     acc[appinfo.app_id] = appinfo
@@ -46,12 +57,30 @@ export async function updateHandler(
   setlastUpdate: Setter<Date>,
   setUpdateNeeded: Setter<boolean>,
   setUpdateReceived: Setter<boolean>,
+  setCuration: Setter<Curation>,
+  setIcons: SetStoreFunction<Record<string, string>>,
+  setScreenshots: SetStoreFunction<Record<string, string[]>>,
+  setUpgradeSuggested: Setter<boolean>,
 ) {
+  // The curation is always sent in full and does not depend on the serial.
+  if ((isInit(payload) || isUpdateResponse(payload)) && payload.curation !== undefined) {
+    setCuration(payload.curation)
+  }
+
   if (isInit(payload)) {
     console.log('Initialising apps')
     const app_infos = to_app_infos_by_id((payload.app_infos).map(app_info => ({ ...app_info, state: AppState.Initial })))
+    // An index sent after a change of the release channel replaces the known apps.
+    const removed = Object.keys(appInfo).filter(key => app_infos[key] === undefined)
+    if (removed.length > 0) {
+      setAppInfo(produce((s) => {
+        removed.forEach(key => delete s[key])
+      }))
+      await db.remove_multiple_app_infos(removed)
+      removed.forEach(key => db.remove_webxdc(key))
+    }
     setAppInfo(app_infos)
-    await db.insertMultiple(Object.values(app_infos))
+    await db.updateMultiple(Object.values(app_infos))
     setIsUpdating(false)
     setlastUpdateSerial(payload.serial)
   }
@@ -107,9 +136,25 @@ export async function updateHandler(
     console.log('Problem downloading some webxdc')
     setAppInfo(payload.app_id, 'state', AppState.DownloadCancelled)
   }
+  else if (isIconsResponse(payload)) {
+    console.log('Received icons')
+    await db.add_icons(payload.icons)
+    setIcons(payload.icons)
+  }
+  else if (isScreenshotsResponse(payload)) {
+    console.log('Received screenshots')
+    const offset = payload.offset
+    setScreenshots(payload.app_id, screenshots => [...(screenshots ?? []).slice(0, offset), ...payload.screenshots])
+  }
   else if (isOutdatedResponse(payload)) {
-    console.log('Current tag_name is outdated')
-    setUpdateNeeded(true)
+    if (payload.critical) {
+      console.log('Current tag_name is outdated')
+      setUpdateNeeded(true)
+    }
+    else {
+      console.log('Newer compatible tag_name available')
+      setUpgradeSuggested(true)
+    }
   }
   else if (isUpdateSentResponse(payload)) {
     console.log('Update received')
@@ -1,6 +1,15 @@
 import type { AppInfo } from './bindings/AppInfo'
+import type { ProtocolVersion } from './bindings/ProtocolVersion'
 import type { WebxdcStatusUpdatePayload } from './bindings/WebxdcStatusUpdatePayload'
 
+// Protocol version of the frontend, the type exported by the bot makes sure it matches.
+export const PROTOCOL_VERSION: ProtocolVersion = 1
+
+// Sends a request to the bot together with the protocol version of the frontend.
+export function sendRequest(payload: WebxdcStatusUpdatePayload) {
+  window.webxdc.sendUpdate({ payload: { ...payload, protocol: PROTOCOL_VERSION } }, '')
+}
+
 export function isAppInfo(p: any): p is AppInfo {
   return Object.prototype.hasOwnProperty.call(p, 'version')
 }
@@ -5,12 +5,13 @@ import { createStore } from 'solid-js/store'
 import { AppInfoDB } from '../src/db/store_db'
 import { AppState } from '../src/types'
 import type { AppInfoWithState, AppInfosById } from '../src/types'
-import type { DownloadResponseError, DownloadResponseOkay, InitResponse, UpdateResponse } from '../src/store-logic'
+import type { DownloadResponseError, DownloadResponseOkay, IconsResponse, InitResponse, ScreenshotsResponse, UpdateResponse } from '../src/store-logic'
 import { updateHandler } from '../src/store-logic'
 import type { WebxdcOutdatedResponse, WebxdcUpdateSentResponse } from '../src/utils'
 import 'fake-indexeddb/auto'
-import mock from '../src/mock'
+import mock, { mockIcons } from '../src/mock'
 import type { AppInfo } from '~/bindings/AppInfo'
+import type { Curation } from '~/bindings/Curation'
 
 const general_handlers = {
   getLastSerial: (() => 0) as (() => number),
@@ -20,6 +21,10 @@ const general_handlers = {
   setlastUpdate: ((() => { }) as Setter<Date>),
   setUpdateNeeded: ((() => { }) as Setter<boolean>),
   setUpdateReceived: ((() => { }) as Setter<boolean>),
+  setCuration: ((() => { }) as Setter<Curation>),
+  setIcons: ((() => { }) as SetStoreFunction<Record<string, string>>),
+  setScreenshots: ((() => { }) as SetStoreFunction<Record<string, string[]>>),
+  setUpgradeSuggested: ((() => { }) as Setter<boolean>),
 }
 
 describe('Store receiving updates', () => {
@@ -37,11 +42,32 @@ describe('Store receiving updates', () => {
     } as WebxdcOutdatedResponse
 
     const updateNeeded = vi.spyOn(handlers, 'setUpdateNeeded')
-    updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
 
     expect(updateNeeded).toHaveBeenCalledWith(true)
   })
 
+  test('Handles non-critical outdated response', () => {
+    const handlers = {
+      db: new AppInfoDB('storetesting'),
+      appInfo: {},
+      ...general_handlers,
+    }
+
+    const payload = {
+      type: 'Outdated',
+      critical: false,
+      tag_name: 'v2',
+    } as WebxdcOutdatedResponse
+
+    const updateNeeded = vi.spyOn(handlers, 'setUpdateNeeded')
+    const upgradeSuggested = vi.spyOn(handlers, 'setUpgradeSuggested')
+    updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
+
+    expect(upgradeSuggested).toHaveBeenCalledWith(true)
+    expect(updateNeeded).not.toHaveBeenCalled()
+  })
+
   test('Handes update received', () => {
     const handlers = {
       db: new AppInfoDB('storetesting'),
@@ -54,7 +80,7 @@ describe('Store receiving updates', () => {
     } as WebxdcUpdateSentResponse
 
     const setUpdateReceived = vi.spyOn(handlers, 'setUpdateReceived')
-    updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
     expect(setUpdateReceived).toHaveBeenCalledWith(true)
   })
 
@@ -72,7 +98,7 @@ describe('Store receiving updates', () => {
     } as DownloadResponseError
 
     const setAppInfo = vi.spyOn(handlers, 'setAppInfo')
-    updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
     expect(setAppInfo).toHaveBeenCalledWith(payload.app_id, 'state', AppState.DownloadCancelled)
   })
 
@@ -95,7 +121,7 @@ describe('Store receiving updates', () => {
     }
 
     const setAppInfo = vi.spyOn(handlers, 'setAppInfo')
-    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
     expect(await db.get_webxdc(payload.app_id)).matchSnapshot()
     expect(await db.get(payload.app_id)).toStrictEqual({ ...mock.app_12, state: AppState.Received })
     expect(setAppInfo).toHaveBeenCalledWith(payload.app_id, 'state', AppState.Received)
@@ -110,7 +136,7 @@ describe('Store receiving updates', () => {
       data: 'test',
     }
 
-    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
     expect(setAppInfo).toHaveBeenCalledWith(payload.app_id, 'state', AppState.Received)
     expect(await db.get(payload.app_id)).toStrictEqual({ ...mock.app_16, state: AppState.Received })
     expect(await db.get_webxdc(payload.app_id)).toMatchSnapshot()
@@ -133,7 +159,7 @@ describe('Store receiving updates', () => {
     const setAppInfo = vi.spyOn(handlers, 'setAppInfo')
     const satlastUpdateSerial = vi.spyOn(handlers, 'setlastUpdateSerial')
     const setIsUpdating = vi.spyOn(handlers, 'setIsUpdating')
-    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
 
     const apps_with_initial_state = Object.keys(mock).reduce((res, key) => {
       res[key] = { ...mock[key], state: AppState.Initial }
@@ -145,6 +171,93 @@ describe('Store receiving updates', () => {
     expect(setIsUpdating).toHaveBeenCalledWith(false)
   })
 
+  test('Handles Init after switching back to stable', async () => {
+    const db = new AppInfoDB('storetesting9')
+    // The index of the beta channel.
+    await db.insertMultiple(Object.values(mock))
+    const [appInfo, setAppInfo] = createStore({ ...mock })
+    const handlers = {
+      db,
+      ...general_handlers,
+      appInfo,
+      setAppInfo,
+    }
+
+    const payload = {
+      type: 'Init',
+      app_infos: [mock.app_12, mock.app_13],
+      serial: 14,
+    } as InitResponse
+
+    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
+    expect(Object.keys(appInfo).sort()).toStrictEqual(['app_12', 'app_13'])
+    expect((await db.get_all()).map(app_info => app_info.app_id).sort()).toStrictEqual(['app_12', 'app_13'])
+  })
+
+  test('Handles icons', async () => {
+    const db = new AppInfoDB('storetesting7')
+    const handlers = {
+      db,
+      ...general_handlers,
+      appInfo: {},
+    }
+
+    const payload: IconsResponse = {
+      type: 'Icons',
+      icons: { icon_12: mockIcons.icon_12 },
+    }
+
+    const setIcons = vi.spyOn(handlers, 'setIcons')
+    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
+    expect(setIcons).toHaveBeenCalledWith(payload.icons)
+    expect(await db.get_all_icons()).toStrictEqual(payload.icons)
+  })
+
+  test('Handles screenshots', async () => {
+    const handlers = {
+      db: new AppInfoDB('storetesting8'),
+      ...general_handlers,
+      appInfo: {},
+    }
+
+    const payload: ScreenshotsResponse = {
+      type: 'Screenshots',
+      app_id: 'app_12',
+      screenshots: [mockIcons.icon_12],
+    }
+
+    const setScreenshots = vi.spyOn(handlers, 'setScreenshots')
+    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
+    expect(setScreenshots).toHaveBeenCalledWith('app_12', payload.screenshots)
+  })
+
+  test('Handles curation', async () => {
+    const handlers = {
+      db: new AppInfoDB('storetesting6'),
+      ...general_handlers,
+      appInfo: {},
+    }
+
+    const curation: Curation = {
+      featured: ['app_12'],
+      priorities: { app_12: 3 },
+      collections: [{ name: 'Games', app_ids: ['app_15'] }],
+    }
+    const payload = {
+      type: 'Update',
+      app_infos: {},
+      serial: 12,
+      old_serial: 10,
+      updating: [],
+      curation,
+    } as UpdateResponse
+
+    const setCuration = vi.spyOn(handlers, 'setCuration')
+    // The curation is applied even if the serial does not match.
+    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
+    expect(setCuration).toHaveBeenCalledWith(curation)
+  })
+
   test('Handles ongoing AppIndex updates', async () => {
     const db = new AppInfoDB('storetesting3')
 
@@ -183,7 +296,7 @@ describe('Store receiving updates', () => {
     const setIsUpdating = vi.spyOn(handlers, 'setIsUpdating')
     const setlastUpdate = vi.spyOn(handlers, 'setlastUpdate')
 
-    await updateHandler(payload, handlers.db, handlers.appInfo, () => 10, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, handlers.db, handlers.appInfo, () => 10, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
 
     expect(insertMultiple).toHaveBeenCalledWith([{ ...mock.app_13, state: AppState.Initial }, { ...mock.app_14, state: AppState.Initial }, { ...mock.app_16, state: AppState.Initial }])
     expect(updateMultiple).toHaveBeenCalledWith([mock.app_12, mock.app_15])
@@ -200,7 +313,7 @@ describe('Store receiving updates', () => {
       updating: ['app_15'],
     } as UpdateResponse
 
-    await updateHandler(payload, handlers.db, handlers.appInfo, () => 10, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, handlers.db, handlers.appInfo, () => 10, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
     expect(appInfo.app_15.state).toBe(AppState.Updating)
 
     const download: DownloadResponseOkay = {
@@ -210,7 +323,7 @@ describe('Store receiving updates', () => {
       data: 'test',
     }
 
-    await updateHandler(download, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(download, handlers.db, handlers.appInfo, handlers.getLastSerial, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
     expect(appInfo.app_15.state).toBe(AppState.Received)
   })
 
@@ -238,7 +351,7 @@ describe('Store receiving updates', () => {
       updating: [],
     } as UpdateResponse
 
-    await updateHandler(payload, handlers.db, handlers.appInfo, () => 10, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, handlers.db, handlers.appInfo, () => 10, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
     expect(await db.get('app_12')).toStrictEqual({ ...mock.app_12, description: 'pupu' })
   })
 
@@ -264,7 +377,7 @@ describe('Store receiving updates', () => {
 
     const removeSpy = vi.spyOn(db, 'remove_multiple_app_infos')
     const cacheDeleteSpy = vi.spyOn(db, 'remove_webxdc')
-    await updateHandler(payload, handlers.db, handlers.appInfo, () => 10, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, handlers.db, handlers.appInfo, () => 10, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setCuration, handlers.setIcons, handlers.setScreenshots, handlers.setUpgradeSuggested)
     expect(removeSpy).not.toHaveBeenCalledWith([['app_12']])
     expect(cacheDeleteSpy).not.toHaveBeenCalledWith([['app_12']])
   })
@@ -0,0 +1,11 @@
+-- Serial of the newest app index change removed by garbage collection.
+-- Frontends with an older serial can't be updated incrementally
+-- and receive the full index instead.
+ALTER TABLE config ADD COLUMN gc_serial INTEGER NOT NULL DEFAULT 0;
+
+-- Apps deleted completely by garbage collection.
+-- Frontends which can't be updated incrementally anymore
+-- are told to remove them.
+CREATE TABLE IF NOT EXISTS deleted_apps (
+    app_id TEXT PRIMARY KEY NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Settings from the configuration file which were applied last, serialized as JSON.
+ALTER TABLE config ADD COLUMN settings TEXT;
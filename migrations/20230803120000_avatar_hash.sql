@@ -0,0 +1,2 @@
+-- SHA-256 hash of the avatar file applied from the configuration file.
+ALTER TABLE config ADD COLUMN avatar_hash TEXT;
@@ -0,0 +1,10 @@
+-- Group chats the store was posted to.
+CREATE TABLE IF NOT EXISTS group_chats (
+    chat_id INTEGER PRIMARY KEY NOT NULL,
+    -- Address of the member who added the bot to the group.
+    admin_addr TEXT NOT NULL,
+    -- True if downloads are disabled in the group.
+    -- The bot can't tell which group member requested a download,
+    -- so downloads can only be disabled for the whole group.
+    downloads_disabled BOOLEAN NOT NULL DEFAULT 0
+);
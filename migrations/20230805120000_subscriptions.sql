@@ -0,0 +1,10 @@
+-- Apps contacts subscribed to, to get notified about new versions.
+CREATE TABLE IF NOT EXISTS subscriptions (
+    contact_id INTEGER NOT NULL,
+    app_id TEXT NOT NULL,
+    PRIMARY KEY (contact_id, app_id)
+);
+
+-- Serial up to which subscribers were notified about new app versions.
+ALTER TABLE config ADD COLUMN notified_serial INTEGER NOT NULL DEFAULT 0;
+UPDATE config SET notified_serial = serial;
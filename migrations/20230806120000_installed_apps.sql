@@ -0,0 +1,12 @@
+-- App versions cached in the store frontends of the contacts.
+CREATE TABLE IF NOT EXISTS installed_apps (
+    contact_id INTEGER NOT NULL,
+    addr TEXT NOT NULL,
+    app_id TEXT NOT NULL,
+    tag_name TEXT NOT NULL,
+    -- Timestamp of the last update request reporting the app.
+    last_seen INTEGER NOT NULL,
+    PRIMARY KEY (contact_id, app_id)
+);
+
+CREATE INDEX IF NOT EXISTS installed_apps_app_id ON installed_apps (app_id);
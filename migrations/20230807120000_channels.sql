@@ -0,0 +1,8 @@
+-- Release channel of the app version, 0 = stable, 1 = beta.
+ALTER TABLE app_infos ADD COLUMN channel INTEGER NOT NULL DEFAULT 0;
+
+-- Release channels the contacts opted into.
+CREATE TABLE IF NOT EXISTS contact_channels (
+    contact_id INTEGER PRIMARY KEY NOT NULL,
+    channel INTEGER NOT NULL
+);
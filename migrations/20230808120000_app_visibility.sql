@@ -0,0 +1,12 @@
+-- Visibility rules of apps restricted to selected contacts, serialized as JSON.
+-- Apps without a rule are public.
+CREATE TABLE IF NOT EXISTS app_visibility (
+    app_id TEXT PRIMARY KEY NOT NULL,
+    rule TEXT NOT NULL
+);
+
+-- The serial is not only raised by garbage collection
+-- but also when the visibility of an app changes.
+-- Frontends with an older serial receive the full index
+-- with removals of all apps they can't see anymore.
+ALTER TABLE config RENAME COLUMN gc_serial TO resync_serial;
@@ -0,0 +1,20 @@
+-- Featured flag and ordering priority of apps, independent of the app versions.
+CREATE TABLE IF NOT EXISTS app_curation (
+    app_id TEXT PRIMARY KEY NOT NULL,
+    featured BOOLEAN NOT NULL DEFAULT 0,
+    -- Apps with a higher priority are shown first.
+    priority INTEGER NOT NULL DEFAULT 0
+);
+
+-- Curated collections of apps, e.g. "Games".
+CREATE TABLE IF NOT EXISTS collections (
+    name TEXT PRIMARY KEY NOT NULL,
+    position INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS collection_apps (
+    collection TEXT NOT NULL,
+    app_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (collection, app_id)
+);
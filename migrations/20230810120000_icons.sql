@@ -0,0 +1,12 @@
+-- App icons stored once per content hash.
+CREATE TABLE IF NOT EXISTS icons (
+    -- Hex encoded SHA-256 hash of the data URL.
+    hash TEXT PRIMARY KEY NOT NULL,
+    -- Icon encoded as a data URL.
+    data TEXT NOT NULL
+);
+
+-- Hash of the icon of the app version.
+-- Icons of existing versions are moved from the `image` column
+-- to the icons table when the bot starts.
+ALTER TABLE app_infos ADD COLUMN icon TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,9 @@
+-- Screenshots of app versions.
+CREATE TABLE IF NOT EXISTS screenshots (
+    app_info_id INTEGER NOT NULL,
+    -- Position of the screenshot in the listing.
+    position INTEGER NOT NULL,
+    -- Screenshot encoded as a data URL.
+    data TEXT NOT NULL,
+    PRIMARY KEY (app_info_id, position)
+);
@@ -0,0 +1,8 @@
+-- Contacts who were sent the store after completing a SecureJoin
+-- and have not written a message since.
+CREATE TABLE IF NOT EXISTS pending_welcomes (
+    contact_id INTEGER PRIMARY KEY NOT NULL,
+    -- Time the store was sent after joining.
+    -- Only messages shortly after joining are considered answered by the welcome.
+    sent INTEGER NOT NULL
+);
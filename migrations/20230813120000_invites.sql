@@ -0,0 +1,21 @@
+-- Named invite QR codes, e.g. per campaign or partner.
+-- Each invite is the SecureJoin QR code of a group of its own,
+-- so the bot can tell through which invite a contact joined.
+CREATE TABLE IF NOT EXISTS invites (
+    name TEXT PRIMARY KEY NOT NULL,
+    -- Group of the Delta Chat account the QR code of the invite joins.
+    chat_id INTEGER NOT NULL UNIQUE,
+    created INTEGER NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT 0
+);
+
+-- Contacts who completed a SecureJoin with the bot.
+CREATE TABLE IF NOT EXISTS invite_joins (
+    contact_id INTEGER PRIMARY KEY NOT NULL,
+    addr TEXT NOT NULL,
+    -- Name of the invite or NULL for the default invite.
+    invite TEXT,
+    joined INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS invite_joins_invite ON invite_joins (invite);
@@ -0,0 +1,2 @@
+-- App opened by a deep-link invite, NULL for invites to the store.
+ALTER TABLE invites ADD COLUMN app_id TEXT;
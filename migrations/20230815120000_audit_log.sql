@@ -0,0 +1,23 @@
+-- Changes of the app catalog.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- Timestamp of the change.
+    time INTEGER NOT NULL,
+    -- `create`, `remove`, `delete` or `reset`.
+    action TEXT NOT NULL,
+    -- What triggered the change, e.g. `import`.
+    source TEXT NOT NULL,
+    -- Operating system user who ran the command, if known.
+    user TEXT NOT NULL,
+    -- NULL for changes of the whole index.
+    app_id TEXT,
+    channel INTEGER,
+    previous_tag_name TEXT,
+    tag_name TEXT,
+    -- SHA-256 hash of the .xdc file.
+    file_hash TEXT,
+    -- Serial of the index after the change.
+    serial INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_app_id ON audit_log (app_id, time);
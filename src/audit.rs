@@ -0,0 +1,107 @@
+//! Audit log of the changes of the app catalog.
+//!
+//! Each created, removed or deleted app version and each reset of the index
+//! is recorded together with what triggered it,
+//! so the operator can find out when a version went live and who published it
+//! with the `log` command.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use std::{env, fmt, path::Path};
+use time::{format_description, Date};
+
+/// What triggered a change of the catalog.
+#[derive(ValueEnum, sqlx::Type, Debug, Clone, Copy, Default, PartialEq)]
+#[sqlx(rename_all = "lowercase")]
+pub enum Source {
+    /// The `import` command.
+    #[default]
+    Import,
+
+    /// The `visibility` command.
+    Visibility,
+
+    /// The garbage collection, run by the `gc` command or periodically by the bot.
+    Gc,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Import => write!(f, "import"),
+            Source::Visibility => write!(f, "visibility"),
+            Source::Gc => write!(f, "gc"),
+        }
+    }
+}
+
+/// Who or what triggered a change of the catalog.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Actor {
+    /// What triggered the change.
+    pub source: Source,
+
+    /// Operating system user who ran the command, empty if unknown.
+    pub user: String,
+}
+
+impl Actor {
+    /// Returns the actor for a command run by the current operating system user.
+    pub fn cli(source: Source) -> Self {
+        let user = env::var("USER")
+            .or_else(|_| env::var("USERNAME"))
+            .unwrap_or_default();
+        Self { source, user }
+    }
+}
+
+impl fmt::Display for Actor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.user.is_empty() {
+            write!(f, "{}", self.source)
+        } else {
+            write!(f, "{} by {}", self.source, self.user)
+        }
+    }
+}
+
+/// Returns the hex encoded SHA-256 hash of a file.
+pub async fn file_hash(path: &Path) -> Result<String> {
+    Ok(format!(
+        "{:x}",
+        Sha256::digest(tokio::fs::read(path).await?)
+    ))
+}
+
+/// Parses a date given as `YYYY-MM-DD` on the command line.
+pub fn parse_date(date: &str) -> Result<Date> {
+    let format = format_description::parse("[year]-[month]-[day]")?;
+    Ok(Date::parse(date, &format)?)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use time::Month;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(
+            parse_date("2023-08-15").unwrap(),
+            Date::from_calendar_date(2023, Month::August, 15).unwrap()
+        );
+        assert!(parse_date("15.08.2023").is_err());
+    }
+
+    #[test]
+    fn test_actor_display() {
+        let actor = Actor {
+            source: Source::Import,
+            user: "alice".to_string(),
+        };
+        assert_eq!(actor.to_string(), "import by alice");
+        assert_eq!(Actor::default().to_string(), "import");
+    }
+}
@@ -1,6 +1,6 @@
 //! Entry for the bot code.
 
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
 use deltachat::{
     chat::{self, ChatId},
     config::Config,
@@ -8,25 +8,48 @@ use deltachat::{
     message::{Message, MsgId},
     securejoin,
     stock_str::StockStrings,
-    EventType, Events,
+    EventEmitter, EventType, Events,
 };
+use futures::future::join_all;
 use log::{debug, error, info, trace, warn};
 use qrcode_generator::QrCodeEcc;
 use serde::{Deserialize, Serialize};
 use sqlx::{pool::PoolConnection, FromRow, Sqlite, SqlitePool};
-use std::{fs, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{sync::watch, task::JoinHandle};
 
 use crate::{
+    audit::{Actor, Source},
     db::{self, MIGRATOR},
-    project_dirs,
-    request_handlers::{store, WebxdcStatusUpdate, WebxdcStatusUpdatePayload},
+    dispatch::Dispatcher,
+    gc::{self, RetentionPolicy},
+    invites,
+    metrics::Metrics,
+    profile,
+    request_handlers::{store, Compatibility, WebxdcStatusUpdate, WebxdcStatusUpdatePayload},
+    settings::Settings,
+    status, subscriptions,
     utils::{
-        configure_from_env, get_icon_path, get_store_xdc_path, get_webxdc_tag_name,
-        send_update_payload_only, unpack_assets, update_store,
+        configure_account, get_db_path, get_dc_db_path, get_icon_path, get_webxdc_tag_name,
+        get_xdcs_dir, send_update_payload_only, unpack_assets, update_store,
     },
     INVITE_QR, VERSION,
 };
 
+/// Maximum number of Delta Chat events handled at once.
+const EVENT_WORKERS: usize = 8;
+
+/// Maximum number of Delta Chat events dispatched but not handled yet.
+///
+/// When the queue is full, no more events are read until a handler finishes.
+const EVENT_QUEUE_SIZE: u32 = 256;
+
 /// Bot configuration.
 #[derive(FromRow, Serialize, Deserialize, PartialEq, Debug, Default)]
 pub struct BotConfig {
@@ -47,6 +70,32 @@ pub struct State {
 
     /// `tag_name` field from the `manifest.toml` of the `store.xdc`.
     pub store_tag_name: String,
+
+    /// Directory containing the bot data.
+    pub data_dir: PathBuf,
+
+    /// Settings from the configuration file.
+    pub settings: Settings,
+
+    /// Times of the recent download requests per chat,
+    /// used to enforce the download limits.
+    pub downloads: Mutex<HashMap<ChatId, Vec<Instant>>>,
+
+    /// Event counters.
+    pub metrics: Metrics,
+}
+
+impl State {
+    /// Retrieves a database connection from the pool,
+    /// recording the time spent waiting for it.
+    pub async fn db_connection(&self) -> sqlx::Result<PoolConnection<Sqlite>> {
+        let start = Instant::now();
+        let conn = self.db.acquire().await;
+        self.metrics
+            .db_pool_waits
+            .observe(start.elapsed().as_secs_f64());
+        conn
+    }
 }
 
 /// Store bot.
@@ -56,57 +105,47 @@ pub struct Bot {
 
     /// Reference to the bot state.
     state: Arc<State>,
+
+    /// Set to true to make the background tasks stop.
+    shutdown: watch::Sender<bool>,
+
+    /// Background tasks, i.e. the event loop and the scheduled jobs.
+    tasks: Vec<JoinHandle<()>>,
 }
 
 impl Bot {
-    /// Creates a new instance of the bot.
+    /// Creates a new instance of the bot using the Delta Chat account `context`
+    /// and storing its data in `data_dir`.
     /// Handles the configuration for dc and the bot itself.
-    pub async fn new() -> Result<Self> {
-        if std::env::var("XDCSTORE_KEEP_ASSETS")
-            .unwrap_or_default()
-            .is_empty()
-        {
-            unpack_assets().context("failed to unpack assets")?;
+    pub async fn new(context: Context, data_dir: &Path, settings: Settings) -> Result<Self> {
+        fs::create_dir_all(data_dir)?;
+        if !settings.keep_assets {
+            unpack_assets(data_dir).context("failed to unpack assets")?;
         }
 
-        let dirs = project_dirs()?;
-
-        std::fs::create_dir_all(dirs.config_dir())?;
-        let deltachat_db_file = dirs.config_dir().to_path_buf().join("deltachat.db");
-        let context = Context::new(
-            deltachat_db_file.as_path(),
-            1,
-            Events::new(),
-            StockStrings::new(),
-        )
-        .await
-        .context("Failed to create context")?;
-
         if !context.get_config_bool(Config::Configured).await? {
             info!("DC: Start configuring...");
-            configure_from_env(&context).await?;
+            configure_account(&context, &settings.account).await?;
             info!("DC: Configuration done");
         }
 
-        let bot_db_file = dirs.config_dir().to_path_buf().join("bot.db");
-        if !bot_db_file.exists() {
-            fs::write(&bot_db_file, "")?;
+        let db = Self::open_db(data_dir).await?;
+        let moved = db::move_inline_icons(&mut *db.acquire().await?).await?;
+        if moved > 0 {
+            info!("Moved {moved} app icons to the icons table");
         }
-        let bot_db_url = format!("sqlite://{}", bot_db_file.display());
-        let db = SqlitePool::connect(&bot_db_url)
-            .await
-            .with_context(|| format!("connect to database pool {bot_db_url:?}"))?;
-        MIGRATOR.run(&db).await?;
 
         let config = match db::get_config(&mut *db.acquire().await?).await {
             Ok(config) => config,
             Err(_) => {
                 info!("Bot hasn't been configured yet, start configuring...");
-                let config = Self::setup(&context).await.context("Failed to setup bot")?;
+                let config = Self::setup(&context, data_dir, &settings)
+                    .await
+                    .context("Failed to setup bot")?;
                 db::set_config(&mut *db.acquire().await?, &config).await?;
 
                 // Save QR code to disk.
-                let dest_path = dirs.config_dir().to_path_buf().join(INVITE_QR);
+                let dest_path = data_dir.join(INVITE_QR);
                 qrcode_generator::to_png_to_file(
                     &config.invite_qr,
                     QrCodeEcc::Low,
@@ -119,34 +158,80 @@ impl Bot {
             }
         };
 
-        let store_xdc_path = get_store_xdc_path()?;
-        let store_tag_name = get_webxdc_tag_name(&store_xdc_path).await?;
+        let conn = &mut *db.acquire().await?;
+        let current_settings = serde_json::to_string(&settings)?;
+        let changed = match db::get_settings(conn).await? {
+            Some(stored) => {
+                let changed = settings.changed_since(&stored)?;
+                if !changed.is_empty() {
+                    info!("Settings changed: {}", changed.join(", "));
+                }
+                Some(changed)
+            }
+            None => None,
+        };
+        profile::apply_profile(&context, conn, &settings, changed.as_deref())
+            .await
+            .context("failed to apply profile settings")?;
+        db::set_settings(conn, &current_settings).await?;
+
+        let store_tag_name = get_webxdc_tag_name(&settings.store_xdc)
+            .await
+            .with_context(|| format!("failed to read {}", settings.store_xdc.display()))?;
         info!("Store tag_name: {store_tag_name}");
-        info!("Store frontend location: {}", store_xdc_path.display());
+        info!("Store frontend location: {}", settings.store_xdc.display());
 
         Ok(Self {
+            shutdown: watch::channel(false).0,
+            tasks: Vec::new(),
             dc_ctx: context,
             state: Arc::new(State {
                 db,
                 config,
                 store_tag_name,
+                data_dir: data_dir.to_path_buf(),
+                settings,
+                downloads: Mutex::default(),
+                metrics: Metrics::default(),
             }),
         })
     }
 
-    /// Sets avatar and creates a QR code.
+    /// Opens the bot database in `data_dir`, creating it if it does not exist,
+    /// and applies the migrations.
+    pub async fn open_db(data_dir: &Path) -> Result<SqlitePool> {
+        let bot_db_file = get_db_path(data_dir);
+        if !bot_db_file.exists() {
+            fs::write(&bot_db_file, "")?;
+        }
+        let bot_db_url = format!("sqlite://{}", bot_db_file.display());
+        let db = SqlitePool::connect(&bot_db_url)
+            .await
+            .with_context(|| format!("connect to database pool {bot_db_url:?}"))?;
+        MIGRATOR.run(&db).await?;
+        Ok(db)
+    }
+
+    /// Opens the Delta Chat account stored in `data_dir`.
+    pub async fn create_context(data_dir: &Path) -> Result<Context> {
+        std::fs::create_dir_all(data_dir)?;
+        let deltachat_db_file = get_dc_db_path(data_dir);
+        Context::new(
+            deltachat_db_file.as_path(),
+            1,
+            Events::new(),
+            StockStrings::new(),
+        )
+        .await
+        .context("Failed to create context")
+    }
+
+    /// Sets the bundled avatar unless an avatar is configured and creates a QR code.
     /// Returns the complete bot config.
-    async fn setup(context: &Context) -> Result<BotConfig> {
-        context
-            .set_config(
-                Config::Selfavatar,
-                Some(
-                    get_icon_path()?
-                        .to_str()
-                        .context("Can't convert image file")?,
-                ),
-            )
-            .await?;
+    async fn setup(context: &Context, data_dir: &Path, settings: &Settings) -> Result<BotConfig> {
+        if settings.avatar.is_none() {
+            profile::set_avatar(context, &get_icon_path(data_dir)).await?;
+        }
 
         let invite_qr = securejoin::get_securejoin_qr(context, None).await?;
 
@@ -158,19 +243,171 @@ impl Bot {
 
     /// Start the bot.
     pub async fn start(&mut self) {
-        let events_emitter = self.dc_ctx.get_event_emitter();
+        let accounts = HashMap::from([(self.account_id(), self.event_target())]);
+        let event_loop = spawn_event_loop(
+            self.dc_ctx.get_event_emitter(),
+            accounts,
+            self.shutdown.subscribe(),
+        );
+        self.tasks.push(event_loop);
+        self.dc_ctx.start_io().await;
+        self.schedule_notifications();
+        self.schedule_connectivity_reports();
+        info!("Successfully started bot! 🥳");
+    }
+
+    /// Returns the ID of the Delta Chat account of the bot,
+    /// used to route the events of an account manager.
+    pub fn account_id(&self) -> u32 {
+        self.dc_ctx.get_id()
+    }
+
+    /// Returns the Delta Chat account of the bot.
+    pub fn context(&self) -> &Context {
+        &self.dc_ctx
+    }
+
+    /// Returns the account and the state the events of the bot are handled with.
+    pub fn event_target(&self) -> (Context, Arc<State>) {
+        (self.dc_ctx.clone(), self.state.clone())
+    }
+
+    /// Stops the bot.
+    ///
+    /// No new events are handled, the running handlers and jobs get `timeout` to finish.
+    /// Then the IO is stopped and the database is closed.
+    /// Returns an error if the handlers did not finish in time.
+    pub async fn stop(self, timeout: Duration) -> Result<()> {
+        info!("Stopping bot...");
+        self.shutdown.send_replace(true);
+        let tasks = self
+            .tasks
+            .iter()
+            .map(|task| task.abort_handle())
+            .collect::<Vec<_>>();
+        let finished = tokio::time::timeout(timeout, join_all(self.tasks))
+            .await
+            .is_ok();
+        if !finished {
+            warn!("Handlers did not finish within {timeout:?}, aborting them");
+            for task in tasks {
+                task.abort();
+            }
+        }
+        self.dc_ctx.stop_io().await;
+        self.state.db.close().await;
+        if let Err(e) = status::clear_connectivity(&self.state.data_dir).await {
+            warn!("Failed to clear the connectivity report: {e:#}");
+        }
+        if !finished {
+            bail!("Bot was stopped before all handlers finished");
+        }
+        info!("Bot stopped");
+        Ok(())
+    }
+
+    /// Periodically notifies subscribers about imported app versions.
+    pub fn schedule_notifications(&mut self) {
         let ctx = self.dc_ctx.clone();
         let state = self.state.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        self.tasks.push(tokio::spawn(async move {
+            let mut timer = tokio::time::interval(subscriptions::NOTIFY_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = timer.tick() => {}
+                    _ = shutdown.changed() => break,
+                }
+                if let Err(e) = subscriptions::notify_subscribers(&ctx, &state).await {
+                    warn!("Failed to notify subscribers: {e:#}");
+                }
+            }
+        }));
+    }
+
+    /// Periodically refreshes the connectivity report,
+    /// so the `status` command can tell a running bot from a crashed one.
+    fn schedule_connectivity_reports(&mut self) {
+        let ctx = self.dc_ctx.clone();
+        let data_dir = self.state.data_dir.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        self.tasks.push(tokio::spawn(async move {
+            let mut timer = tokio::time::interval(status::CONNECTIVITY_REPORT_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = timer.tick() => {}
+                    _ = shutdown.changed() => break,
+                }
+                let connectivity = ctx.get_connectivity().await;
+                if let Err(e) = status::report_connectivity(&data_dir, connectivity).await {
+                    warn!("Failed to report the connectivity: {e:#}");
+                }
+            }
+        }));
+    }
 
-        tokio::spawn(async move {
-            while let Some(event) = events_emitter.recv().await {
-                if let Err(e) = Self::dc_event_handler(&ctx, state.clone(), event.typ).await {
-                    warn!("{}", e)
+    /// Runs the garbage collection with the given retention policy
+    /// every `interval`, starting immediately.
+    pub fn schedule_gc(&mut self, policy: RetentionPolicy, interval: Duration) {
+        let state = self.state.clone();
+        let xdcs_dir = get_xdcs_dir(&self.state.data_dir);
+        let mut shutdown = self.shutdown.subscribe();
+        self.tasks.push(tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = timer.tick() => {}
+                    _ = shutdown.changed() => break,
+                }
+                let res = async {
+                    let conn = &mut *state.db_connection().await?;
+                    let actor = Actor {
+                        source: Source::Gc,
+                        user: String::new(),
+                    };
+                    gc::collect_garbage(conn, &xdcs_dir, &policy, &actor, gc::BLOB_GRACE_PERIOD)
+                        .await
+                }
+                .await;
+                match res {
+                    Ok(report) => info!(
+                        "Garbage collection removed {} app versions and {} files",
+                        report.removed_versions,
+                        report.removed_blobs.len()
+                    ),
+                    Err(e) => warn!("Garbage collection failed: {e:#}"),
                 }
             }
-        });
-        self.dc_ctx.start_io().await;
-        info!("Successfully started bot! 🥳");
+        }));
+    }
+
+    /// Returns the chat whose events must be handled in order with `event`,
+    /// `None` if the event can be handled right away.
+    async fn event_chat(context: &Context, event: &EventType) -> Result<Option<ChatId>> {
+        Ok(match event {
+            EventType::IncomingMsg { chat_id, .. } => Some(*chat_id),
+            EventType::WebxdcStatusUpdate { msg_id, .. } => {
+                Some(Message::load_from_db(context, *msg_id).await?.get_chat_id())
+            }
+            // The store is sent to the 1:1 chat, see [store::handle_joined].
+            EventType::SecurejoinInviterProgress {
+                contact_id,
+                progress: 1000,
+            } => Some(ChatId::create_for_contact(context, *contact_id).await?),
+            _ => None,
+        })
+    }
+
+    /// Handles a dc-event, logging errors and recording the time it took.
+    async fn handle_event(context: Context, state: Arc<State>, event: EventType) {
+        let start = Instant::now();
+        if let Err(e) = Self::dc_event_handler(&context, state.clone(), event).await {
+            warn!("{}", e)
+        }
+        state
+            .metrics
+            .event_handling
+            .observe(start.elapsed().as_secs_f64());
     }
 
     /// Handle dc-events.
@@ -186,10 +423,32 @@ impl Bot {
             EventType::Info(msg) => trace!("DC: {msg}"),
             EventType::Warning(msg) => warn!("DC: {msg}"),
             EventType::Error(msg) => error!("DC: {msg}"),
-            EventType::ConnectivityChanged => trace!("DC: ConnectivityChanged"),
+            EventType::ConnectivityChanged => {
+                trace!("DC: ConnectivityChanged");
+                status::report_connectivity(&state.data_dir, context.get_connectivity().await)
+                    .await?
+            }
             EventType::IncomingMsg { chat_id, msg_id } => {
+                state.metrics.incoming_messages.inc();
                 Self::handle_dc_message(context, state, chat_id, msg_id).await?
             }
+            EventType::SecurejoinInviterProgress {
+                contact_id,
+                progress,
+            } => {
+                // 1000 means the SecureJoin protocol completed successfully.
+                if progress == 1000 {
+                    let invite = match invites::record_join(context, &state, contact_id).await {
+                        Ok(invite) => invite,
+                        Err(err) => {
+                            warn!("Failed to record the invite of {contact_id}: {err:#}");
+                            None
+                        }
+                    };
+                    let app_id = invite.and_then(|invite| invite.app_id);
+                    store::handle_joined(context, &state, contact_id, app_id.as_deref()).await?
+                }
+            }
             EventType::WebxdcStatusUpdate {
                 msg_id,
                 status_update_serial,
@@ -221,7 +480,7 @@ impl Bot {
         }
 
         info!("Handling message {msg_id}.");
-        store::handle_message(context, state, chat_id).await?;
+        store::handle_message(context, state, &msg).await?;
 
         Ok(())
     }
@@ -233,12 +492,18 @@ impl Bot {
         msg_id: MsgId,
         update: String,
     ) -> Result<()> {
-        let msg = Message::load_from_db(context, msg_id).await?;
-        let chat_id = msg.get_chat_id();
-        let conn = &mut *state.db.acquire().await?;
-        let store_tag_name = db::get_store_tag_name(conn, msg.get_id()).await?;
+        let Some(store_tag_name) =
+            db::get_store_tag_name(&mut *state.db_connection().await?, msg_id).await?
+        else {
+            Self::handle_foreign_webxdc_update(&state, msg_id);
+            return Ok(());
+        };
+        let chat_id = Message::load_from_db(context, msg_id).await?.get_chat_id();
 
-        let Ok(request) = serde_json::from_str::<WebxdcStatusUpdate>(&update) else {
+        let Ok(WebxdcStatusUpdate { payload: request }) =
+            serde_json::from_str::<WebxdcStatusUpdate>(&update)
+        else {
+            state.metrics.invalid_webxdc_updates.inc();
             info!(
                 "Ignoring WebXDC update: {}",
                 &update.get(..100.min(update.len())).unwrap_or_default()
@@ -246,6 +511,15 @@ impl Bot {
             return Ok(());
         };
 
+        // Responses sent by the bot itself.
+        if !request.payload.is_request() {
+            return Ok(());
+        }
+        state
+            .metrics
+            .status_updates
+            .increment(request.payload.name());
+
         if let WebxdcStatusUpdatePayload::UpdateWebxdc { serial } = request.payload {
             send_update_payload_only(context, msg_id, WebxdcStatusUpdatePayload::UpdateSent)
                 .await?;
@@ -253,14 +527,34 @@ impl Bot {
             return Ok(());
         }
 
-        if store_tag_name != state.store_tag_name {
-            info!(
-                "Store xdc frontend's tag_name changed from {} to {}, triggering update",
-                store_tag_name, state.store_tag_name
-            );
-
-            // Only try to upgrade version, if the webxdc event is _not_ an update response already
-            if !matches!(request.payload, WebxdcStatusUpdatePayload::Outdated { .. }) {
+        match Compatibility::of(request.protocol, &store_tag_name, &state.store_tag_name) {
+            Compatibility::Current => {}
+            Compatibility::Compatible => {
+                // Suggest the upgrade once per index update instead of on every request.
+                if matches!(
+                    request.payload,
+                    WebxdcStatusUpdatePayload::UpdateRequest { .. }
+                ) {
+                    info!(
+                        "Suggesting upgrade of store xdc frontend {store_tag_name} with protocol {} to {}",
+                        request.protocol, state.store_tag_name
+                    );
+                    send_update_payload_only(
+                        context,
+                        msg_id,
+                        WebxdcStatusUpdatePayload::Outdated {
+                            tag_name: state.store_tag_name.clone(),
+                            critical: false,
+                        },
+                    )
+                    .await?;
+                }
+            }
+            Compatibility::Incompatible => {
+                info!(
+                    "Store xdc frontend {store_tag_name} with protocol {} is incompatible, triggering update to {}",
+                    request.protocol, state.store_tag_name
+                );
                 send_update_payload_only(
                     context,
                     msg_id,
@@ -270,8 +564,8 @@ impl Bot {
                     },
                 )
                 .await?;
+                return Ok(());
             }
-            return Ok(());
         }
 
         store::handle_status_update(context, state, msg_id, request.payload).await?;
@@ -279,8 +573,71 @@ impl Bot {
         Ok(())
     }
 
+    /// Handles webxdc updates from instances which were not sent by the store,
+    /// e.g. apps sent to the bot or apps sent to subscribers.
+    ///
+    /// The bot does not take part in these apps, so the updates are only counted.
+    fn handle_foreign_webxdc_update(state: &State, msg_id: MsgId) {
+        debug!("Ignoring WebXDC update of non-store instance {msg_id}");
+        state.metrics.foreign_webxdc_updates.inc();
+    }
+
+    /// Returns the bot state, e.g. to export the metrics.
+    pub fn state(&self) -> Arc<State> {
+        self.state.clone()
+    }
+
     /// Retrieves a database connection from the pool.
     pub async fn get_db_connection(&self) -> sqlx::Result<PoolConnection<Sqlite>> {
         self.state.db.acquire().await
     }
 }
+
+/// Spawns the loop handling the events of `events` until `shutdown` changes.
+///
+/// Events are routed to the account and the bot state by the account ID,
+/// so one loop can handle the events of all accounts of an account manager.
+pub fn spawn_event_loop(
+    events: EventEmitter,
+    accounts: HashMap<u32, (Context, Arc<State>)>,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let dispatcher = Dispatcher::new(EVENT_WORKERS, EVENT_QUEUE_SIZE);
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = shutdown.changed() => None,
+            };
+            let Some(event) = event else {
+                break;
+            };
+            let Some((ctx, state)) = accounts.get(&event.id) else {
+                debug!("Ignoring event of unknown account {}", event.id);
+                continue;
+            };
+            let chat_id = match Bot::event_chat(ctx, &event.typ).await {
+                Ok(chat_id) => chat_id,
+                Err(e) => {
+                    warn!("Failed to find the chat of an event: {e:#}");
+                    None
+                }
+            };
+            let handler = Bot::handle_event(ctx.clone(), state.clone(), event.typ);
+            match chat_id {
+                Some(chat_id) => {
+                    let queued = state.metrics.queued_events.clone();
+                    if let Err(e) = dispatcher
+                        .dispatch((event.id, chat_id), queued, handler)
+                        .await
+                    {
+                        warn!("Failed to dispatch event: {e:#}");
+                    }
+                }
+                None => handler.await,
+            }
+        }
+        // Dispatched events are still handled, so no request is left unanswered.
+        dispatcher.drain().await;
+    })
+}
@@ -1,11 +1,36 @@
 //! Command line interface.
 
 use clap::{Parser, Subcommand};
+use std::{net::SocketAddr, path::PathBuf};
+use time::Date;
+
+use crate::{
+    audit::{parse_date, Source},
+    gc::RetentionPolicy,
+    invites::QrFormat,
+    request_handlers::Channel,
+    settings::AccountSettings,
+};
 
 /// Command line argument parser.
 #[derive(Parser, Debug)]
 #[command()]
 pub struct BotCli {
+    /// Directory where the bot stores its data.
+    /// Defaults to the platform-specific configuration directory.
+    #[arg(long, global = true, env = "XDCSTORE_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Path to the configuration file.
+    /// Defaults to `config.toml` in the data directory.
+    #[arg(long, global = true, env = "XDCSTORE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Name of the store to use if several stores are configured.
+    /// The `start` command starts all stores if no store is selected.
+    #[arg(long, global = true, env = "XDCSTORE_STORE")]
+    pub store: Option<String>,
+
     #[allow(clippy::missing_docs_in_private_items)]
     #[command(subcommand)]
     pub action: BotActions,
@@ -15,14 +40,222 @@ pub struct BotCli {
 #[derive(Subcommand, Debug)]
 pub enum BotActions {
     /// Start the bot.
-    Start,
+    Start {
+        #[allow(clippy::missing_docs_in_private_items)]
+        #[command(flatten)]
+        retention: RetentionPolicy,
+
+        /// Run the garbage collection every given number of hours.
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        gc_interval_hours: Option<u64>,
+
+        /// Serve the metrics in the Prometheus text format at `http://<addr>/metrics`.
+        #[arg(long)]
+        metrics_addr: Option<SocketAddr>,
+    },
     /// Import xdcs.
     Import {
         /// Path from which files should be imported.
         path: String,
+
+        /// Release channel of the imported versions.
+        #[arg(long, value_enum, default_value_t)]
+        channel: Channel,
+    },
+    /// Configure or reconfigure the bot account and check the connection.
+    ///
+    /// Options override the `[account]` section of the configuration file.
+    Configure {
+        #[allow(clippy::missing_docs_in_private_items)]
+        #[command(flatten)]
+        account: AccountSettings,
+    },
+    /// Show and change the display name, status text and avatar of the bot.
+    ///
+    /// Values from the configuration file are applied again
+    /// when they are changed in the configuration file.
+    Profile {
+        /// New display name.
+        #[arg(long)]
+        name: Option<String>,
+
+        /// New status text.
+        #[arg(long)]
+        status: Option<String>,
+
+        /// Path to the new avatar image.
+        #[arg(long)]
+        avatar: Option<PathBuf>,
+    },
+    /// Remove old app versions and unreferenced .xdc files.
+    Gc {
+        #[allow(clippy::missing_docs_in_private_items)]
+        #[command(flatten)]
+        retention: RetentionPolicy,
+    },
+    /// Show which app versions are installed by the users of the store.
+    ///
+    /// Without an app, shows the number of installs of each app version.
+    Installs {
+        /// Show the users who installed this app.
+        app_id: Option<String>,
+
+        /// Only show users who did not install the newest version on their release channel.
+        #[arg(long, requires = "app_id")]
+        outdated: bool,
+    },
+    /// Show or change who can see an app.
+    ///
+    /// Apps are public by default.
+    /// Without an app, shows all apps which are not public.
+    Visibility {
+        /// ID of the app.
+        app_id: Option<String>,
+
+        /// Make the app visible to everyone.
+        #[arg(long, requires = "app_id", conflicts_with_all = ["contacts", "group"])]
+        public: bool,
+
+        /// Only show the app to the contacts with the given comma-separated addresses.
+        #[arg(
+            long,
+            requires = "app_id",
+            value_delimiter = ',',
+            conflicts_with = "group"
+        )]
+        contacts: Vec<String>,
+
+        /// Only show the app to the members of the group chat with the given ID.
+        #[arg(long, requires = "app_id")]
+        group: Option<u32>,
+    },
+    /// Show or change featured apps, priorities and collections.
+    Curate {
+        #[allow(clippy::missing_docs_in_private_items)]
+        #[command(subcommand)]
+        action: Option<CurateActions>,
+    },
+    /// Manage named invite QR codes and show who joined through an invite.
+    Invite {
+        #[allow(clippy::missing_docs_in_private_items)]
+        #[command(subcommand)]
+        action: Option<InviteActions>,
+    },
+    /// Show the audit log of the changes of the app catalog.
+    Log {
+        /// Only show the changes of this app and of the whole index.
+        app_id: Option<String>,
+
+        /// Only show changes on or after this date, e.g. `2023-08-01`.
+        #[arg(long, value_parser = parse_date)]
+        since: Option<Date>,
+
+        /// Only show changes before this date.
+        #[arg(long, value_parser = parse_date)]
+        until: Option<Date>,
+
+        /// Only show changes triggered by this source.
+        #[arg(long, value_enum)]
+        source: Option<Source>,
+    },
+    /// Show the state of the store and exit with an error if a problem is found.
+    ///
+    /// Suitable as a health check of a container or service manager.
+    Status {
+        /// Also report a problem if the bot is not running
+        /// or not connected to the mail server.
+        #[arg(long)]
+        check_connectivity: bool,
     },
     /// Show the 1:1-invite QR code.
     ShowQr,
     /// Show the bots version.
     Version,
 }
+
+/// Subcommands of the `curate` command.
+///
+/// Without a subcommand, the current curation is shown.
+#[derive(Subcommand, Debug)]
+pub enum CurateActions {
+    /// Change the featured flag and the ordering priority of an app.
+    App {
+        /// ID of the app.
+        app_id: String,
+
+        /// Feature the app or stop featuring it.
+        #[arg(long)]
+        featured: Option<bool>,
+
+        /// Ordering priority, apps with a higher priority are shown first.
+        #[arg(long, allow_negative_numbers = true)]
+        priority: Option<i32>,
+    },
+    /// Create, change or delete a collection of apps.
+    Collection {
+        /// Name of the collection, e.g. `Games`.
+        name: String,
+
+        /// Append the apps with the given comma-separated IDs.
+        #[arg(long, value_delimiter = ',', conflicts_with = "delete")]
+        add: Vec<String>,
+
+        /// Remove the apps with the given comma-separated IDs.
+        #[arg(long, value_delimiter = ',', conflicts_with = "delete")]
+        remove: Vec<String>,
+
+        /// Position of the collection, collections are shown in ascending order.
+        #[arg(long, allow_negative_numbers = true, conflicts_with = "delete")]
+        position: Option<i32>,
+
+        /// Delete the collection.
+        #[arg(long)]
+        delete: bool,
+    },
+}
+
+/// Subcommands of the `invite` command.
+///
+/// Without a subcommand, all invites with the number of joins are shown.
+#[derive(Subcommand, Debug)]
+pub enum InviteActions {
+    /// Create a named invite, e.g. for a campaign or partner.
+    Create {
+        /// Name of the invite.
+        name: String,
+
+        /// Send this app to contacts joining through the invite,
+        /// e.g. for QR codes on posters leading to a particular app.
+        #[arg(long)]
+        app: Option<String>,
+    },
+    /// Replace the QR code of an invite, previously exported codes stop working.
+    Rotate {
+        /// Name of the invite.
+        name: String,
+    },
+    /// Revoke an invite, contacts can no longer join through it.
+    Revoke {
+        /// Name of the invite.
+        name: String,
+    },
+    /// Export the QR code of an invite.
+    Export {
+        /// Name of the invite.
+        name: String,
+
+        /// Format of the exported QR code.
+        #[arg(long, value_enum, default_value_t)]
+        format: QrFormat,
+
+        /// Destination file.
+        /// Defaults to `invite_<name>.<format>` in the data directory.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Show the contacts who joined through an invite.
+    Joins {
+        /// Name of the invite.
+        name: String,
+    },
+}
@@ -0,0 +1,90 @@
+//! Featured apps, ordering priorities and curated collections.
+//!
+//! The curation is managed by the operator with the `curate` command
+//! and stored independently of the app versions,
+//! so it survives imports and can be changed without re-importing apps.
+//! It is sent in full with every `Init` and `Update` response.
+
+use anyhow::Result;
+use sqlx::SqliteConnection;
+
+use crate::{
+    db,
+    request_handlers::{Collection, Curation},
+    visibility::Viewer,
+};
+
+/// Returns the curation of the store with the apps not visible to the viewer removed.
+pub async fn get_curation(conn: &mut SqliteConnection, viewer: &Viewer) -> Result<Curation> {
+    let mut curation = Curation::default();
+    for app in db::get_app_curations(conn).await? {
+        if !viewer.can_see(&app.app_id) {
+            continue;
+        }
+        if app.featured {
+            curation.featured.push(app.app_id.clone());
+        }
+        if app.priority != 0 {
+            curation.priorities.insert(app.app_id, app.priority);
+        }
+    }
+    curation.collections = db::get_collections(conn)
+        .await?
+        .into_iter()
+        .map(|collection| Collection {
+            app_ids: collection
+                .app_ids
+                .into_iter()
+                .filter(|app_id| viewer.can_see(app_id))
+                .collect(),
+            ..collection
+        })
+        .collect();
+    Ok(curation)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::{bot::BotConfig, db::MIGRATOR};
+    use sqlx::Connection;
+
+    #[tokio::test]
+    async fn test_get_curation() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        db::set_config(&mut conn, &BotConfig::default())
+            .await
+            .unwrap();
+
+        db::set_app_curation(&mut conn, "poll", Some(true), Some(2))
+            .await
+            .unwrap();
+        db::set_app_curation(&mut conn, "secret", Some(true), Some(3))
+            .await
+            .unwrap();
+        db::add_to_collection(
+            &mut conn,
+            "Essentials",
+            &["secret".to_string(), "poll".to_string()],
+        )
+        .await
+        .unwrap();
+
+        let viewer = Viewer {
+            hidden: ["secret".to_string()].into(),
+            ..Default::default()
+        };
+        let curation = get_curation(&mut conn, &viewer).await.unwrap();
+        assert_eq!(curation.featured, vec!["poll"]);
+        assert_eq!(curation.priorities, [("poll".to_string(), 2)].into());
+        assert_eq!(
+            curation.collections,
+            vec![Collection {
+                name: "Essentials".to_string(),
+                app_ids: vec!["poll".to_string()]
+            }]
+        );
+    }
+}
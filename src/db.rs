@@ -2,15 +2,34 @@
 //! It consists of these tables:
 //! - app_infos (Stores the app infos)
 //! - config (Where config is stored)
+//! - group_chats (Group chats the store was posted to)
+//! - subscriptions (Apps contacts get notified about)
+//! - installed_apps (App versions installed by contacts)
+//! - contact_channels (Release channels of the contacts)
+//! - app_visibility (Apps restricted to selected contacts)
+//! - app_curation (Featured flags and priorities of apps)
+//! - collections, collection_apps (Curated collections of apps)
+//! - icons (App icons by content hash)
+//! - screenshots (Screenshots of app versions)
+//! - pending_welcomes (Contacts sent the store after joining)
+//! - invites, invite_joins (Named invite QR codes and the contacts who joined through an invite)
+//! - audit_log (Changes of the app catalog)
 //!
 //! See migrations folder for further details.
 
-use crate::{bot::BotConfig, request_handlers::AppInfo};
-use anyhow::Result;
-use deltachat::message::MsgId;
+use crate::{
+    audit::{self, Actor, Source},
+    bot::BotConfig,
+    icons::icon_hash,
+    request_handlers::{AppInfo, Channel, Collection},
+    visibility::Visibility,
+};
+use anyhow::{Context as _, Result};
+use deltachat::{chat::ChatId, contact::ContactId, message::MsgId};
 use itertools::Itertools;
 use sqlx::{migrate::Migrator, Connection, FromRow, Row, SqliteConnection};
 use std::path::PathBuf;
+use time::OffsetDateTime;
 
 #[allow(clippy::missing_docs_in_private_items)]
 pub static MIGRATOR: Migrator = sqlx::migrate!();
@@ -33,9 +52,8 @@ pub struct DBAppInfo {
     /// Source code URL, e.g. `https://codeberg.org/webxdc/checklist`.
     pub source_code_url: String,
 
-    /// Application icon encoded as a data URL,
-    /// for example `data:image/png;base64,...`.
-    pub image: String,
+    /// Hash of the application icon, see [get_icons].
+    pub icon: String,
 
     /// Human-readable application description.
     pub description: String,
@@ -51,6 +69,9 @@ pub struct DBAppInfo {
 
     /// True if the application has been removed.
     pub removed: bool,
+
+    /// Release channel of this version.
+    pub channel: Channel,
 }
 
 impl From<DBAppInfo> for AppInfo {
@@ -61,12 +82,15 @@ impl From<DBAppInfo> for AppInfo {
             name: db_app.name,
             date: db_app.date,
             source_code_url: db_app.source_code_url,
-            image: db_app.image,
+            icon: db_app.icon,
+            image: String::new(),
+            screenshots: Vec::new(),
             description: db_app.description,
             xdc_blob_path: PathBuf::from(db_app.xdc_blob_path),
             size: db_app.size,
             tag_name: db_app.tag_name,
             removed: db_app.removed,
+            channel: db_app.channel,
         }
     }
 }
@@ -74,6 +98,133 @@ impl From<DBAppInfo> for AppInfo {
 #[allow(clippy::missing_docs_in_private_items)]
 pub type RecordId = i32;
 
+/// App version installed by a contact.
+#[derive(FromRow, Debug, PartialEq)]
+pub struct InstalledApp {
+    /// ID of the contact.
+    pub contact_id: u32,
+
+    /// Address of the contact.
+    pub addr: String,
+
+    /// Application ID, e.g. `webxdc-poll`.
+    pub app_id: String,
+
+    /// Installed release tag, e.g. `v2.2.0`.
+    pub tag_name: String,
+
+    /// Timestamp in seconds of the last update request reporting the app.
+    pub last_seen: i64,
+}
+
+/// Number of contacts with a version of an app installed.
+#[derive(FromRow, Debug, PartialEq)]
+pub struct VersionAdoption {
+    /// Application ID, e.g. `webxdc-poll`.
+    pub app_id: String,
+
+    /// Release tag, e.g. `v2.2.0`.
+    pub tag_name: String,
+
+    /// Number of contacts with this version installed.
+    pub installs: i64,
+
+    /// Timestamp in seconds the version was reported last.
+    pub last_seen: i64,
+}
+
+/// Group chat the store was posted to.
+#[derive(FromRow, Debug, PartialEq)]
+pub struct GroupChat {
+    /// ID of the group chat.
+    pub chat_id: u32,
+
+    /// Address of the member who added the bot to the group.
+    pub admin_addr: String,
+
+    /// True if apps can't be downloaded in the group.
+    pub downloads_disabled: bool,
+}
+
+/// Featured flag and priority of an app.
+#[derive(FromRow, Debug, PartialEq)]
+pub struct AppCuration {
+    /// Application ID, e.g. `webxdc-poll`.
+    pub app_id: String,
+
+    /// True if the app is featured.
+    pub featured: bool,
+
+    /// Ordering priority, apps with a higher priority are shown first.
+    pub priority: i32,
+}
+
+/// Named invite QR code.
+#[derive(FromRow, Debug, PartialEq)]
+pub struct Invite {
+    /// Name of the invite, e.g. `conference`.
+    pub name: String,
+
+    /// Group the QR code of the invite joins.
+    pub chat_id: u32,
+
+    /// Timestamp in seconds the invite was created.
+    pub created: i64,
+
+    /// True if the invite was revoked.
+    pub revoked: bool,
+
+    /// ID of the app sent to contacts joining through the invite.
+    pub app_id: Option<String>,
+}
+
+/// Contact who completed a SecureJoin with the bot.
+#[derive(FromRow, Debug, PartialEq)]
+pub struct InviteJoin {
+    /// Address of the contact.
+    pub addr: String,
+
+    /// Name of the invite or `None` for the default invite.
+    pub invite: Option<String>,
+
+    /// Timestamp in seconds of the join.
+    pub joined: i64,
+}
+
+/// Change of the app catalog.
+#[derive(FromRow, Debug, PartialEq)]
+pub struct AuditRecord {
+    /// Timestamp in seconds of the change.
+    pub time: i64,
+
+    /// Kind of change, `create`, `remove` or `reset`.
+    pub action: String,
+
+    /// What triggered the change.
+    pub source: Source,
+
+    /// Operating system user who triggered the change, empty if unknown.
+    pub user: String,
+
+    /// Application ID, `None` for changes of the whole index.
+    pub app_id: Option<String>,
+
+    /// Release channel of the changed version.
+    pub channel: Option<Channel>,
+
+    /// Newest release tag on the channel before the change.
+    pub previous_tag_name: Option<String>,
+
+    /// Release tag of the created version.
+    pub tag_name: Option<String>,
+
+    /// SHA-256 hash of the .xdc file of the created version.
+    pub file_hash: Option<String>,
+
+    /// Serial of the index after the change.
+    pub serial: u32,
+}
+
 /// Stores the bot configuration into the `config` table of the bot database.
 pub async fn set_config(c: &mut SqliteConnection, config: &BotConfig) -> Result<()> {
     sqlx::query("INSERT INTO config (invite_qr, serial) VALUES (?, ?)")
@@ -92,6 +243,40 @@ pub async fn get_config(c: &mut SqliteConnection) -> Result<BotConfig> {
     Ok(res)
 }
 
+/// Returns the serialized settings stored by [set_settings], if any.
+pub async fn get_settings(c: &mut SqliteConnection) -> sqlx::Result<Option<String>> {
+    sqlx::query("SELECT settings FROM config")
+        .fetch_one(c)
+        .await
+        .map(|row| row.get("settings"))
+}
+
+/// Stores the serialized settings applied by the bot.
+pub async fn set_settings(c: &mut SqliteConnection, settings: &str) -> sqlx::Result<()> {
+    sqlx::query("UPDATE config SET settings = ?")
+        .bind(settings)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Returns the hash of the avatar file applied from the settings, if any.
+pub async fn get_avatar_hash(c: &mut SqliteConnection) -> sqlx::Result<Option<String>> {
+    sqlx::query("SELECT avatar_hash FROM config")
+        .fetch_one(c)
+        .await
+        .map(|row| row.get("avatar_hash"))
+}
+
+/// Stores the hash of the avatar file applied from the settings.
+pub async fn set_avatar_hash(c: &mut SqliteConnection, hash: &str) -> sqlx::Result<()> {
+    sqlx::query("UPDATE config SET avatar_hash = ?")
+        .bind(hash)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
 /// Returns the latest store serial.
 pub async fn get_last_serial(c: &mut SqliteConnection) -> sqlx::Result<u32> {
     sqlx::query("SELECT serial FROM config")
@@ -120,58 +305,111 @@ pub async fn increase_get_serial(c: &mut SqliteConnection) -> sqlx::Result<u32>
 }
 
 /// Create [AppInfo].
-pub async fn create_app_info(c: &mut SqliteConnection, app_info: &mut AppInfo) -> Result<()> {
+///
+/// The icon in [AppInfo::image] is moved to the icons table
+/// and [AppInfo::icon] is set to its hash.
+/// [AppInfo::screenshots] are moved to the screenshots table.
+/// The change is recorded in the audit log
+/// together with the hash of the .xdc file, which must exist.
+pub async fn create_app_info(
+    c: &mut SqliteConnection,
+    app_info: &mut AppInfo,
+    actor: &Actor,
+) -> Result<()> {
+    let file_hash = audit::file_hash(&app_info.xdc_blob_path)
+        .await
+        .with_context(|| format!("failed to hash {}", app_info.xdc_blob_path.display()))?;
     let mut trans = c.begin().await?;
+    let image = std::mem::take(&mut app_info.image);
+    if !image.is_empty() {
+        app_info.icon = icon_hash(&image);
+        add_icon(&mut trans, &app_info.icon, &image).await?;
+    }
     let next_serial = increase_get_serial(&mut trans).await?;
-    let res = sqlx::query("INSERT INTO app_infos (app_id, name, description, tag_name, image, xdc_blob_path, source_code_url, serial, date, size) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+    let previous_tag_name: Option<String> = sqlx::query_scalar(
+        "SELECT tag_name FROM app_infos WHERE app_id = ? AND channel = ? ORDER BY serial DESC LIMIT 1",
+    )
+    .bind(&app_info.app_id)
+    .bind(app_info.channel)
+    .fetch_optional(&mut *trans)
+    .await?;
+    add_audit_record(
+        &mut trans,
+        &AuditRecord {
+            time: OffsetDateTime::now_utc().unix_timestamp(),
+            action: "create".to_string(),
+            source: actor.source,
+            user: actor.user.clone(),
+            app_id: Some(app_info.app_id.clone()),
+            channel: Some(app_info.channel),
+            previous_tag_name,
+            tag_name: Some(app_info.tag_name.clone()),
+            file_hash: Some(file_hash),
+            serial: next_serial,
+        },
+    )
+    .await?;
+    let res = sqlx::query("INSERT INTO app_infos (app_id, name, description, tag_name, image, icon, xdc_blob_path, source_code_url, serial, date, size, channel) VALUES (?, ?, ?, ?, '', ?, ?, ?, ?, ?, ?, ?)")
         .bind(app_info.app_id.as_str())
         .bind(app_info.name.as_str())
         .bind(&app_info.description)
         .bind(&app_info.tag_name)
-        .bind(&app_info.image)
+        .bind(&app_info.icon)
         .bind(app_info.xdc_blob_path.to_str())
         .bind(&app_info.source_code_url)
         .bind(next_serial)
         .bind(app_info.date)
         .bind(app_info.size)
+        .bind(app_info.channel)
         .execute(&mut *trans)
         .await?;
     app_info.id = i32::try_from(res.last_insert_rowid())?;
+    for (position, screenshot) in std::mem::take(&mut app_info.screenshots).iter().enumerate() {
+        sqlx::query("INSERT INTO screenshots (app_info_id, position, data) VALUES (?, ?, ?)")
+            .bind(app_info.id)
+            .bind(i64::try_from(position)?)
+            .bind(screenshot)
+            .execute(&mut *trans)
+            .await?;
+    }
     trans.commit().await?;
     Ok(())
 }
 
-/// Get [AppInfo] by app_id.
+/// Get the newest [AppInfo] by app_id visible on the channel.
 pub async fn get_app_info_for_app_id(
     c: &mut SqliteConnection,
     app_id: &str,
+    channel: Channel,
 ) -> sqlx::Result<AppInfo> {
     sqlx::query_as::<_, DBAppInfo>(
-        "SELECT * FROM app_infos WHERE app_id = ? ORDER BY serial DESC LIMIT 1;",
+        "SELECT * FROM app_infos WHERE app_id = ? AND channel <= ? ORDER BY serial DESC LIMIT 1;",
     )
     .bind(app_id)
+    .bind(channel)
     .fetch_one(c)
     .await
     .map(|app| app.into())
 }
 
-/// Returns wheter an [AppInfo] with greater tag_name exists.
+/// Returns wheter an [AppInfo] with greater tag_name exists on the channel.
 pub async fn maybe_get_greater_tag_name(
     c: &mut SqliteConnection,
     app_id: &str,
     tag_name: &str,
+    channel: Channel,
 ) -> sqlx::Result<bool> {
     sqlx::query(
-        "SELECT EXISTS(SELECT 1 FROM app_infos WHERE app_id = ? AND tag_name > ? AND removed = 0 LIMIT 1) AS exists_greater_tag_name",
+        "SELECT EXISTS(SELECT 1 FROM app_infos WHERE app_id = ? AND tag_name > ? AND removed = 0 AND channel <= ? LIMIT 1) AS exists_greater_tag_name",
     )
     .bind(app_id)
     .bind(tag_name)
+    .bind(channel)
     .fetch_one(c)
     .await
     .map(|app| app.get(0))
 }
 
-#[cfg(test)]
 /// Return all [AppInfo]s.
 pub async fn get_app_infos(c: &mut SqliteConnection) -> sqlx::Result<Vec<AppInfo>> {
     sqlx::query_as::<_, DBAppInfo>("SELECT * FROM app_infos")
@@ -180,27 +418,33 @@ pub async fn get_app_infos(c: &mut SqliteConnection) -> sqlx::Result<Vec<AppInfo
         .map(|app| app.into_iter().map(|a| a.into()).collect())
 }
 
-/// Returns the newest AppInfo for each app.
-pub async fn get_active_app_infos(c: &mut SqliteConnection) -> sqlx::Result<Vec<AppInfo>> {
+/// Returns the newest AppInfo for each app visible on the channel.
+pub async fn get_active_app_infos(
+    c: &mut SqliteConnection,
+    channel: Channel,
+) -> sqlx::Result<Vec<AppInfo>> {
     sqlx::query_as::<_, DBAppInfo>(
         r#"SELECT a.*
     FROM app_infos a
     JOIN (
         SELECT app_id, MAX(serial) AS latest_serial
         FROM app_infos
+        WHERE channel <= ?
         GROUP BY app_id
     ) b ON a.app_id = b.app_id AND a.serial = b.latest_serial"#,
     )
+    .bind(channel)
     .fetch_all(c)
     .await
     .map(|app| app.into_iter().map(|a| a.into()).collect())
 }
 
 /// Get the newest [AppInfo]s with a serial greater than serial.
-/// Gets the latest versions of all changed apps.
+/// Gets the latest versions of all changed apps visible on the channel.
 pub async fn get_changed_app_infos_since(
     c: &mut SqliteConnection,
     serial: u32,
+    channel: Channel,
 ) -> sqlx::Result<Vec<AppInfo>> {
     sqlx::query_as::<_, DBAppInfo>(
         r#"SELECT a.*
@@ -208,21 +452,25 @@ FROM app_infos a
 JOIN (
     SELECT app_id, MAX(serial) AS latest_serial
     FROM app_infos
+    WHERE channel <= ?
     GROUP BY app_id
 ) b ON a.app_id = b.app_id AND a.serial = b.latest_serial
 WHERE a.serial > ?"#,
     )
+    .bind(channel)
     .bind(serial)
     .fetch_all(c)
     .await
     .map(|app| app.into_iter().map(|a| a.into()).collect())
 }
 
-/// This function takes a list of app_ids's and returns the latest version of each app where serial <= serial.
+/// This function takes a list of app_ids's and returns the latest version of each app where serial <= serial
+/// visible on the channel.
 pub async fn get_app_infos_for(
     c: &mut SqliteConnection,
     apps: &[&str],
     serial: u32,
+    channel: Channel,
 ) -> sqlx::Result<Vec<AppInfo>> {
     #[allow(unstable_name_collisions)]
     let list = apps
@@ -242,11 +490,13 @@ pub async fn get_app_infos_for(
             FROM app_infos
             WHERE app_id = a.app_id
               AND serial <= ?
+              AND channel <= ?
         )
     "#
     ))
     .bind(serial)
     .bind(serial)
+    .bind(channel)
     .fetch_all(c)
     .await
     .map(|app| app.into_iter().map(|a| a.into()).collect())
@@ -261,17 +511,35 @@ pub async fn app_exists(c: &mut SqliteConnection, app_id: &str) -> sqlx::Result<
         .map(|row| row.get(0))
 }
 
-/// Returns wheter an [AppInfo] with given tag_name exists for the given app_id.
+/// Returns the number of apps and app versions in the catalog.
+pub async fn get_catalog_size(c: &mut SqliteConnection) -> sqlx::Result<(i64, i64)> {
+    sqlx::query_as("SELECT COUNT(DISTINCT app_id), COUNT(*) FROM app_infos WHERE removed = 0")
+        .fetch_one(c)
+        .await
+}
+
+/// Returns the number of known store instances per `tag_name` of the store frontend.
+pub async fn get_store_instances(c: &mut SqliteConnection) -> sqlx::Result<Vec<(String, i64)>> {
+    sqlx::query_as(
+        "SELECT tag_name, COUNT(*) FROM webxdc_tag_names GROUP BY tag_name ORDER BY tag_name",
+    )
+    .fetch_all(c)
+    .await
+}
+
+/// Returns wheter an [AppInfo] with given tag_name visible on the channel exists for the given app_id.
 pub async fn app_tag_name_exists(
     c: &mut SqliteConnection,
     app_id: &str,
     tag_name: &str,
+    channel: Channel,
 ) -> sqlx::Result<bool> {
     sqlx::query(
-        "SELECT EXISTS(SELECT 1 FROM app_infos WHERE app_id = ? AND tag_name = ? AND removed = 0)",
+        "SELECT EXISTS(SELECT 1 FROM app_infos WHERE app_id = ? AND tag_name = ? AND removed = 0 AND channel <= ?)",
     )
     .bind(app_id)
     .bind(tag_name)
+    .bind(channel)
     .fetch_one(c)
     .await
     .map(|row| row.get(0))
@@ -291,270 +559,1719 @@ pub async fn set_store_tag_name(
     Ok(())
 }
 
-/// Returns the webxdc `tag_name` for some previously sent `store.xdc` instance.
-pub async fn get_store_tag_name(c: &mut SqliteConnection, msg: MsgId) -> sqlx::Result<String> {
+/// Returns the webxdc `tag_name` for some previously sent `store.xdc` instance,
+/// or `None` if the message is not a `store.xdc` instance.
+pub async fn get_store_tag_name(
+    c: &mut SqliteConnection,
+    msg: MsgId,
+) -> sqlx::Result<Option<String>> {
     sqlx::query("SELECT * FROM webxdc_tag_names WHERE msg_id = ?")
         .bind(msg.to_u32())
+        .fetch_optional(c)
+        .await
+        .map(|row| row.map(|a| a.get("tag_name")))
+}
+
+/// Returns the serial before which frontends can't be updated incrementally.
+///
+/// It is raised when garbage collection deletes an app removal
+/// and when the index is reset, e.g. because the visibility of an app changed.
+pub async fn get_resync_serial(c: &mut SqliteConnection) -> sqlx::Result<u32> {
+    sqlx::query("SELECT resync_serial FROM config")
         .fetch_one(c)
         .await
-        .map(|a| (a.get("tag_name")))
+        .map(|a| a.get("resync_serial"))
 }
 
-/// Removes app with app_id from store.
-pub async fn remove_app(c: &mut SqliteConnection, app_id: &str) -> sqlx::Result<()> {
+/// Deletes the [AppInfo]s with the given ids.
+///
+/// If removed apps are deleted, the resync serial is raised
+/// so that frontends which have not seen the removal receive the full index.
+/// Apps without any remaining version are remembered as deleted,
+/// so these frontends can be told to remove them.
+/// Each deleted version is recorded in the audit log.
+pub async fn delete_app_infos(
+    c: &mut SqliteConnection,
+    ids: &[RecordId],
+    actor: &Actor,
+) -> sqlx::Result<()> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let list = ids.iter().join(",");
     let mut t = c.begin().await?;
-    let next_serial = increase_get_serial(&mut t).await?;
-    sqlx::query("UPDATE app_infos SET removed = 1, serial = ? WHERE app_id = ? AND serial = (SELECT MAX(serial) FROM app_infos WHERE app_id = ?);")
-        .bind(next_serial)
-        .bind(app_id)
-        .bind(app_id)
+    let serial = get_last_serial(&mut t).await?;
+    let deleted: Vec<(String, Channel, String)> = sqlx::query_as(&format!(
+        "SELECT app_id, channel, tag_name FROM app_infos WHERE id IN ({list}) ORDER BY id"
+    ))
+    .fetch_all(&mut *t)
+    .await?;
+    for (app_id, channel, tag_name) in deleted {
+        add_audit_record(
+            &mut t,
+            &AuditRecord {
+                time: OffsetDateTime::now_utc().unix_timestamp(),
+                action: "delete".to_string(),
+                source: actor.source,
+                user: actor.user.clone(),
+                app_id: Some(app_id),
+                channel: Some(channel),
+                previous_tag_name: Some(tag_name),
+                tag_name: None,
+                file_hash: None,
+                serial,
+            },
+        )
+        .await?;
+    }
+    sqlx::query(&format!(
+        "UPDATE config SET resync_serial = MAX(resync_serial, IFNULL((SELECT MAX(serial) FROM app_infos WHERE id IN ({list}) AND removed = 1), 0))"
+    ))
+    .execute(&mut *t)
+    .await?;
+    sqlx::query(&format!(
+        "INSERT OR IGNORE INTO deleted_apps (app_id) SELECT DISTINCT app_id FROM app_infos WHERE id IN ({list})"
+    ))
+    .execute(&mut *t)
+    .await?;
+    sqlx::query(&format!("DELETE FROM app_infos WHERE id IN ({list})"))
+        .execute(&mut *t)
+        .await?;
+    sqlx::query("DELETE FROM deleted_apps WHERE app_id IN (SELECT app_id FROM app_infos)")
         .execute(&mut *t)
         .await?;
+    sqlx::query("DELETE FROM icons WHERE hash NOT IN (SELECT icon FROM app_infos)")
+        .execute(&mut *t)
+        .await?;
+    sqlx::query(&format!(
+        "DELETE FROM screenshots WHERE app_info_id IN ({list})"
+    ))
+    .execute(&mut *t)
+    .await?;
     t.commit().await
 }
 
-#[cfg(test)]
-#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
-mod tests {
-    use super::*;
-    use crate::utils::AddType;
-    use sqlx::{Connection, SqliteConnection};
-    use std::{env, fs::create_dir, vec};
+/// Returns the IDs of all apps a frontend may know,
+/// including apps deleted completely by the garbage collection.
+pub async fn get_known_app_ids(c: &mut SqliteConnection) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar("SELECT app_id FROM app_infos UNION SELECT app_id FROM deleted_apps")
+        .fetch_all(c)
+        .await
+}
 
-    #[tokio::test]
-    async fn test_create_load_config() {
-        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
-        MIGRATOR.run(&mut conn).await.unwrap();
+/// Returns the paths of all .xdc files referenced by the app index.
+pub async fn get_xdc_blob_paths(c: &mut SqliteConnection) -> sqlx::Result<Vec<PathBuf>> {
+    sqlx::query("SELECT DISTINCT xdc_blob_path FROM app_infos")
+        .fetch_all(c)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| PathBuf::from(row.get::<String, _>("xdc_blob_path")))
+                .collect()
+        })
+}
 
-        let config = BotConfig {
-            invite_qr: "invite_qr".to_string(),
-            serial: 0,
+/// Removes app with app_id from store.
+///
+/// The newest version visible on each channel is marked as removed.
+/// The change is recorded in the audit log.
+pub async fn remove_app(c: &mut SqliteConnection, app_id: &str, actor: &Actor) -> sqlx::Result<()> {
+    let mut t = c.begin().await?;
+    for channel in [Channel::Stable, Channel::Beta] {
+        let newest: Option<(RecordId, bool, String)> = sqlx::query_as(
+            "SELECT id, removed, tag_name FROM app_infos WHERE app_id = ? AND channel <= ? ORDER BY serial DESC LIMIT 1",
+        )
+        .bind(app_id)
+        .bind(channel)
+        .fetch_optional(&mut *t)
+        .await?;
+        let Some((id, false, tag_name)) = newest else {
+            continue;
         };
-        set_config(&mut conn, &config).await.unwrap();
-        let loaded_config = get_config(&mut conn).await.unwrap();
-        assert_eq!(config, loaded_config);
-    }
-
-    #[tokio::test]
-    async fn increase_serial() {
-        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
-        MIGRATOR.run(&mut conn).await.unwrap();
-        set_config(&mut conn, &BotConfig::default()).await.unwrap();
-        let serial = increase_get_serial(&mut conn).await.unwrap();
-        assert_eq!(serial, 1);
+        let next_serial = increase_get_serial(&mut t).await?;
+        sqlx::query("UPDATE app_infos SET removed = 1, serial = ? WHERE id = ?")
+            .bind(next_serial)
+            .bind(id)
+            .execute(&mut *t)
+            .await?;
+        add_audit_record(
+            &mut t,
+            &AuditRecord {
+                time: OffsetDateTime::now_utc().unix_timestamp(),
+                action: "remove".to_string(),
+                source: actor.source,
+                user: actor.user.clone(),
+                app_id: Some(app_id.to_string()),
+                channel: Some(channel),
+                previous_tag_name: Some(tag_name),
+                tag_name: None,
+                file_hash: None,
+                serial: next_serial,
+            },
+        )
+        .await?;
     }
+    t.commit().await
+}
 
-    #[tokio::test]
-    async fn store_tag_name_set_get() {
-        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
-        MIGRATOR.run(&mut conn).await.unwrap();
+/// Records that the store was sent to a contact after joining at `time`.
+pub async fn add_pending_welcome(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    time: i64,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT OR REPLACE INTO pending_welcomes (contact_id, sent) VALUES (?, ?)")
+        .bind(contact_id.to_u32())
+        .bind(time)
+        .execute(c)
+        .await?;
+    Ok(())
+}
 
-        let msg = MsgId::new(1);
-        set_store_tag_name(&mut conn, msg, "v1.2.1").await.unwrap();
-        let loaded_tag_name = get_store_tag_name(&mut conn, msg).await.unwrap();
-        assert_eq!(loaded_tag_name, "v1.2.1".to_string());
-    }
+/// Returns true if the store was sent to a contact after joining at `since` or later
+/// and the contact did not write since.
+pub async fn has_pending_welcome(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    since: i64,
+) -> sqlx::Result<bool> {
+    sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM pending_welcomes WHERE contact_id = ? AND sent >= ?)",
+    )
+    .bind(contact_id.to_u32())
+    .bind(since)
+    .fetch_one(c)
+    .await
+}
 
-    #[tokio::test]
-    async fn test_create_get_app_info() {
-        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
-        MIGRATOR.run(&mut conn).await.unwrap();
-        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+/// Removes the pending welcome of a contact.
+///
+/// Returns true if the store was sent to the contact after joining at `since` or later.
+pub async fn take_pending_welcome(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    since: i64,
+) -> sqlx::Result<bool> {
+    let mut t = c.begin().await?;
+    let pending = has_pending_welcome(&mut t, contact_id, since).await?;
+    sqlx::query("DELETE FROM pending_welcomes WHERE contact_id = ?")
+        .bind(contact_id.to_u32())
+        .execute(&mut *t)
+        .await?;
+    t.commit().await?;
+    Ok(pending)
+}
 
-        let mut app_info = AppInfo {
-            size: 998887,
-            date: 1688835984521,
-            app_id: "app_id".to_string(),
-            id: 12,
-            tag_name: "v1.2.1".to_string(),
-            name: "Sebastians coole app".to_string(),
-            source_code_url: "https://git.example.com/sebastian/app".to_string(),
-            image: "aaaaaaaaaaaaaaaaaaaaaaaaa".to_string(),
-            description: "This is a cool app".to_string(),
-            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
-            removed: false,
-        };
+/// Adds a named invite.
+pub async fn add_invite(c: &mut SqliteConnection, invite: &Invite) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO invites (name, chat_id, created, revoked, app_id) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(&invite.name)
+    .bind(invite.chat_id)
+    .bind(invite.created)
+    .bind(invite.revoked)
+    .bind(&invite.app_id)
+    .execute(c)
+    .await?;
+    Ok(())
+}
 
-        create_app_info(&mut conn, &mut app_info).await.unwrap();
+/// Returns the invite with the given name.
+pub async fn get_invite(c: &mut SqliteConnection, name: &str) -> sqlx::Result<Option<Invite>> {
+    sqlx::query_as("SELECT * FROM invites WHERE name = ?")
+        .bind(name)
+        .fetch_optional(c)
+        .await
+}
 
-        let loaded_app_info = get_app_info_for_app_id(&mut conn, &app_info.app_id)
-            .await
-            .unwrap();
+/// Returns all invites ordered by name.
+pub async fn get_invites(c: &mut SqliteConnection) -> sqlx::Result<Vec<Invite>> {
+    sqlx::query_as("SELECT * FROM invites ORDER BY name")
+        .fetch_all(c)
+        .await
+}
 
-        assert_eq!(app_info, loaded_app_info);
+/// Returns true if the chat is the group of a named invite.
+pub async fn is_invite_chat(c: &mut SqliteConnection, chat_id: ChatId) -> sqlx::Result<bool> {
+    sqlx::query_scalar("SELECT COUNT(*) > 0 FROM invites WHERE chat_id = ?")
+        .bind(chat_id.to_u32())
+        .fetch_one(c)
+        .await
+}
 
-        app_info.app_id = "test2".to_string();
-        let dest = env::temp_dir().join("example-xdcs");
-        create_dir(&dest).ok();
-        let add_type = crate::utils::maybe_upgrade_xdc(&mut app_info, &mut conn, &dest)
-            .await
-            .unwrap();
+/// Marks an invite as revoked or as usable again.
+pub async fn set_invite_revoked(
+    c: &mut SqliteConnection,
+    name: &str,
+    revoked: bool,
+) -> sqlx::Result<()> {
+    sqlx::query("UPDATE invites SET revoked = ? WHERE name = ?")
+        .bind(revoked)
+        .bind(name)
+        .execute(c)
+        .await?;
+    Ok(())
+}
 
-        assert_eq!(add_type, crate::utils::AddType::Added);
+/// Records the invite a contact joined through.
+///
+/// Only the first join of a contact is recorded.
+pub async fn add_invite_join(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    join: &InviteJoin,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT OR IGNORE INTO invite_joins (contact_id, addr, invite, joined) VALUES (?, ?, ?, ?)",
+    )
+    .bind(contact_id.to_u32())
+    .bind(&join.addr)
+    .bind(&join.invite)
+    .bind(join.joined)
+    .execute(c)
+    .await?;
+    Ok(())
+}
 
-        let loaded_app_info = get_app_info_for_app_id(&mut conn, &app_info.app_id)
-            .await
-            .unwrap();
-        assert_eq!(app_info, loaded_app_info);
-    }
+/// Returns the number of joins per invite, `None` being the default invite.
+pub async fn get_invite_join_counts(
+    c: &mut SqliteConnection,
+) -> sqlx::Result<Vec<(Option<String>, i64)>> {
+    sqlx::query_as("SELECT invite, COUNT(*) FROM invite_joins GROUP BY invite ORDER BY invite")
+        .fetch_all(c)
+        .await
+}
 
-    #[tokio::test]
-    async fn upgrade_app() {
-        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
-        MIGRATOR.run(&mut conn).await.unwrap();
-        set_config(&mut conn, &BotConfig::default()).await.unwrap();
-        let dest = env::temp_dir().join("example-xdcs");
-        create_dir(&dest).ok();
+/// Returns the contacts who joined through an invite, the newest first.
+pub async fn get_invite_joins(
+    c: &mut SqliteConnection,
+    invite: &str,
+) -> sqlx::Result<Vec<InviteJoin>> {
+    sqlx::query_as(
+        "SELECT addr, invite, joined FROM invite_joins WHERE invite = ? ORDER BY joined DESC",
+    )
+    .bind(invite)
+    .fetch_all(c)
+    .await
+}
 
-        let mut app_info = AppInfo {
-            app_id: "testxdc".to_string(),
-            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
-            tag_name: "v1.2.1".to_string(),
-            ..Default::default()
-        };
+/// Returns the release channel of a contact.
+pub async fn get_contact_channel(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+) -> sqlx::Result<Channel> {
+    sqlx::query("SELECT channel FROM contact_channels WHERE contact_id = ?")
+        .bind(contact_id.to_u32())
+        .fetch_optional(c)
+        .await
+        .map(|row| row.map_or(Channel::Stable, |row| row.get("channel")))
+}
 
-        super::create_app_info(&mut conn, &mut app_info)
-            .await
+/// Sets the release channel of a contact.
+pub async fn set_contact_channel(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    channel: Channel,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT OR REPLACE INTO contact_channels (contact_id, channel) VALUES (?, ?)")
+        .bind(contact_id.to_u32())
+        .bind(channel)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Returns the settings of a group chat the store was posted to.
+pub async fn get_group_chat(
+    c: &mut SqliteConnection,
+    chat_id: ChatId,
+) -> sqlx::Result<Option<GroupChat>> {
+    sqlx::query_as("SELECT * FROM group_chats WHERE chat_id = ?")
+        .bind(chat_id.to_u32())
+        .fetch_optional(c)
+        .await
+}
+
+/// Records a group chat the store is posted to.
+pub async fn create_group_chat(
+    c: &mut SqliteConnection,
+    chat_id: ChatId,
+    admin_addr: &str,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT OR REPLACE INTO group_chats (chat_id, admin_addr) VALUES (?, ?)")
+        .bind(chat_id.to_u32())
+        .bind(admin_addr)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Sets whether apps can be downloaded in a group chat.
+pub async fn set_downloads_disabled(
+    c: &mut SqliteConnection,
+    chat_id: ChatId,
+    disabled: bool,
+) -> sqlx::Result<()> {
+    sqlx::query("UPDATE group_chats SET downloads_disabled = ? WHERE chat_id = ?")
+        .bind(disabled)
+        .bind(chat_id.to_u32())
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Subscribes a contact to new versions of an app.
+pub async fn add_subscription(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    app_id: &str,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT OR IGNORE INTO subscriptions (contact_id, app_id) VALUES (?, ?)")
+        .bind(contact_id.to_u32())
+        .bind(app_id)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Unsubscribes a contact from new versions of an app.
+pub async fn remove_subscription(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    app_id: &str,
+) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM subscriptions WHERE contact_id = ? AND app_id = ?")
+        .bind(contact_id.to_u32())
+        .bind(app_id)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Returns the contacts subscribed to an app.
+pub async fn get_subscribers(
+    c: &mut SqliteConnection,
+    app_id: &str,
+) -> sqlx::Result<Vec<ContactId>> {
+    sqlx::query("SELECT contact_id FROM subscriptions WHERE app_id = ?")
+        .bind(app_id)
+        .fetch_all(c)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| ContactId::new(row.get("contact_id")))
+                .collect()
+        })
+}
+
+/// Returns the serial up to which subscribers were notified.
+pub async fn get_notified_serial(c: &mut SqliteConnection) -> sqlx::Result<u32> {
+    sqlx::query("SELECT notified_serial FROM config")
+        .fetch_one(c)
+        .await
+        .map(|row| row.get("notified_serial"))
+}
+
+/// Stores the serial up to which subscribers were notified.
+pub async fn set_notified_serial(c: &mut SqliteConnection, serial: u32) -> sqlx::Result<()> {
+    sqlx::query("UPDATE config SET notified_serial = ?")
+        .bind(serial)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Returns the app versions changed since the last notification of subscribers per release channel
+/// and marks them as notified.
+///
+/// The versions are read and marked in one transaction,
+/// so versions imported meanwhile are left for the next notification.
+pub async fn take_unnotified_app_infos(
+    c: &mut SqliteConnection,
+) -> sqlx::Result<Vec<(Channel, Vec<AppInfo>)>> {
+    let mut t = c.begin().await?;
+    let notified_serial = get_notified_serial(&mut t).await?;
+    let last_serial = get_last_serial(&mut t).await?;
+    let mut changed = Vec::new();
+    if last_serial > notified_serial {
+        for channel in [Channel::Stable, Channel::Beta] {
+            let app_infos = get_changed_app_infos_since(&mut t, notified_serial, channel).await?;
+            changed.push((channel, app_infos));
+        }
+        set_notified_serial(&mut t, last_serial).await?;
+    }
+    t.commit().await?;
+    Ok(changed)
+}
+
+/// Replaces the apps installed by a contact with the apps reported in an update request.
+pub async fn set_installed_apps(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    addr: &str,
+    apps: &[(String, String)],
+    now: i64,
+) -> sqlx::Result<()> {
+    let mut t = c.begin().await?;
+    sqlx::query("DELETE FROM installed_apps WHERE contact_id = ?")
+        .bind(contact_id.to_u32())
+        .execute(&mut *t)
+        .await?;
+    for (app_id, tag_name) in apps {
+        sqlx::query("INSERT OR REPLACE INTO installed_apps (contact_id, addr, app_id, tag_name, last_seen) VALUES (?, ?, ?, ?, ?)")
+            .bind(contact_id.to_u32())
+            .bind(addr)
+            .bind(app_id)
+            .bind(tag_name)
+            .bind(now)
+            .execute(&mut *t)
+            .await?;
+    }
+    t.commit().await
+}
+
+/// Returns the number of installs of each app version.
+pub async fn get_version_adoption(c: &mut SqliteConnection) -> sqlx::Result<Vec<VersionAdoption>> {
+    sqlx::query_as(
+        "SELECT app_id, tag_name, COUNT(*) AS installs, MAX(last_seen) AS last_seen FROM installed_apps GROUP BY app_id, tag_name ORDER BY app_id, tag_name",
+    )
+    .fetch_all(c)
+    .await
+}
+
+/// Returns the contacts with a version of the app installed.
+pub async fn get_installed_apps(
+    c: &mut SqliteConnection,
+    app_id: &str,
+) -> sqlx::Result<Vec<InstalledApp>> {
+    sqlx::query_as("SELECT * FROM installed_apps WHERE app_id = ? ORDER BY last_seen DESC")
+        .bind(app_id)
+        .fetch_all(c)
+        .await
+}
+
+/// Returns the visibility rules of all restricted apps.
+pub async fn get_app_visibilities(c: &mut SqliteConnection) -> Result<Vec<(String, Visibility)>> {
+    let rows = sqlx::query("SELECT app_id, rule FROM app_visibility")
+        .fetch_all(c)
+        .await?;
+    let mut visibilities = Vec::new();
+    for row in rows {
+        let rule: String = row.get("rule");
+        visibilities.push((row.get("app_id"), serde_json::from_str(&rule)?));
+    }
+    Ok(visibilities)
+}
+
+/// Returns the visibility of an app.
+pub async fn get_app_visibility(c: &mut SqliteConnection, app_id: &str) -> Result<Visibility> {
+    let rule: Option<String> = sqlx::query("SELECT rule FROM app_visibility WHERE app_id = ?")
+        .bind(app_id)
+        .fetch_optional(c)
+        .await?
+        .map(|row| row.get("rule"));
+    match rule {
+        Some(rule) => Ok(serde_json::from_str(&rule)?),
+        None => Ok(Visibility::Public),
+    }
+}
+
+/// Stores the visibility of an app.
+pub async fn set_app_visibility(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    visibility: &Visibility,
+) -> Result<()> {
+    match visibility {
+        Visibility::Public => {
+            sqlx::query("DELETE FROM app_visibility WHERE app_id = ?")
+                .bind(app_id)
+                .execute(c)
+                .await?;
+        }
+        _ => {
+            sqlx::query("INSERT OR REPLACE INTO app_visibility (app_id, rule) VALUES (?, ?)")
+                .bind(app_id)
+                .bind(serde_json::to_string(visibility)?)
+                .execute(c)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the curation of all curated apps, highest priority first.
+pub async fn get_app_curations(c: &mut SqliteConnection) -> sqlx::Result<Vec<AppCuration>> {
+    sqlx::query_as("SELECT * FROM app_curation ORDER BY priority DESC, app_id")
+        .fetch_all(c)
+        .await
+}
+
+/// Changes the featured flag and/or the priority of an app.
+pub async fn set_app_curation(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    featured: Option<bool>,
+    priority: Option<i32>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO app_curation (app_id, featured, priority) VALUES (?1, COALESCE(?2, 0), COALESCE(?3, 0))
+         ON CONFLICT (app_id) DO UPDATE SET featured = COALESCE(?2, featured), priority = COALESCE(?3, priority)",
+    )
+    .bind(app_id)
+    .bind(featured)
+    .bind(priority)
+    .execute(&mut *c)
+    .await?;
+    sqlx::query("DELETE FROM app_curation WHERE featured = 0 AND priority = 0")
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Returns all collections with their apps in the curated order.
+pub async fn get_collections(c: &mut SqliteConnection) -> sqlx::Result<Vec<Collection>> {
+    let rows: Vec<(String, Option<String>)> = sqlx::query_as(
+        "SELECT collections.name, collection_apps.app_id FROM collections
+         LEFT JOIN collection_apps ON collection_apps.collection = collections.name
+         ORDER BY collections.position, collections.name, collection_apps.position",
+    )
+    .fetch_all(c)
+    .await?;
+    let mut collections: Vec<Collection> = Vec::new();
+    for (name, app_id) in rows {
+        if collections
+            .last()
+            .map_or(true, |collection| collection.name != name)
+        {
+            collections.push(Collection {
+                name,
+                app_ids: Vec::new(),
+            });
+        }
+        if let (Some(collection), Some(app_id)) = (collections.last_mut(), app_id) {
+            collection.app_ids.push(app_id);
+        }
+    }
+    Ok(collections)
+}
+
+/// Appends apps to a collection, creating the collection at the end if it does not exist.
+pub async fn add_to_collection(
+    c: &mut SqliteConnection,
+    name: &str,
+    app_ids: &[String],
+) -> sqlx::Result<()> {
+    let mut t = c.begin().await?;
+    sqlx::query(
+        "INSERT OR IGNORE INTO collections (name, position)
+         SELECT ?, COALESCE(MAX(position) + 1, 0) FROM collections",
+    )
+    .bind(name)
+    .execute(&mut *t)
+    .await?;
+    for app_id in app_ids {
+        sqlx::query(
+            "INSERT OR IGNORE INTO collection_apps (collection, app_id, position)
+             SELECT ?1, ?2, COALESCE(MAX(position) + 1, 0) FROM collection_apps WHERE collection = ?1",
+        )
+        .bind(name)
+        .bind(app_id)
+        .execute(&mut *t)
+        .await?;
+    }
+    t.commit().await
+}
+
+/// Removes apps from a collection.
+pub async fn remove_from_collection(
+    c: &mut SqliteConnection,
+    name: &str,
+    app_ids: &[String],
+) -> sqlx::Result<()> {
+    let mut t = c.begin().await?;
+    for app_id in app_ids {
+        sqlx::query("DELETE FROM collection_apps WHERE collection = ? AND app_id = ?")
+            .bind(name)
+            .bind(app_id)
+            .execute(&mut *t)
+            .await?;
+    }
+    t.commit().await
+}
+
+/// Moves a collection to the given position.
+/// Collections are shown in ascending order of their positions.
+///
+/// Returns false if the collection does not exist.
+pub async fn set_collection_position(
+    c: &mut SqliteConnection,
+    name: &str,
+    position: i32,
+) -> sqlx::Result<bool> {
+    let res = sqlx::query("UPDATE collections SET position = ? WHERE name = ?")
+        .bind(position)
+        .bind(name)
+        .execute(c)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Deletes a collection.
+pub async fn delete_collection(c: &mut SqliteConnection, name: &str) -> sqlx::Result<()> {
+    let mut t = c.begin().await?;
+    sqlx::query("DELETE FROM collection_apps WHERE collection = ?")
+        .bind(name)
+        .execute(&mut *t)
+        .await?;
+    sqlx::query("DELETE FROM collections WHERE name = ?")
+        .bind(name)
+        .execute(&mut *t)
+        .await?;
+    t.commit().await
+}
+
+/// Stores an icon encoded as a data URL under its hash.
+pub async fn add_icon(c: &mut SqliteConnection, hash: &str, data: &str) -> sqlx::Result<()> {
+    sqlx::query("INSERT OR IGNORE INTO icons (hash, data) VALUES (?, ?)")
+        .bind(hash)
+        .bind(data)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Returns the icons with the given hashes as pairs of hash and data URL.
+/// Unknown hashes are skipped.
+pub async fn get_icons(
+    c: &mut SqliteConnection,
+    hashes: &[String],
+) -> sqlx::Result<Vec<(String, String)>> {
+    let mut icons = Vec::new();
+    for hash in hashes {
+        let icon: Option<(String, String)> =
+            sqlx::query_as("SELECT hash, data FROM icons WHERE hash = ?")
+                .bind(hash)
+                .fetch_optional(&mut *c)
+                .await?;
+        icons.extend(icon);
+    }
+    Ok(icons)
+}
+
+/// Returns the screenshots of an app version as data URLs.
+pub async fn get_screenshots(c: &mut SqliteConnection, id: RecordId) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar("SELECT data FROM screenshots WHERE app_info_id = ? ORDER BY position")
+        .bind(id)
+        .fetch_all(c)
+        .await
+}
+
+/// Moves the icons stored inline in the app index of older versions to the icons table.
+pub async fn move_inline_icons(c: &mut SqliteConnection) -> sqlx::Result<usize> {
+    let mut t = c.begin().await?;
+    let rows: Vec<(RecordId, String)> =
+        sqlx::query_as("SELECT id, image FROM app_infos WHERE icon = '' AND image != ''")
+            .fetch_all(&mut *t)
+            .await?;
+    for (id, image) in &rows {
+        let hash = icon_hash(image);
+        add_icon(&mut t, &hash, image).await?;
+        sqlx::query("UPDATE app_infos SET icon = ?, image = '' WHERE id = ?")
+            .bind(&hash)
+            .bind(id)
+            .execute(&mut *t)
+            .await?;
+    }
+    t.commit().await?;
+    Ok(rows.len())
+}
+
+/// Increases the serial and makes all frontends with an older serial
+/// receive the full index instead of incremental updates.
+/// The change is recorded in the audit log.
+pub async fn reset_index(c: &mut SqliteConnection, actor: &Actor) -> sqlx::Result<()> {
+    let mut t = c.begin().await?;
+    let serial = increase_get_serial(&mut t).await?;
+    sqlx::query("UPDATE config SET resync_serial = ?")
+        .bind(serial)
+        .execute(&mut *t)
+        .await?;
+    add_audit_record(
+        &mut t,
+        &AuditRecord {
+            time: OffsetDateTime::now_utc().unix_timestamp(),
+            action: "reset".to_string(),
+            source: actor.source,
+            user: actor.user.clone(),
+            app_id: None,
+            channel: None,
+            previous_tag_name: None,
+            tag_name: None,
+            file_hash: None,
+            serial,
+        },
+    )
+    .await?;
+    t.commit().await
+}
+
+/// Adds a record to the audit log.
+async fn add_audit_record(c: &mut SqliteConnection, record: &AuditRecord) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO audit_log (time, action, source, user, app_id, channel, previous_tag_name, tag_name, file_hash, serial) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+        .bind(record.time)
+        .bind(&record.action)
+        .bind(record.source)
+        .bind(&record.user)
+        .bind(&record.app_id)
+        .bind(record.channel)
+        .bind(&record.previous_tag_name)
+        .bind(&record.tag_name)
+        .bind(&record.file_hash)
+        .bind(record.serial)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Returns the audit log records, the oldest first.
+///
+/// Records of changes of the whole index are included when filtering by app.
+/// `since` and `until` are timestamps in seconds, `until` is exclusive.
+pub async fn get_audit_records(
+    c: &mut SqliteConnection,
+    app_id: Option<&str>,
+    since: Option<i64>,
+    until: Option<i64>,
+) -> sqlx::Result<Vec<AuditRecord>> {
+    sqlx::query_as(
+        "SELECT time, action, source, user, app_id, channel, previous_tag_name, tag_name, file_hash, serial FROM audit_log
+        WHERE (?1 IS NULL OR app_id = ?1 OR app_id IS NULL) AND (?2 IS NULL OR time >= ?2) AND (?3 IS NULL OR time < ?3)
+        ORDER BY id",
+    )
+    .bind(app_id)
+    .bind(since)
+    .bind(until)
+    .fetch_all(c)
+    .await
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::utils::AddType;
+    use sqlx::{Connection, SqliteConnection};
+    use std::vec;
+
+    #[tokio::test]
+    async fn test_create_load_config() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let config = BotConfig {
+            invite_qr: "invite_qr".to_string(),
+            serial: 0,
+        };
+        set_config(&mut conn, &config).await.unwrap();
+        let loaded_config = get_config(&mut conn).await.unwrap();
+        assert_eq!(config, loaded_config);
+    }
+
+    #[tokio::test]
+    async fn test_settings_set_get() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        assert_eq!(get_settings(&mut conn).await.unwrap(), None);
+        set_settings(&mut conn, "{}").await.unwrap();
+        assert_eq!(
+            get_settings(&mut conn).await.unwrap(),
+            Some("{}".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn increase_serial() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+        let serial = increase_get_serial(&mut conn).await.unwrap();
+        assert_eq!(serial, 1);
+    }
+
+    #[tokio::test]
+    async fn store_tag_name_set_get() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let msg = MsgId::new(1);
+        set_store_tag_name(&mut conn, msg, "v1.2.1").await.unwrap();
+        let loaded_tag_name = get_store_tag_name(&mut conn, msg).await.unwrap();
+        assert_eq!(loaded_tag_name, Some("v1.2.1".to_string()));
+        assert_eq!(
+            get_store_tag_name(&mut conn, MsgId::new(2)).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_get_app_info() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let mut app_info = AppInfo {
+            size: 998887,
+            date: 1688835984521,
+            app_id: "app_id".to_string(),
+            id: 12,
+            tag_name: "v1.2.1".to_string(),
+            name: "Sebastians coole app".to_string(),
+            source_code_url: "https://git.example.com/sebastian/app".to_string(),
+            image: "aaaaaaaaaaaaaaaaaaaaaaaaa".to_string(),
+            icon: String::new(),
+            description: "This is a cool app".to_string(),
+            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+            removed: false,
+            channel: Channel::Stable,
+        };
+
+        create_app_info(&mut conn, &mut app_info, &Actor::default())
+            .await
+            .unwrap();
+        assert_eq!(app_info.icon, icon_hash("aaaaaaaaaaaaaaaaaaaaaaaaa"));
+        assert_eq!(app_info.image, "");
+
+        let loaded_app_info = get_app_info_for_app_id(&mut conn, &app_info.app_id, Channel::Stable)
+            .await
+            .unwrap();
+
+        assert_eq!(app_info, loaded_app_info);
+
+        app_info.app_id = "test2".to_string();
+        let dest = tempfile::tempdir().unwrap();
+        let add_type = crate::utils::maybe_upgrade_xdc(
+            &mut app_info,
+            &mut conn,
+            dest.path(),
+            &Actor::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(add_type, crate::utils::AddType::Added);
+
+        let loaded_app_info = get_app_info_for_app_id(&mut conn, &app_info.app_id, Channel::Stable)
+            .await
+            .unwrap();
+        assert_eq!(app_info, loaded_app_info);
+    }
+
+    #[tokio::test]
+    async fn upgrade_app() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+        let dest = tempfile::tempdir().unwrap();
+
+        let mut app_info = AppInfo {
+            app_id: "testxdc".to_string(),
+            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+            tag_name: "v1.2.1".to_string(),
+            ..Default::default()
+        };
+
+        super::create_app_info(&mut conn, &mut app_info, &Actor::default())
+            .await
+            .unwrap();
+
+        assert_eq!(super::get_app_infos(&mut conn).await.unwrap().len(), 1);
+
+        let mut new_app_info = AppInfo {
+            tag_name: "v1.2.2".to_string(),
+            ..app_info.clone()
+        };
+
+        let state = crate::utils::maybe_upgrade_xdc(
+            &mut new_app_info,
+            &mut conn,
+            dest.path(),
+            &Actor::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(state, AddType::Updated);
+        assert_eq!(
+            super::get_changed_app_infos_since(&mut conn, 1, Channel::Stable)
+                .await
+                .unwrap(),
+            vec![new_app_info.clone()]
+        );
+
+        let state = crate::utils::maybe_upgrade_xdc(
+            &mut new_app_info,
+            &mut conn,
+            dest.path(),
+            &Actor::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(state, AddType::Ignored);
+    }
+
+    #[tokio::test]
+    async fn test_app_exists() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let mut app_info = AppInfo {
+            app_id: "testxdc".to_string(),
+            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+            ..Default::default()
+        };
+
+        super::create_app_info(&mut conn, &mut app_info, &Actor::default())
+            .await
+            .unwrap();
+
+        assert!(super::app_exists(&mut conn, "testxdc").await.unwrap());
+        assert!(!super::app_exists(&mut conn, "testxdc2").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_maybe_get_greater() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let dest = tempfile::tempdir().unwrap();
+
+        let mut app_info = AppInfo {
+            app_id: "testxdc".to_string(),
+            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+            ..Default::default()
+        };
+
+        crate::utils::maybe_upgrade_xdc(&mut app_info, &mut conn, dest.path(), &Actor::default())
+            .await
+            .unwrap();
+
+        // test that file has been moved
+        assert!(dest.path().join("webxdc-2048-v1.2.1.xdc").exists());
+
+        assert!(!maybe_get_greater_tag_name(
+            &mut conn,
+            &app_info.app_id,
+            &app_info.tag_name,
+            Channel::Stable
+        )
+        .await
+        .unwrap());
+
+        crate::utils::maybe_upgrade_xdc(
+            &mut AppInfo {
+                tag_name: "v1.2.1".to_string(),
+                app_id: "testxdc".to_string(),
+                ..app_info.clone()
+            },
+            &mut conn,
+            dest.path(),
+            &Actor::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(maybe_get_greater_tag_name(
+            &mut conn,
+            &app_info.app_id,
+            &app_info.tag_name,
+            Channel::Stable
+        )
+        .await
+        .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_app_remove() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let mut app_info = AppInfo {
+            app_id: "testxdc".to_string(),
+            tag_name: "v0.0.1".to_string(),
+            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+            ..Default::default()
+        };
+
+        super::create_app_info(&mut conn, &mut app_info, &Actor::default())
+            .await
+            .unwrap();
+        app_info.tag_name = "v0.0.3".to_string();
+        super::create_app_info(&mut conn, &mut app_info, &Actor::default())
+            .await
+            .unwrap();
+
+        app_info.tag_name = "v0.0.10".to_string();
+        super::create_app_info(&mut conn, &mut app_info, &Actor::default())
+            .await
+            .unwrap();
+
+        let serial = super::get_last_serial(&mut conn).await.unwrap();
+        super::remove_app(&mut conn, &app_info.app_id, &Actor::default())
+            .await
+            .unwrap();
+
+        let loaded_app_info = get_app_info_for_app_id(&mut conn, &app_info.app_id, Channel::Stable)
+            .await
+            .unwrap();
+
+        assert!(loaded_app_info.removed);
+        assert_eq!(loaded_app_info.tag_name, "v0.0.10".to_string());
+
+        let changed = super::get_changed_app_infos_since(&mut conn, serial, Channel::Stable)
+            .await
             .unwrap();
 
-        assert_eq!(super::get_app_infos(&mut conn).await.unwrap().len(), 1);
+        assert_eq!(changed[0].tag_name, "v0.0.10".to_string());
+        assert!(changed[0].removed)
+    }
 
-        let mut new_app_info = AppInfo {
-            tag_name: "v1.2.2".to_string(),
-            ..app_info.clone()
-        };
+    #[tokio::test]
+    async fn test_group_chats() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        let chat_id = ChatId::new(42);
 
-        let state = crate::utils::maybe_upgrade_xdc(&mut new_app_info, &mut conn, &dest)
+        assert_eq!(get_group_chat(&mut conn, chat_id).await.unwrap(), None);
+        create_group_chat(&mut conn, chat_id, "alice@example.org")
+            .await
+            .unwrap();
+        set_downloads_disabled(&mut conn, chat_id, true)
             .await
             .unwrap();
 
-        assert_eq!(state, AddType::Updated);
         assert_eq!(
-            super::get_changed_app_infos_since(&mut conn, 1)
-                .await
-                .unwrap(),
-            vec![new_app_info.clone()]
+            get_group_chat(&mut conn, chat_id).await.unwrap(),
+            Some(GroupChat {
+                chat_id: 42,
+                admin_addr: "alice@example.org".to_string(),
+                downloads_disabled: true,
+            })
         );
+    }
 
-        let state = crate::utils::maybe_upgrade_xdc(&mut new_app_info, &mut conn, &dest)
-            .await
-            .unwrap();
+    #[tokio::test]
+    async fn test_subscriptions() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        let alice = ContactId::new(10);
+        let bob = ContactId::new(11);
 
-        assert_eq!(state, AddType::Ignored);
+        add_subscription(&mut conn, alice, "poll").await.unwrap();
+        add_subscription(&mut conn, alice, "poll").await.unwrap();
+        add_subscription(&mut conn, bob, "poll").await.unwrap();
+        add_subscription(&mut conn, bob, "chess").await.unwrap();
+        remove_subscription(&mut conn, bob, "poll").await.unwrap();
+
+        assert_eq!(
+            get_subscribers(&mut conn, "poll").await.unwrap(),
+            vec![alice]
+        );
+        assert_eq!(
+            get_subscribers(&mut conn, "chess").await.unwrap(),
+            vec![bob]
+        );
+        assert!(get_subscribers(&mut conn, "other")
+            .await
+            .unwrap()
+            .is_empty());
     }
 
     #[tokio::test]
-    async fn test_app_exists() {
+    async fn test_take_unnotified_app_infos() {
         let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
         MIGRATOR.run(&mut conn).await.unwrap();
         set_config(&mut conn, &BotConfig::default()).await.unwrap();
+        assert!(take_unnotified_app_infos(&mut conn)
+            .await
+            .unwrap()
+            .is_empty());
 
         let mut app_info = AppInfo {
-            app_id: "testxdc".to_string(),
+            app_id: "poll".to_string(),
+            tag_name: "v1".to_string(),
+            xdc_blob_path: PathBuf::from("poll-v1.xdc"),
             ..Default::default()
         };
-
-        super::create_app_info(&mut conn, &mut app_info)
+        create_app_info(&mut conn, &mut app_info, &Actor::default())
             .await
             .unwrap();
+        let changed = take_unnotified_app_infos(&mut conn).await.unwrap();
+        assert_eq!(changed.len(), 2);
+        assert_eq!(changed[0].0, Channel::Stable);
+        assert_eq!(changed[0].1[0].tag_name, "v1");
 
-        assert!(super::app_exists(&mut conn, "testxdc").await.unwrap());
-        assert!(!super::app_exists(&mut conn, "testxdc2").await.unwrap());
+        // Versions are only taken once.
+        assert!(take_unnotified_app_infos(&mut conn)
+            .await
+            .unwrap()
+            .is_empty());
     }
 
     #[tokio::test]
-    async fn test_maybe_get_greater() {
+    async fn test_installed_apps() {
         let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
         MIGRATOR.run(&mut conn).await.unwrap();
-        set_config(&mut conn, &BotConfig::default()).await.unwrap();
-
-        let dest = env::temp_dir().join("example-xdcs");
-        create_dir(&dest).ok();
-
-        let mut app_info = AppInfo {
-            app_id: "testxdc".to_string(),
-            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
-            ..Default::default()
+        let alice = ContactId::new(10);
+        let bob = ContactId::new(11);
+        let apps = |list: &[(&str, &str)]| {
+            list.iter()
+                .map(|(app_id, tag_name)| (app_id.to_string(), tag_name.to_string()))
+                .collect_vec()
         };
 
-        crate::utils::maybe_upgrade_xdc(
-            &mut app_info,
+        set_installed_apps(
             &mut conn,
-            &env::temp_dir().join("example-xdcs"),
+            alice,
+            "alice@example.org",
+            &apps(&[("poll", "v1")]),
+            1,
+        )
+        .await
+        .unwrap();
+        set_installed_apps(
+            &mut conn,
+            bob,
+            "bob@example.org",
+            &apps(&[("poll", "v1"), ("chess", "v1")]),
+            2,
+        )
+        .await
+        .unwrap();
+        set_installed_apps(
+            &mut conn,
+            alice,
+            "alice@example.org",
+            &apps(&[("chess", "v2")]),
+            3,
         )
         .await
         .unwrap();
 
-        // test that file has been moved
-        assert!(dest.join("webxdc-2048-v1.2.1.xdc").exists());
+        let adoption = get_version_adoption(&mut conn).await.unwrap();
+        assert_eq!(
+            adoption
+                .iter()
+                .map(|v| (
+                    v.app_id.as_str(),
+                    v.tag_name.as_str(),
+                    v.installs,
+                    v.last_seen
+                ))
+                .collect_vec(),
+            vec![
+                ("chess", "v1", 1, 2),
+                ("chess", "v2", 1, 3),
+                ("poll", "v1", 1, 2)
+            ]
+        );
+
+        let installed = get_installed_apps(&mut conn, "chess").await.unwrap();
+        assert_eq!(installed.len(), 2);
+        assert_eq!(installed[0].addr, "alice@example.org");
+        assert_eq!(installed[0].tag_name, "v2");
+    }
+
+    #[tokio::test]
+    async fn test_channels() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        for (tag_name, channel) in [("v1", Channel::Stable), ("v2", Channel::Beta)] {
+            let mut app_info = AppInfo {
+                app_id: "testxdc".to_string(),
+                tag_name: tag_name.to_string(),
+                channel,
+                xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+                ..Default::default()
+            };
+            create_app_info(&mut conn, &mut app_info, &Actor::default())
+                .await
+                .unwrap();
+        }
+
+        let newest = |app_infos: Vec<AppInfo>| {
+            app_infos
+                .into_iter()
+                .map(|app_info| app_info.tag_name)
+                .collect_vec()
+        };
+        assert_eq!(
+            newest(
+                get_active_app_infos(&mut conn, Channel::Stable)
+                    .await
+                    .unwrap()
+            ),
+            vec!["v1"]
+        );
+        assert_eq!(
+            newest(
+                get_active_app_infos(&mut conn, Channel::Beta)
+                    .await
+                    .unwrap()
+            ),
+            vec!["v2"]
+        );
+        assert!(get_changed_app_infos_since(&mut conn, 1, Channel::Stable)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            get_app_info_for_app_id(&mut conn, "testxdc", Channel::Beta)
+                .await
+                .unwrap()
+                .tag_name,
+            "v2"
+        );
 
+        // Promoting the beta version to the stable channel adds a new version.
+        assert!(
+            app_tag_name_exists(&mut conn, "testxdc", "v2", Channel::Beta)
+                .await
+                .unwrap()
+        );
         assert!(
-            !maybe_get_greater_tag_name(&mut conn, &app_info.app_id, &app_info.tag_name)
+            !app_tag_name_exists(&mut conn, "testxdc", "v2", Channel::Stable)
                 .await
                 .unwrap()
         );
 
-        crate::utils::maybe_upgrade_xdc(
-            &mut AppInfo {
-                tag_name: "v1.2.1".to_string(),
-                app_id: "testxdc".to_string(),
-                ..app_info.clone()
-            },
+        remove_app(&mut conn, "testxdc", &Actor::default())
+            .await
+            .unwrap();
+        for channel in [Channel::Stable, Channel::Beta] {
+            let app_info = get_app_info_for_app_id(&mut conn, "testxdc", channel)
+                .await
+                .unwrap();
+            assert!(app_info.removed);
+        }
+
+        let alice = ContactId::new(10);
+        assert_eq!(
+            get_contact_channel(&mut conn, alice).await.unwrap(),
+            Channel::Stable
+        );
+        set_contact_channel(&mut conn, alice, Channel::Beta)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_contact_channel(&mut conn, alice).await.unwrap(),
+            Channel::Beta
+        );
+    }
+
+    #[tokio::test]
+    async fn test_app_visibility() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let contacts = Visibility::Contacts(vec!["alice@example.org".to_string()]);
+        set_app_visibility(&mut conn, "tool", &contacts)
+            .await
+            .unwrap();
+        set_app_visibility(&mut conn, "other", &Visibility::Group(12))
+            .await
+            .unwrap();
+        set_app_visibility(&mut conn, "other", &Visibility::Public)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            get_app_visibility(&mut conn, "tool").await.unwrap(),
+            contacts
+        );
+        assert_eq!(
+            get_app_visibility(&mut conn, "other").await.unwrap(),
+            Visibility::Public
+        );
+        assert_eq!(
+            get_app_visibilities(&mut conn).await.unwrap(),
+            vec![("tool".to_string(), contacts)]
+        );
+
+        reset_index(&mut conn, &Actor::default()).await.unwrap();
+        assert_eq!(get_resync_serial(&mut conn).await.unwrap(), 1);
+        assert_eq!(get_last_serial(&mut conn).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_curation() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        set_app_curation(&mut conn, "poll", Some(true), None)
+            .await
+            .unwrap();
+        set_app_curation(&mut conn, "chess", None, Some(5))
+            .await
+            .unwrap();
+        set_app_curation(&mut conn, "poll", None, Some(1))
+            .await
+            .unwrap();
+        set_app_curation(&mut conn, "tool", Some(true), None)
+            .await
+            .unwrap();
+        set_app_curation(&mut conn, "tool", Some(false), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_app_curations(&mut conn).await.unwrap(),
+            vec![
+                AppCuration {
+                    app_id: "chess".to_string(),
+                    featured: false,
+                    priority: 5
+                },
+                AppCuration {
+                    app_id: "poll".to_string(),
+                    featured: true,
+                    priority: 1
+                }
+            ]
+        );
+
+        let apps = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        add_to_collection(&mut conn, "Games", &apps(&["chess", "sudoku"]))
+            .await
+            .unwrap();
+        add_to_collection(&mut conn, "Essentials", &apps(&["poll"]))
+            .await
+            .unwrap();
+        add_to_collection(&mut conn, "Empty", &[]).await.unwrap();
+        add_to_collection(&mut conn, "Games", &apps(&["poll", "chess"]))
+            .await
+            .unwrap();
+        remove_from_collection(&mut conn, "Games", &apps(&["sudoku"]))
+            .await
+            .unwrap();
+        assert!(set_collection_position(&mut conn, "Essentials", -1)
+            .await
+            .unwrap());
+        assert!(!set_collection_position(&mut conn, "Unknown", 1)
+            .await
+            .unwrap());
+        assert_eq!(
+            get_collections(&mut conn).await.unwrap(),
+            vec![
+                Collection {
+                    name: "Essentials".to_string(),
+                    app_ids: apps(&["poll"])
+                },
+                Collection {
+                    name: "Games".to_string(),
+                    app_ids: apps(&["chess", "poll"])
+                },
+                Collection {
+                    name: "Empty".to_string(),
+                    app_ids: vec![]
+                },
+            ]
+        );
+
+        delete_collection(&mut conn, "Games").await.unwrap();
+        let collections = get_collections(&mut conn).await.unwrap();
+        assert_eq!(collections.len(), 2);
+        assert_eq!(collections[1].name, "Empty");
+    }
+
+    #[tokio::test]
+    async fn test_icons() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let mut app_info = AppInfo {
+            app_id: "poll".to_string(),
+            image: "data:image/png;base64,AAAA".to_string(),
+            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+            ..Default::default()
+        };
+        create_app_info(&mut conn, &mut app_info, &Actor::default())
+            .await
+            .unwrap();
+        let mut other = AppInfo {
+            app_id: "other".to_string(),
+            image: "data:image/png;base64,AAAA".to_string(),
+            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+            ..Default::default()
+        };
+        create_app_info(&mut conn, &mut other, &Actor::default())
+            .await
+            .unwrap();
+        assert_eq!(app_info.icon, other.icon);
+
+        // Icon of an older version stored inline.
+        sqlx::query(
+            "UPDATE app_infos SET icon = '', image = 'data:image/png;base64,BBBB' WHERE id = ?",
+        )
+        .bind(other.id)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        assert_eq!(move_inline_icons(&mut conn).await.unwrap(), 1);
+        assert_eq!(move_inline_icons(&mut conn).await.unwrap(), 0);
+        let other_icon = icon_hash("data:image/png;base64,BBBB");
+
+        let icons = get_icons(
             &mut conn,
-            &dest,
+            &[
+                app_info.icon.clone(),
+                "unknown".to_string(),
+                other_icon.clone(),
+            ],
         )
         .await
         .unwrap();
+        assert_eq!(
+            icons,
+            vec![
+                (
+                    app_info.icon.clone(),
+                    "data:image/png;base64,AAAA".to_string()
+                ),
+                (other_icon.clone(), "data:image/png;base64,BBBB".to_string())
+            ]
+        );
 
-        assert!(
-            maybe_get_greater_tag_name(&mut conn, &app_info.app_id, &app_info.tag_name)
+        delete_app_infos(&mut conn, &[app_info.id], &Actor::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            get_icons(&mut conn, &[app_info.icon.clone(), other_icon])
                 .await
                 .unwrap()
+                .len(),
+            1
         );
     }
 
     #[tokio::test]
-    async fn test_app_remove() {
+    async fn test_screenshots() {
         let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
         MIGRATOR.run(&mut conn).await.unwrap();
         set_config(&mut conn, &BotConfig::default()).await.unwrap();
 
+        let screenshots = vec![
+            "data:image/jpeg;base64,AAAA".to_string(),
+            "data:image/jpeg;base64,BBBB".to_string(),
+        ];
         let mut app_info = AppInfo {
-            app_id: "testxdc".to_string(),
-            tag_name: "v0.0.1".to_string(),
+            app_id: "poll".to_string(),
+            screenshots: screenshots.clone(),
+            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
             ..Default::default()
         };
-
-        super::create_app_info(&mut conn, &mut app_info)
+        create_app_info(&mut conn, &mut app_info, &Actor::default())
             .await
             .unwrap();
-        app_info.tag_name = "v0.0.3".to_string();
-        super::create_app_info(&mut conn, &mut app_info)
+        assert!(app_info.screenshots.is_empty());
+        assert_eq!(
+            get_screenshots(&mut conn, app_info.id).await.unwrap(),
+            screenshots
+        );
+
+        delete_app_infos(&mut conn, &[app_info.id], &Actor::default())
             .await
             .unwrap();
+        assert!(get_screenshots(&mut conn, app_info.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
 
-        app_info.tag_name = "v0.0.10".to_string();
-        super::create_app_info(&mut conn, &mut app_info)
+    #[tokio::test]
+    async fn test_pending_welcomes() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let contact_id = ContactId::new(10);
+        assert!(!take_pending_welcome(&mut conn, contact_id, 0)
+            .await
+            .unwrap());
+        add_pending_welcome(&mut conn, contact_id, 100)
             .await
             .unwrap();
+        assert!(has_pending_welcome(&mut conn, contact_id, 50)
+            .await
+            .unwrap());
+        assert!(take_pending_welcome(&mut conn, contact_id, 50)
+            .await
+            .unwrap());
+        assert!(!take_pending_welcome(&mut conn, contact_id, 50)
+            .await
+            .unwrap());
 
-        let serial = super::get_last_serial(&mut conn).await.unwrap();
-        super::remove_app(&mut conn, &app_info.app_id)
+        // Expired welcomes are removed without being reported.
+        add_pending_welcome(&mut conn, contact_id, 100)
             .await
             .unwrap();
+        assert!(!has_pending_welcome(&mut conn, contact_id, 150)
+            .await
+            .unwrap());
+        assert!(!take_pending_welcome(&mut conn, contact_id, 150)
+            .await
+            .unwrap());
+        assert!(!has_pending_welcome(&mut conn, contact_id, 0).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_invites() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let invite = Invite {
+            name: "conference".to_string(),
+            chat_id: 12,
+            created: 1,
+            revoked: false,
+            app_id: Some("webxdc-poll".to_string()),
+        };
+        add_invite(&mut conn, &invite).await.unwrap();
+        assert!(add_invite(&mut conn, &invite).await.is_err());
+        assert_eq!(
+            get_invite(&mut conn, "conference").await.unwrap(),
+            Some(invite)
+        );
+
+        assert!(is_invite_chat(&mut conn, ChatId::new(12)).await.unwrap());
+        assert!(!is_invite_chat(&mut conn, ChatId::new(13)).await.unwrap());
 
-        let loaded_app_info = get_app_info_for_app_id(&mut conn, &app_info.app_id)
+        set_invite_revoked(&mut conn, "conference", true)
             .await
             .unwrap();
+        assert!(
+            get_invite(&mut conn, "conference")
+                .await
+                .unwrap()
+                .unwrap()
+                .revoked
+        );
 
-        assert!(loaded_app_info.removed);
-        assert_eq!(loaded_app_info.tag_name, "v0.0.10".to_string());
+        for (contact_id, invite) in [(10, Some("conference")), (11, None), (10, None)] {
+            let join = InviteJoin {
+                addr: format!("{contact_id}@example.org"),
+                invite: invite.map(str::to_string),
+                joined: contact_id.into(),
+            };
+            add_invite_join(&mut conn, ContactId::new(contact_id), &join)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            get_invite_join_counts(&mut conn).await.unwrap(),
+            vec![(None, 1), (Some("conference".to_string()), 1)]
+        );
+        let joins = get_invite_joins(&mut conn, "conference").await.unwrap();
+        assert_eq!(joins.len(), 1);
+        assert_eq!(joins[0].addr, "10@example.org");
+        assert_eq!(get_invites(&mut conn).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let actor = Actor {
+            source: Source::Import,
+            user: "alice".to_string(),
+        };
+        let mut ids = Vec::new();
+        for (app_id, tag_name) in [("poll", "v1"), ("poll", "v2"), ("chess", "v1")] {
+            let mut app_info = AppInfo {
+                app_id: app_id.to_string(),
+                tag_name: tag_name.to_string(),
+                xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+                ..Default::default()
+            };
+            create_app_info(&mut conn, &mut app_info, &actor)
+                .await
+                .unwrap();
+            ids.push(app_info.id);
+        }
+        // The hash of the .xdc file is required.
+        let mut missing = AppInfo {
+            app_id: "missing".to_string(),
+            xdc_blob_path: PathBuf::from("example-xdcs/missing.xdc"),
+            ..Default::default()
+        };
+        assert!(create_app_info(&mut conn, &mut missing, &actor)
+            .await
+            .is_err());
+        remove_app(&mut conn, "poll", &actor).await.unwrap();
+        let gc = Actor {
+            source: Source::Gc,
+            user: String::new(),
+        };
+        delete_app_infos(&mut conn, &[ids[0]], &gc).await.unwrap();
+        reset_index(&mut conn, &Actor::default()).await.unwrap();
 
-        let changed = super::get_changed_app_infos_since(&mut conn, serial)
+        let records = get_audit_records(&mut conn, Some("poll"), None, None)
             .await
             .unwrap();
+        let summary = records
+            .iter()
+            .map(|record| {
+                (
+                    record.action.as_str(),
+                    record.previous_tag_name.as_deref(),
+                    record.tag_name.as_deref(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("create", None, Some("v1")),
+                ("create", Some("v1"), Some("v2")),
+                ("remove", Some("v2"), None),
+                ("delete", Some("v1"), None),
+                ("reset", None, None),
+            ]
+        );
+        assert_eq!(records[0].user, "alice");
+        assert_eq!(records[0].serial, 1);
+        assert!(records[0].file_hash.is_some());
+        assert_eq!(records[3].source, Source::Gc);
+        assert_eq!(records[3].serial, records[2].serial);
+        assert_eq!(records[4].user, "");
 
-        assert_eq!(changed[0].tag_name, "v0.0.10".to_string());
-        assert!(changed[0].removed)
+        assert_eq!(
+            get_audit_records(&mut conn, None, None, None)
+                .await
+                .unwrap()
+                .len(),
+            6
+        );
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        assert!(get_audit_records(&mut conn, None, Some(now + 1), None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(get_audit_records(&mut conn, None, None, Some(now - 60))
+            .await
+            .unwrap()
+            .is_empty());
     }
 }
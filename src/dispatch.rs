@@ -0,0 +1,251 @@
+//! Concurrent handling of Delta Chat events.
+//!
+//! Events of the same chat are handled one after another in the order they arrived,
+//! events of different chats are handled concurrently by a bounded number of workers,
+//! so a large download does not block the other users.
+//! When too many events are waiting, [Dispatcher::dispatch] waits for a free slot,
+//! so the event loop stops reading events until the workers catch up.
+
+use anyhow::Result;
+use futures::{future::BoxFuture, FutureExt};
+use log::error;
+use prometheus_client::metrics::gauge::Gauge;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    hash::Hash,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex, PoisonError},
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::AbortHandle,
+};
+
+/// Pending events of one key, handled by a single task.
+struct Lane {
+    /// Events waiting to be handled, oldest first.
+    jobs: VecDeque<Job>,
+
+    /// Task handling the events.
+    task: AbortHandle,
+}
+
+/// Handler of a dispatched event.
+struct Job {
+    /// Future handling the event.
+    future: BoxFuture<'static, ()>,
+
+    /// Slot in the queue, released when the event is handled.
+    _slot: OwnedSemaphorePermit,
+
+    /// Number of events waiting for a worker the event is counted in.
+    queued: Gauge,
+}
+
+/// Lanes by key.
+type Lanes<K> = Arc<Mutex<HashMap<K, Lane>>>;
+
+/// Runs event handlers with per-key ordering on a bounded number of workers.
+pub struct Dispatcher<K> {
+    /// Lanes with pending events.
+    lanes: Lanes<K>,
+
+    /// Permits to run a handler, one per worker.
+    workers: Arc<Semaphore>,
+
+    /// Permits to dispatch an event, one per event which is not handled yet.
+    slots: Arc<Semaphore>,
+
+    /// Maximum number of events which are not handled yet.
+    capacity: u32,
+}
+
+impl<K: Clone + Eq + Hash + Send + 'static> Dispatcher<K> {
+    /// Creates a dispatcher running at most `workers` handlers at once
+    /// and accepting at most `capacity` events which are not handled yet.
+    pub fn new(workers: usize, capacity: u32) -> Self {
+        Self {
+            lanes: Default::default(),
+            workers: Arc::new(Semaphore::new(workers)),
+            slots: Arc::new(Semaphore::new(capacity as usize)),
+            capacity,
+        }
+    }
+
+    /// Queues `future` to be run after the futures dispatched earlier with the same key.
+    ///
+    /// `queued` counts the event until a worker starts handling it.
+    /// Waits while the queue is full.
+    pub async fn dispatch(
+        &self,
+        key: K,
+        queued: Gauge,
+        future: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<()> {
+        let slot = self.slots.clone().acquire_owned().await?;
+        queued.inc();
+        let job = Job {
+            future: future.boxed(),
+            _slot: slot,
+            queued,
+        };
+        let mut lanes = self.lanes.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(lane) = lanes.get_mut(&key) {
+            lane.jobs.push_back(job);
+            return Ok(());
+        }
+        // The task waits for the lock, so it sees the lane inserted below.
+        let task = tokio::spawn(run_lane(
+            key.clone(),
+            self.lanes.clone(),
+            self.workers.clone(),
+        ));
+        lanes.insert(
+            key,
+            Lane {
+                jobs: VecDeque::from([job]),
+                task: task.abort_handle(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Waits until all dispatched events are handled.
+    pub async fn drain(&self) {
+        // Each event holds a slot until it is handled.
+        if let Ok(permits) = self.slots.acquire_many(self.capacity).await {
+            drop(permits);
+        }
+    }
+}
+
+impl<K> Drop for Dispatcher<K> {
+    /// Aborts the handlers which are still running or waiting.
+    fn drop(&mut self) {
+        let lanes = self.lanes.lock().unwrap_or_else(PoisonError::into_inner);
+        for lane in lanes.values() {
+            lane.task.abort();
+        }
+    }
+}
+
+/// Runs the events of the lane `key` one after another
+/// and removes the lane once it is empty.
+async fn run_lane<K: Eq + Hash>(key: K, lanes: Lanes<K>, workers: Arc<Semaphore>) {
+    loop {
+        let job = {
+            let mut lanes = lanes.lock().unwrap_or_else(PoisonError::into_inner);
+            match lanes.get_mut(&key).and_then(|lane| lane.jobs.pop_front()) {
+                Some(job) => job,
+                None => {
+                    lanes.remove(&key);
+                    return;
+                }
+            }
+        };
+        let Ok(_worker) = workers.acquire().await else {
+            return;
+        };
+        job.queued.dec();
+        // A panicking handler must not stall the other events of the chat.
+        if AssertUnwindSafe(job.future).catch_unwind().await.is_err() {
+            error!("Event handler panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    #[tokio::test]
+    async fn test_order_per_key() {
+        let queued: Gauge = Gauge::default();
+        let dispatcher = Dispatcher::new(4, 100);
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..20u64 {
+            let handled = handled.clone();
+            dispatcher
+                .dispatch(i % 2, queued.clone(), async move {
+                    tokio::time::sleep(Duration::from_millis(20 - i)).await;
+                    handled.lock().unwrap().push(i);
+                })
+                .await
+                .unwrap();
+        }
+        dispatcher.drain().await;
+
+        let handled = handled.lock().unwrap();
+        assert_eq!(handled.len(), 20);
+        for key in 0..2 {
+            let lane = handled
+                .iter()
+                .filter(|i| *i % 2 == key)
+                .copied()
+                .collect::<Vec<_>>();
+            assert_eq!(lane, (0..20).filter(|i| i % 2 == key).collect::<Vec<_>>());
+        }
+        assert_eq!(queued.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_across_keys() {
+        let dispatcher = Dispatcher::new(2, 100);
+        let notify = Arc::new(Notify::new());
+
+        // The first event only finishes once the event of another chat was handled.
+        let waiting = notify.clone();
+        dispatcher
+            .dispatch("alice", Default::default(), async move {
+                waiting.notified().await
+            })
+            .await
+            .unwrap();
+        let notifying = notify.clone();
+        dispatcher
+            .dispatch(
+                "bob",
+                Default::default(),
+                async move { notifying.notify_one() },
+            )
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), dispatcher.drain())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let dispatcher = Dispatcher::new(1, 1);
+        let notify = Arc::new(Notify::new());
+
+        let waiting = notify.clone();
+        dispatcher
+            .dispatch(
+                1,
+                Default::default(),
+                async move { waiting.notified().await },
+            )
+            .await
+            .unwrap();
+        // The queue is full until the first event is handled.
+        assert!(tokio::time::timeout(
+            Duration::from_millis(50),
+            dispatcher.dispatch(2, Default::default(), async {})
+        )
+        .await
+        .is_err());
+
+        notify.notify_one();
+        dispatcher
+            .dispatch(2, Default::default(), async {})
+            .await
+            .unwrap();
+        dispatcher.drain().await;
+    }
+}
@@ -0,0 +1,336 @@
+//! Garbage collection of old app versions.
+//!
+//! Every import keeps the previous versions of an app in the database
+//! and the `xdcs` directory. Garbage collection removes versions
+//! which are not covered by the [RetentionPolicy] and deletes
+//! .xdc files no longer referenced by the app index.
+//! Files younger than [BLOB_GRACE_PERIOD] are kept,
+//! they may have been copied by an import which did not add them to the index yet.
+
+use anyhow::{Context as _, Result};
+use clap::Args;
+use itertools::Itertools;
+use log::info;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use time::OffsetDateTime;
+use tokio::fs;
+
+use crate::{
+    audit::Actor,
+    db,
+    request_handlers::{AppInfo, Channel},
+};
+
+/// Number of seconds in a day.
+const DAY: i64 = 24 * 60 * 60;
+
+/// Minimum age of an unreferenced .xdc file before it is deleted.
+pub const BLOB_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Retention policy for old app versions.
+///
+/// The rules apply to the versions of each app on each release channel separately.
+/// The newest version on a channel is always kept.
+/// Older versions are kept as long as they satisfy at least one of the rules,
+/// starting from the newest version.
+/// Once a version is not kept, all versions older than it on the channel are removed too.
+/// The newest version which is not removed and visible on a channel is always kept,
+/// so viewers of the channel keep the app.
+/// Apps removed from the store on all channels are deleted completely.
+///
+/// If no rule is set, nothing is removed.
+#[derive(Args, Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionPolicy {
+    /// Number of versions to keep per app and channel, including the newest one.
+    #[arg(long)]
+    pub keep_versions: Option<usize>,
+
+    /// Keep versions released less than this number of days ago.
+    #[arg(long)]
+    pub max_age_days: Option<u32>,
+}
+
+impl RetentionPolicy {
+    /// Returns the policy with the rules of `other` used for all rules not set in `self`.
+    pub fn or(&self, other: &RetentionPolicy) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_versions: self.keep_versions.or(other.keep_versions),
+            max_age_days: self.max_age_days.or(other.max_age_days),
+        }
+    }
+
+    /// Returns true if no retention rule is set.
+    pub fn is_empty(&self) -> bool {
+        self.keep_versions.is_none() && self.max_age_days.is_none()
+    }
+
+    /// Returns true if the `index`-th newest version released at `date` should be kept.
+    fn keeps(&self, index: usize, date: i64, now: i64) -> bool {
+        if index == 0 || self.is_empty() {
+            return true;
+        }
+        let by_count = self.keep_versions.map_or(false, |count| index < count);
+        let by_age = self
+            .max_age_days
+            .map_or(false, |days| now - date < i64::from(days) * DAY);
+        by_count || by_age
+    }
+}
+
+/// Result of a garbage collection run.
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Number of app versions removed from the database.
+    pub removed_versions: usize,
+
+    /// .xdc files removed from the `xdcs` directory.
+    pub removed_blobs: Vec<PathBuf>,
+}
+
+/// Returns the ids of the versions of one app which are not covered by `policy`.
+fn get_expired_versions(
+    mut versions: Vec<AppInfo>,
+    policy: &RetentionPolicy,
+    now: i64,
+) -> Vec<db::RecordId> {
+    versions.sort_by_key(|app_info| std::cmp::Reverse(app_info.id));
+    let channels = [Channel::Stable, Channel::Beta];
+    let newest_visible = |channel: Channel, skip_removed: bool| {
+        versions
+            .iter()
+            .find(|app_info| app_info.channel <= channel && !(skip_removed && app_info.removed))
+    };
+    if channels
+        .into_iter()
+        .all(|channel| newest_visible(channel, false).map_or(true, |app_info| app_info.removed))
+    {
+        return versions.iter().map(|app_info| app_info.id).collect();
+    }
+
+    let mut kept: HashSet<db::RecordId> = channels
+        .into_iter()
+        .filter_map(|channel| newest_visible(channel, true))
+        .map(|app_info| app_info.id)
+        .collect();
+    for channel in channels {
+        kept.extend(
+            versions
+                .iter()
+                .filter(|app_info| app_info.channel == channel)
+                .enumerate()
+                .take_while(|(index, app_info)| policy.keeps(*index, app_info.date, now))
+                .map(|(_, app_info)| app_info.id),
+        );
+    }
+    versions
+        .iter()
+        .map(|app_info| app_info.id)
+        .filter(|id| !kept.contains(id))
+        .collect()
+}
+
+/// Removes app versions not covered by `policy`
+/// and all .xdc files in `xdcs_dir` which are not referenced anymore
+/// and were last modified at least `min_blob_age` ago.
+///
+/// The removed versions are recorded in the audit log with `actor`.
+pub async fn collect_garbage(
+    conn: &mut SqliteConnection,
+    xdcs_dir: &Path,
+    policy: &RetentionPolicy,
+    actor: &Actor,
+    min_blob_age: Duration,
+) -> Result<GcReport> {
+    let mut report = GcReport::default();
+    if policy.is_empty() {
+        info!("No retention policy set, keeping all app versions");
+    } else {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let expired = db::get_app_infos(conn)
+            .await?
+            .into_iter()
+            .into_group_map_by(|app_info| app_info.app_id.clone())
+            .into_values()
+            .flat_map(|versions| get_expired_versions(versions, policy, now))
+            .collect_vec();
+        db::delete_app_infos(conn, &expired, actor).await?;
+        report.removed_versions = expired.len();
+    }
+
+    let referenced: HashSet<PathBuf> = HashSet::from_iter(db::get_xdc_blob_paths(conn).await?);
+    let mut entries = fs::read_dir(xdcs_dir)
+        .await
+        .with_context(|| format!("failed to read {}", xdcs_dir.display()))?;
+    let now = SystemTime::now();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let metadata = entry.metadata().await?;
+        let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+        if metadata.is_file()
+            && path.extension().map_or(false, |ext| ext == "xdc")
+            && !referenced.contains(&path)
+            && age >= min_blob_age
+        {
+            fs::remove_file(&path)
+                .await
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+            report.removed_blobs.push(path);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::{audit::Source, bot::BotConfig, db::MIGRATOR};
+    use sqlx::Connection;
+
+    #[test]
+    fn test_retention_policy() {
+        let now = 100 * DAY;
+        let policy = RetentionPolicy {
+            keep_versions: Some(2),
+            max_age_days: Some(10),
+        };
+        assert!(policy.keeps(0, 0, now));
+        assert!(policy.keeps(1, 0, now));
+        assert!(!policy.keeps(2, 0, now));
+        assert!(policy.keeps(5, 95 * DAY, now));
+        assert!(RetentionPolicy::default().keeps(5, 0, now));
+    }
+
+    #[test]
+    fn test_expired_versions_per_channel() {
+        let version = |id, channel, removed| AppInfo {
+            id,
+            channel,
+            removed,
+            ..Default::default()
+        };
+        let policy = RetentionPolicy {
+            keep_versions: Some(1),
+            max_age_days: None,
+        };
+
+        // Beta versions newer than the stable ones do not expire the stable version.
+        let versions = vec![
+            version(1, Channel::Stable, false),
+            version(2, Channel::Stable, false),
+            version(3, Channel::Beta, false),
+            version(4, Channel::Beta, false),
+        ];
+        assert_eq!(get_expired_versions(versions, &policy, 0), vec![3, 1]);
+
+        // The newest version which is not removed is kept on each channel.
+        let versions = vec![
+            version(1, Channel::Stable, false),
+            version(2, Channel::Stable, true),
+            version(3, Channel::Beta, false),
+        ];
+        assert!(get_expired_versions(versions, &policy, 0).is_empty());
+
+        // Apps removed on all channels are deleted completely.
+        let versions = vec![
+            version(1, Channel::Stable, false),
+            version(2, Channel::Beta, true),
+            version(3, Channel::Stable, true),
+        ];
+        assert_eq!(get_expired_versions(versions, &policy, 0), vec![3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_collect_garbage() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        db::set_config(&mut conn, &BotConfig::default())
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let xdcs_dir = dir.path().to_path_buf();
+
+        for tag_name in ["v1", "v2", "v3"] {
+            let xdc_blob_path = xdcs_dir.join(format!("testxdc-{tag_name}.xdc"));
+            std::fs::write(&xdc_blob_path, "").unwrap();
+            let mut app_info = AppInfo {
+                app_id: "testxdc".to_string(),
+                tag_name: tag_name.to_string(),
+                xdc_blob_path,
+                ..Default::default()
+            };
+            db::create_app_info(&mut conn, &mut app_info, &Actor::default())
+                .await
+                .unwrap();
+        }
+        let mut removed_app = AppInfo {
+            app_id: "removedxdc".to_string(),
+            xdc_blob_path: PathBuf::from("example-xdcs/webxdc-2048-v1.2.1.xdc"),
+            ..Default::default()
+        };
+        db::create_app_info(&mut conn, &mut removed_app, &Actor::default())
+            .await
+            .unwrap();
+        db::remove_app(&mut conn, "removedxdc", &Actor::default())
+            .await
+            .unwrap();
+        let removal_serial = db::get_last_serial(&mut conn).await.unwrap();
+
+        let policy = RetentionPolicy {
+            keep_versions: Some(2),
+            max_age_days: None,
+        };
+        let gc = Actor {
+            source: Source::Gc,
+            user: String::new(),
+        };
+        // Files of a running import are kept.
+        let report = collect_garbage(&mut conn, &xdcs_dir, &policy, &gc, BLOB_GRACE_PERIOD)
+            .await
+            .unwrap();
+        assert_eq!(report.removed_versions, 2);
+        assert!(report.removed_blobs.is_empty());
+
+        let report = collect_garbage(&mut conn, &xdcs_dir, &policy, &gc, Duration::ZERO)
+            .await
+            .unwrap();
+
+        assert_eq!(report.removed_versions, 0);
+        assert_eq!(report.removed_blobs, vec![xdcs_dir.join("testxdc-v1.xdc")]);
+        let tag_names = db::get_app_infos(&mut conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|app_info| app_info.tag_name)
+            .collect_vec();
+        assert_eq!(tag_names, vec!["v2", "v3"]);
+        assert_eq!(
+            db::get_resync_serial(&mut conn).await.unwrap(),
+            removal_serial
+        );
+        let mut known = db::get_known_app_ids(&mut conn).await.unwrap();
+        known.sort();
+        assert_eq!(known, vec!["removedxdc", "testxdc"]);
+        let deletions = db::get_audit_records(&mut conn, None, None, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|record| record.source == Source::Gc)
+            .map(|record| (record.action, record.app_id))
+            .collect_vec();
+        assert_eq!(
+            deletions,
+            vec![
+                ("delete".to_string(), Some("testxdc".to_string())),
+                ("delete".to_string(), Some("removedxdc".to_string()))
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! App icons.
+//!
+//! Icons are stored once per content hash and not sent with the app index.
+//! [AppInfo](crate::request_handlers::AppInfo)s only contain the hash of the icon,
+//! the frontend requests the icons it has not cached yet with a `GetIcons` request.
+//!
+//! Icons are downscaled to at most [ICON_SIZE] pixels and re-encoded as PNG at import.
+
+use anyhow::{Context as _, Result};
+use async_zip::tokio::read::fs::ZipFileReader;
+use image::ImageOutputFormat;
+use sha2::{Digest, Sha256};
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+
+use crate::{db, images::normalize_image, utils::read_vec};
+
+/// Maximum width and height of icons in pixels.
+pub const ICON_SIZE: u32 = 128;
+
+/// Maximum total size of the icons or screenshots sent in a single response.
+/// Larger requests are answered with several responses.
+pub const MAX_BATCH_SIZE: usize = 100_000;
+
+/// Returns the hex encoded SHA-256 hash of an icon encoded as a data URL.
+pub fn icon_hash(data: &str) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Reads the `icon.png` or `icon.jpg` of an .xdc file
+/// and returns it normalized as a data URL.
+pub async fn read_icon(reader: &ZipFileReader) -> Result<String> {
+    let index = reader
+        .file()
+        .entries()
+        .iter()
+        .position(|entry| {
+            let name = entry.entry().filename().as_str().unwrap_or_default();
+            name == "icon.png" || name == "icon.jpg"
+        })
+        .context("Could not find image")?;
+    let data = read_vec(reader, index).await?;
+    normalize_icon(&data)
+}
+
+/// Decodes a PNG or JPEG icon, downscales it to fit into [ICON_SIZE] pixels
+/// and returns it encoded as a PNG data URL.
+///
+/// Fails if the image is corrupt or has an unsupported format.
+pub fn normalize_icon(data: &[u8]) -> Result<String> {
+    normalize_image(data, ICON_SIZE, ImageOutputFormat::Png).context("Failed to decode icon")
+}
+
+/// Returns the requested icons split into batches of at most [MAX_BATCH_SIZE] bytes.
+/// Icons larger than [MAX_BATCH_SIZE] are sent in a batch of their own.
+pub async fn get_icon_batches(
+    conn: &mut SqliteConnection,
+    hashes: &[String],
+) -> Result<Vec<HashMap<String, String>>> {
+    Ok(into_batches(db::get_icons(conn, hashes).await?))
+}
+
+/// Splits icons into batches of at most [MAX_BATCH_SIZE] bytes.
+fn into_batches(icons: Vec<(String, String)>) -> Vec<HashMap<String, String>> {
+    let mut batches = Vec::new();
+    let mut batch = HashMap::new();
+    let mut batch_size = 0;
+    for (hash, data) in icons {
+        if !batch.is_empty() && batch_size + data.len() > MAX_BATCH_SIZE {
+            batches.push(std::mem::take(&mut batch));
+            batch_size = 0;
+        }
+        batch_size += data.len();
+        batch.insert(hash, data);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Encodes an image of the given size as PNG.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        image::DynamicImage::new_rgba8(width, height)
+            .write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png)
+            .unwrap();
+        data
+    }
+
+    /// Decodes a PNG data URL.
+    fn decode(data_url: &str) -> image::DynamicImage {
+        let base64 = data_url.strip_prefix("data:image/png;base64,").unwrap();
+        image::load_from_memory(&base64::decode(base64).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_normalize_icon() {
+        let icon = decode(&normalize_icon(&png(512, 256)).unwrap());
+        assert_eq!((icon.width(), icon.height()), (ICON_SIZE, ICON_SIZE / 2));
+
+        // Small icons are not upscaled.
+        let icon = decode(&normalize_icon(&png(64, 64)).unwrap());
+        assert_eq!((icon.width(), icon.height()), (64, 64));
+
+        assert!(normalize_icon(b"not an image").is_err());
+        assert!(normalize_icon(&png(512, 512)[..100]).is_err());
+    }
+
+    #[test]
+    fn test_into_batches() {
+        let icon = |hash: &str, len: usize| (hash.to_string(), "a".repeat(len));
+        assert!(into_batches(vec![]).is_empty());
+
+        let batches = into_batches(vec![
+            icon("a", MAX_BATCH_SIZE / 2),
+            icon("b", MAX_BATCH_SIZE / 2),
+            icon("c", MAX_BATCH_SIZE * 2),
+            icon("d", 10),
+        ]);
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].len(), 2);
+        assert!(batches[1].contains_key("c"));
+        assert!(batches[2].contains_key("d"));
+    }
+}
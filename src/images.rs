@@ -0,0 +1,79 @@
+//! Normalization of the images shipped with apps.
+//!
+//! Icons and screenshots come from untrusted .xdc files,
+//! so they are decoded with limits on their dimensions and memory usage
+//! before they are downscaled and re-encoded.
+
+use anyhow::{bail, Result};
+use base64::encode;
+use image::{
+    imageops::FilterType,
+    io::{Limits, Reader},
+    DynamicImage, ImageOutputFormat,
+};
+use std::io::Cursor;
+
+/// Maximum width and height of a decoded image in pixels.
+const MAX_IMAGE_SIZE: u32 = 8192;
+
+/// Maximum number of bytes allocated to decode an image.
+const MAX_IMAGE_ALLOC: u64 = 128 * 1024 * 1024;
+
+/// Decodes a PNG or JPEG image, downscales it to fit into `size` pixels
+/// and returns it encoded in `format` as a data URL.
+///
+/// Fails if the image is corrupt, has an unsupported format
+/// or exceeds [MAX_IMAGE_SIZE] or [MAX_IMAGE_ALLOC].
+pub fn normalize_image(data: &[u8], size: u32, format: ImageOutputFormat) -> Result<String> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SIZE);
+    limits.max_image_height = Some(MAX_IMAGE_SIZE);
+    limits.max_alloc = Some(MAX_IMAGE_ALLOC);
+    let mut reader = Reader::new(Cursor::new(data)).with_guessed_format()?;
+    reader.limits(limits);
+    let mut image = reader.decode()?;
+    if image.width() > size || image.height() > size {
+        image = image.resize(size, size, FilterType::Lanczos3);
+    }
+    let mime_type = match format {
+        ImageOutputFormat::Png => "image/png",
+        ImageOutputFormat::Jpeg(_) => {
+            // JPEG does not support transparency.
+            image = DynamicImage::ImageRgb8(image.to_rgb8());
+            "image/jpeg"
+        }
+        _ => bail!("Unsupported output format {format:?}"),
+    };
+    let mut encoded = Vec::new();
+    image.write_to(&mut Cursor::new(&mut encoded), format)?;
+    Ok(format!("data:{mime_type};base64,{}", encode(&encoded)))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Encodes an image of the given size as PNG.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        DynamicImage::new_luma8(width, height)
+            .write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_normalize_image() {
+        let data_url = normalize_image(&png(64, 32), 16, ImageOutputFormat::Png).unwrap();
+        let base64 = data_url.strip_prefix("data:image/png;base64,").unwrap();
+        let image = image::load_from_memory(&base64::decode(base64).unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (16, 8));
+
+        let data_url = normalize_image(&png(64, 32), 16, ImageOutputFormat::Jpeg(80)).unwrap();
+        assert!(data_url.starts_with("data:image/jpeg;base64,"));
+
+        // Images exceeding the limits are not decoded.
+        assert!(normalize_image(&png(MAX_IMAGE_SIZE + 1, 1), 16, ImageOutputFormat::Png).is_err());
+    }
+}
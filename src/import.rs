@@ -2,7 +2,6 @@
 
 use anyhow::{bail, Context as _, Result};
 use async_zip::tokio::read::fs::ZipFileReader;
-use base64::encode;
 use futures::future::join_all;
 use serde::Deserialize;
 use sqlx::SqliteConnection;
@@ -14,9 +13,12 @@ use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tokio::fs::{self, File};
 
 use crate::{
+    audit::Actor,
     db,
-    request_handlers::AppInfo,
-    utils::{maybe_upgrade_xdc, read_vec, AddType},
+    icons::read_icon,
+    request_handlers::{AppInfo, Channel},
+    screenshots::{read_screenshot_files, read_screenshots},
+    utils::{maybe_upgrade_xdc, AddType},
 };
 
 /// Structure of the `manifest.toml` stored in .xdc files.
@@ -42,13 +44,23 @@ pub struct WebxdcManifest {
 
     /// Relative path from the sources.ini file to the cached xdc.
     pub cache_relname: PathBuf,
+
+    /// Relative paths from the `xdcget.lock` to screenshot files.
+    /// If empty, the screenshots in the .xdc file are used.
+    #[serde(default)]
+    pub screenshots: Vec<PathBuf>,
 }
 
-#[allow(clippy::missing_docs_in_private_items)]
+/// Imports the apps listed in the `xdcget.lock` in `path` into the channel.
+///
+/// Importing into the stable channel removes the stable apps
+/// which are not listed anymore.
 pub async fn import_many(
     path: &Path,
     xdcs_path: PathBuf,
     conn: &mut SqliteConnection,
+    channel: Channel,
+    actor: &Actor,
 ) -> Result<()> {
     let xdcget_lock = fs::read_to_string(path.join("xdcget.lock"))
         .await
@@ -56,24 +68,28 @@ pub async fn import_many(
     let xdc_metas: HashMap<String, WebxdcManifest> = toml::from_str(&xdcget_lock)?;
 
     let new_app_ids = HashSet::<_, RandomState>::from_iter(xdc_metas.keys().cloned());
-    let curr_app_ids = HashSet::<_, RandomState>::from_iter(
-        db::get_active_app_infos(conn)
-            .await?
-            .into_iter()
-            .map(|a| a.app_id),
-    );
+    let curr_app_ids = match channel {
+        Channel::Stable => HashSet::<_, RandomState>::from_iter(
+            db::get_active_app_infos(conn, Channel::Stable)
+                .await?
+                .into_iter()
+                .map(|a| a.app_id),
+        ),
+        Channel::Beta => HashSet::new(),
+    };
     let removed_app_ids = curr_app_ids.difference(&new_app_ids);
 
     let mut removed = vec![];
     for app_id in removed_app_ids {
-        let app_info = db::get_app_info_for_app_id(conn, app_id).await?;
-        db::remove_app(conn, &app_info.app_id).await?;
+        let app_info = db::get_app_info_for_app_id(conn, app_id, Channel::Stable).await?;
+        db::remove_app(conn, &app_info.app_id, actor).await?;
         fs::remove_file(&app_info.xdc_blob_path).await?;
         removed.push(app_info.xdc_blob_path);
     }
 
     let mut xdcs = vec![];
     for xdc in xdc_metas.into_values() {
+        let dir = path.to_path_buf();
         let path = PathBuf::from(path).join(&xdc.cache_relname);
         xdcs.push(tokio::spawn(async move {
             // compute file size
@@ -87,28 +103,15 @@ pub async fn import_many(
             )?;
             // extract icon
             let reader = ZipFileReader::new(&path).await?;
-            let entries = reader.file().entries();
-            let image = entries
-                .iter()
-                .enumerate()
-                .map(|(index, entry)| {
-                    (index, entry.entry().filename().as_str().unwrap_or_default())
-                })
-                .find(|(_, name)| *name == "icon.png" || *name == "icon.jpg");
-            let image = if let Some((index, name)) = image {
-                let res = read_vec(&reader, index).await?;
-                let mut extension = name
-                    .split('.')
-                    .nth(1)
-                    .context(format!("Can't extract file extension from {name}"))?;
-                if extension == "jpg" {
-                    extension = "jpeg"
-                }
-                let base64 = encode(&res);
-                format!("data:image/{extension};base64,{base64}")
+            let image = read_icon(&reader)
+                .await
+                .with_context(|| format!("Invalid icon in {}", path.display()))?;
+            let screenshots = if xdc.screenshots.is_empty() {
+                read_screenshots(&reader).await
             } else {
-                bail!("Could not find image for {}", path.display())
-            };
+                read_screenshot_files(&dir, &xdc.screenshots).await
+            }
+            .with_context(|| format!("Invalid screenshots for {}", path.display()))?;
 
             Ok(AppInfo {
                 id: 0,
@@ -117,11 +120,14 @@ pub async fn import_many(
                 date: OffsetDateTime::parse(&xdc.date, &Rfc3339)?.unix_timestamp(),
                 name: xdc.name,
                 source_code_url: xdc.source_code_url,
+                icon: String::new(),
                 image,
+                screenshots,
                 description: xdc.description,
                 xdc_blob_path: path,
                 size,
                 removed: false,
+                channel,
             })
         }))
     }
@@ -140,15 +146,17 @@ pub async fn import_many(
     for file in xdcs.into_iter() {
         let app_info = file?;
         match app_info {
-            Ok(mut app_info) => match maybe_upgrade_xdc(&mut app_info, conn, &xdcs_path).await {
-                Ok(AddType::Added) => added.push(app_info.xdc_blob_path),
-                Ok(AddType::Updated) => updated.push(app_info.xdc_blob_path),
-                Ok(AddType::Ignored) => ignored.push(app_info.xdc_blob_path),
-                Err(e) => {
-                    eprintln!("{e:#}");
-                    failed += 1;
+            Ok(mut app_info) => {
+                match maybe_upgrade_xdc(&mut app_info, conn, &xdcs_path, actor).await {
+                    Ok(AddType::Added) => added.push(app_info.xdc_blob_path),
+                    Ok(AddType::Updated) => updated.push(app_info.xdc_blob_path),
+                    Ok(AddType::Ignored) => ignored.push(app_info.xdc_blob_path),
+                    Err(e) => {
+                        eprintln!("{e:#}");
+                        failed += 1;
+                    }
                 }
-            },
+            }
             Err(e) => {
                 eprintln!("{e:#}");
                 failed += 1;
@@ -178,7 +186,13 @@ pub async fn import_many(
 /// Add a single webxdc to the store
 /// - Add it to the db
 /// - Copy it into the `dest` location
-pub async fn import_one(file: &Path, dest: &Path, conn: &mut SqliteConnection) -> Result<AddType> {
+pub async fn import_one(
+    file: &Path,
+    dest: &Path,
+    conn: &mut SqliteConnection,
+    channel: Channel,
+    actor: &Actor,
+) -> Result<AddType> {
     if !file
         .to_str()
         .context("can't convert to str")?
@@ -190,7 +204,8 @@ pub async fn import_one(file: &Path, dest: &Path, conn: &mut SqliteConnection) -
     let mut app_info = AppInfo::from_xdc(file)
         .await
         .context(anyhow::anyhow!("Failed to load {}", file.display()))?;
+    app_info.channel = channel;
 
     // Add it to the db
-    maybe_upgrade_xdc(&mut app_info, conn, dest).await
+    maybe_upgrade_xdc(&mut app_info, conn, dest, actor).await
 }
@@ -0,0 +1,202 @@
+//! Named invite QR codes and the contacts who joined through the invites.
+//!
+//! Besides the default invite created at setup, the operator can create
+//! named invites, e.g. per campaign or partner, with the `invite` command.
+//! Each named invite is the SecureJoin QR code of a group of its own,
+//! so it can be rotated or revoked without affecting the other invites.
+//! Delta Chat adds a contact scanning the QR code to the group,
+//! which tells the bot through which invite the contact joined.
+//! The contact is removed from the group right away,
+//! so the contacts who joined through an invite do not see each other.
+//! Deep-link invites carry an app which is sent to the contacts joining through them.
+
+use anyhow::{bail, Context as _, Result};
+use clap::ValueEnum;
+use deltachat::{
+    chat::{self, ChatId, ProtectionStatus},
+    contact::{Contact, ContactId},
+    context::Context,
+    qr::{check_qr, set_config_from_qr, Qr},
+    securejoin,
+};
+use log::info;
+use qrcode_generator::QrCodeEcc;
+use sqlx::SqliteConnection;
+use std::path::Path;
+use time::OffsetDateTime;
+
+use crate::{
+    bot::State,
+    db::{self, Invite, InviteJoin},
+    messages,
+};
+
+/// Format of an exported invite QR code.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum QrFormat {
+    /// PNG image.
+    #[default]
+    Png,
+
+    /// SVG image.
+    Svg,
+
+    /// Text encoded in the QR code.
+    Text,
+}
+
+impl QrFormat {
+    /// File extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            QrFormat::Png => "png",
+            QrFormat::Svg => "svg",
+            QrFormat::Text => "txt",
+        }
+    }
+}
+
+/// Makes Delta Chat reject the tokens of the invite QR code `qr`.
+async fn withdraw_qr(context: &Context, qr: &str) -> Result<()> {
+    match check_qr(context, qr).await? {
+        Qr::WithdrawVerifyGroup { .. } => set_config_from_qr(context, qr).await,
+        Qr::ReviveVerifyGroup { .. } => Ok(()),
+        _ => bail!("{qr} is not an invite QR code of the bot"),
+    }
+}
+
+/// Creates a named invite with a new group, optionally a deep link to the app `app_id`.
+pub async fn create_invite(
+    conn: &mut SqliteConnection,
+    context: &Context,
+    name: &str,
+    app_id: Option<&str>,
+) -> Result<()> {
+    if db::get_invite(conn, name).await?.is_some() {
+        bail!("Invite {name} already exists");
+    }
+    let chat_id = chat::create_group_chat(
+        context,
+        ProtectionStatus::Unprotected,
+        messages::invite_group_name(),
+    )
+    .await?;
+    let invite = Invite {
+        name: name.to_string(),
+        chat_id: chat_id.to_u32(),
+        created: OffsetDateTime::now_utc().unix_timestamp(),
+        revoked: false,
+        app_id: app_id.map(str::to_string),
+    };
+    db::add_invite(conn, &invite).await?;
+    Ok(())
+}
+
+/// Replaces the tokens of an invite, previously exported QR codes stop working.
+///
+/// Rotating a revoked invite makes it usable again.
+pub async fn rotate_invite(
+    conn: &mut SqliteConnection,
+    context: &Context,
+    name: &str,
+) -> Result<()> {
+    let invite = get_existing_invite(conn, name).await?;
+    if !invite.revoked {
+        withdraw_qr(context, &invite_qr(context, &invite).await?).await?;
+    }
+    db::set_invite_revoked(conn, name, false).await?;
+    Ok(())
+}
+
+/// Revokes an invite, contacts can no longer join through it.
+pub async fn revoke_invite(
+    conn: &mut SqliteConnection,
+    context: &Context,
+    name: &str,
+) -> Result<()> {
+    let invite = get_existing_invite(conn, name).await?;
+    if !invite.revoked {
+        withdraw_qr(context, &invite_qr(context, &invite).await?).await?;
+    }
+    db::set_invite_revoked(conn, name, true).await?;
+    Ok(())
+}
+
+/// Returns the invite with the given name or an error if it does not exist.
+pub async fn get_existing_invite(conn: &mut SqliteConnection, name: &str) -> Result<Invite> {
+    db::get_invite(conn, name)
+        .await?
+        .with_context(|| format!("Invite {name} does not exist"))
+}
+
+/// Returns the text of the QR code of an invite.
+///
+/// Delta Chat creates new tokens for the group if the previous ones were withdrawn,
+/// so this must not be called for revoked invites.
+pub async fn invite_qr(context: &Context, invite: &Invite) -> Result<String> {
+    securejoin::get_securejoin_qr(context, Some(ChatId::new(invite.chat_id))).await
+}
+
+/// Exports the QR code `qr` in the given format.
+pub fn export_qr(qr: &str, format: QrFormat, dest_path: &Path) -> Result<()> {
+    match format {
+        QrFormat::Png => qrcode_generator::to_png_to_file(qr, QrCodeEcc::Low, 1024, dest_path)?,
+        QrFormat::Svg => {
+            qrcode_generator::to_svg_to_file(qr, QrCodeEcc::Low, 1024, None::<&str>, dest_path)?
+        }
+        QrFormat::Text => std::fs::write(dest_path, qr)?,
+    }
+    Ok(())
+}
+
+/// Records the invite through which a contact completed a SecureJoin,
+/// removes the contact from the group of the invite and returns the invite.
+///
+/// Contacts who are not in the group of a named invite joined through the default invite.
+pub async fn record_join(
+    context: &Context,
+    state: &State,
+    contact_id: ContactId,
+) -> Result<Option<Invite>> {
+    let conn = &mut *state.db_connection().await?;
+    let mut invite = None;
+    for candidate in db::get_invites(conn).await? {
+        let chat_id = ChatId::new(candidate.chat_id);
+        if chat::is_contact_in_chat(context, chat_id, contact_id).await? {
+            chat::remove_contact_from_chat(context, chat_id, contact_id).await?;
+            invite = Some(candidate);
+            break;
+        }
+    }
+    let name = invite.as_ref().map(|invite| invite.name.clone());
+    info!(
+        "{contact_id} joined through invite {}",
+        name.as_deref().unwrap_or("default")
+    );
+    let join = InviteJoin {
+        addr: Contact::get_by_id(context, contact_id)
+            .await?
+            .get_addr()
+            .to_string(),
+        invite: name,
+        joined: OffsetDateTime::now_utc().unix_timestamp(),
+    };
+    db::add_invite_join(conn, contact_id, &join).await?;
+    Ok(invite)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_qr() {
+        let dir = tempfile::tempdir().unwrap();
+        for format in [QrFormat::Png, QrFormat::Svg, QrFormat::Text] {
+            let dest_path = dir.path().join(format!("invite.{}", format.extension()));
+            export_qr("OPENPGP4FPR:ABCD#a=bot%40example.org", format, &dest_path).unwrap();
+            assert!(dest_path.metadata().unwrap().len() > 0);
+        }
+    }
+}
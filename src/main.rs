@@ -7,27 +7,58 @@
     clippy::missing_docs_in_private_items,
     missing_docs
 )]
+mod audit;
 mod bot;
 mod cli;
+mod curation;
 mod db;
+mod dispatch;
+mod gc;
+mod icons;
+mod images;
 mod import;
+mod invites;
 mod messages;
+mod metrics;
+mod profile;
 mod request_handlers;
+mod screenshots;
+mod settings;
+mod status;
+mod stores;
+mod subscriptions;
 mod utils;
+mod visibility;
 use std::fs::create_dir_all;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
+use audit::{Actor, Source};
 use bot::Bot;
 use build_script_file_gen::include_file_str;
 use clap::Parser;
-use cli::{BotActions, BotCli};
+use cli::{BotActions, BotCli, CurateActions, InviteActions};
+use deltachat::{config::Config, contact::ContactId, context::Context};
+use gc::RetentionPolicy;
+use log::{info, warn};
+use request_handlers::Channel;
+use settings::Settings;
+use stores::Stores;
+use time::OffsetDateTime;
 use tokio::signal;
-use utils::{project_dirs, AddType};
+use utils::{check_connectivity, configure_account, get_xdcs_dir, AddType};
+use visibility::Visibility;
 
 /// File name of the setup contact QR code.
 const INVITE_QR: &str = "1o1_invite_qr.png";
 
+/// Time to wait for the IMAP connection in the `configure` command.
+const CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time the running handlers get to finish when the bot is stopped.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Bot version printed in response to the `version` command line command
 /// and sent back in response to the `/version` chat message.
 const VERSION: &str = include_file_str!("VERSION");
@@ -36,12 +67,60 @@ const VERSION: &str = include_file_str!("VERSION");
 async fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let cli = BotCli::parse();
+    let data_dir = utils::data_dir(cli.data_dir.as_deref())?;
+    let mut settings = Settings::load(cli.config.as_deref(), &data_dir)?;
+
+    if let BotActions::Start {
+        retention,
+        gc_interval_hours,
+        metrics_addr,
+    } = &cli.action
+    {
+        if settings.is_multi_store() && cli.store.is_none() {
+            let metrics_addr = metrics_addr.or(settings.metrics_addr);
+            let mut accounts = stores::open_accounts(&data_dir).await?;
+            let mut bots = Vec::new();
+            for (name, settings) in settings.stores {
+                let context = stores::get_store_account(&mut accounts, &data_dir, &name).await?;
+                let store_dir = utils::store_data_dir(&data_dir, &name);
+                let bot = create_bot(context, &store_dir, settings, retention, *gc_interval_hours)
+                    .await
+                    .with_context(|| format!("Failed to start store {name}"))?;
+                bots.push((name, bot));
+            }
+            let stores = Stores::start(accounts, bots).await;
+            if let Some(addr) = metrics_addr {
+                tokio::spawn(metrics::serve(metrics::bind(addr).await?, stores.states()));
+            }
+            wait_for_shutdown_signal().await?;
+            return stores.stop(SHUTDOWN_TIMEOUT).await;
+        }
+    }
+
+    let root_dir = data_dir.clone();
+    let data_dir = match &cli.store {
+        Some(name) => {
+            settings = settings
+                .stores
+                .remove(name)
+                .with_context(|| format!("Store {name} is not configured"))?;
+            utils::store_data_dir(&data_dir, name)
+        }
+        None if settings.is_multi_store() => {
+            let names = settings.stores.keys().cloned().collect::<Vec<_>>();
+            bail!(
+                "Several stores are configured, select one with --store: {}",
+                names.join(", ")
+            );
+        }
+        None => data_dir,
+    };
 
     match &cli.action {
-        BotActions::Import { path } => {
+        BotActions::Import { path, channel } => {
             let path = PathBuf::from(path);
-            let bot = Bot::new().await.context("failed to create bot")?;
-            let xdcs_dir = project_dirs()?.config_dir().to_path_buf().join("xdcs");
+            let bot = open_bot(&root_dir, cli.store.as_deref(), &data_dir, settings).await?;
+            let xdcs_dir = get_xdcs_dir(&data_dir);
             create_dir_all(&xdcs_dir)?;
 
             if path.is_file() {
@@ -49,6 +128,8 @@ async fn main() -> Result<()> {
                     path.as_path(),
                     &xdcs_dir,
                     &mut *bot.get_db_connection().await?,
+                    *channel,
+                    &Actor::cli(Source::Import),
                 )
                 .await?
                 {
@@ -61,14 +142,361 @@ async fn main() -> Result<()> {
                     path.as_path(),
                     xdcs_dir,
                     &mut *bot.get_db_connection().await?,
+                    *channel,
+                    &Actor::cli(Source::Import),
                 )
                 .await?;
             } else {
                 eprintln!("{} is not a file or directory", path.display());
             }
         }
+        BotActions::Configure { account } => {
+            let account = account.or(&settings.account);
+            let context = stores::open_context(&root_dir, cli.store.as_deref()).await?;
+            println!(
+                "Logging in to the IMAP and SMTP servers as {}...",
+                account.addr()?
+            );
+            configure_account(&context, &account)
+                .await
+                .context("Login failed")?;
+            println!("Login successful.");
+            println!("Checking the IMAP connection...");
+            check_connectivity(&context, CONNECTIVITY_TIMEOUT)
+                .await
+                .context("Connectivity check failed")?;
+            println!("Connection successful, the account is configured.");
+        }
+        BotActions::Profile {
+            name,
+            status,
+            avatar,
+        } => {
+            let context = stores::open_context(&root_dir, cli.store.as_deref()).await?;
+            if let Some(name) = name {
+                profile::set_display_name(&context, name).await?;
+            }
+            if let Some(status) = status {
+                profile::set_status(&context, status).await?;
+            }
+            if let Some(avatar) = avatar {
+                profile::set_avatar(&context, avatar).await?;
+            }
+            let display_name = context.get_config(Config::Displayname).await?;
+            let status = context.get_config(Config::Selfstatus).await?;
+            let avatar = context.get_config(Config::Selfavatar).await?;
+            println!("Display name: {}", display_name.unwrap_or_default());
+            println!("Status: {}", status.unwrap_or_default());
+            println!("Avatar: {}", avatar.unwrap_or_default());
+        }
+        BotActions::Gc { retention } => {
+            let bot = open_bot(&root_dir, cli.store.as_deref(), &data_dir, settings).await?;
+            let xdcs_dir = get_xdcs_dir(&data_dir);
+            create_dir_all(&xdcs_dir)?;
+            let report = gc::collect_garbage(
+                &mut *bot.get_db_connection().await?,
+                &xdcs_dir,
+                retention,
+                &Actor::cli(Source::Gc),
+                gc::BLOB_GRACE_PERIOD,
+            )
+            .await?;
+            println!("Removed app versions: {}", report.removed_versions);
+            if report.removed_blobs.is_empty() {
+                println!("Removed files: None");
+            } else {
+                println!("Removed files:");
+                for file in report.removed_blobs {
+                    println!("- {}", file.display());
+                }
+            }
+        }
+        BotActions::Installs { app_id, outdated } => {
+            let bot = open_bot(&root_dir, cli.store.as_deref(), &data_dir, settings).await?;
+            let conn = &mut *bot.get_db_connection().await?;
+            let mut lines = Vec::new();
+            match app_id {
+                None => {
+                    for version in db::get_version_adoption(conn).await? {
+                        lines.push(format!(
+                            "{} {}: {} installs, last seen {}",
+                            version.app_id,
+                            version.tag_name,
+                            version.installs,
+                            OffsetDateTime::from_unix_timestamp(version.last_seen)?.date()
+                        ));
+                    }
+                }
+                Some(app_id) => {
+                    for install in db::get_installed_apps(conn, app_id).await? {
+                        // Compared to the newest version on the release channel of the contact.
+                        if *outdated {
+                            let contact_id = ContactId::new(install.contact_id);
+                            let channel = db::get_contact_channel(conn, contact_id).await?;
+                            let newest = db::get_app_info_for_app_id(conn, app_id, channel)
+                                .await
+                                .ok()
+                                .map(|app_info| app_info.tag_name);
+                            if newest.as_ref() == Some(&install.tag_name) {
+                                continue;
+                            }
+                        }
+                        lines.push(format!(
+                            "{} {}, last seen {}",
+                            install.addr,
+                            install.tag_name,
+                            OffsetDateTime::from_unix_timestamp(install.last_seen)?.date()
+                        ));
+                    }
+                }
+            }
+            if lines.is_empty() {
+                println!("No installs");
+            }
+            for line in lines {
+                println!("{line}");
+            }
+        }
+        BotActions::Visibility {
+            app_id,
+            public,
+            contacts,
+            group,
+        } => {
+            let bot = open_bot(&root_dir, cli.store.as_deref(), &data_dir, settings).await?;
+            let conn = &mut *bot.get_db_connection().await?;
+            let Some(app_id) = app_id else {
+                let visibilities = db::get_app_visibilities(conn).await?;
+                if visibilities.is_empty() {
+                    println!("All apps are public");
+                }
+                for (app_id, visibility) in visibilities {
+                    println!("{app_id}: {visibility}");
+                }
+                return Ok(());
+            };
+            let visibility = if *public {
+                Some(Visibility::Public)
+            } else if !contacts.is_empty() {
+                Some(Visibility::Contacts(contacts.clone()))
+            } else {
+                group.map(Visibility::Group)
+            };
+            if let Some(visibility) = visibility {
+                if !db::app_exists(conn, app_id).await? {
+                    warn!("App {app_id} is not in the store yet");
+                }
+                visibility::set_visibility(
+                    conn,
+                    app_id,
+                    &visibility,
+                    &Actor::cli(Source::Visibility),
+                )
+                .await?;
+            }
+            println!("{app_id}: {}", db::get_app_visibility(conn, app_id).await?);
+        }
+        BotActions::Curate { action } => {
+            let bot = open_bot(&root_dir, cli.store.as_deref(), &data_dir, settings).await?;
+            let conn = &mut *bot.get_db_connection().await?;
+            match action {
+                Some(CurateActions::App {
+                    app_id,
+                    featured,
+                    priority,
+                }) => {
+                    if !db::app_exists(conn, app_id).await? {
+                        warn!("App {app_id} is not in the store yet");
+                    }
+                    db::set_app_curation(conn, app_id, *featured, *priority).await?;
+                }
+                Some(CurateActions::Collection {
+                    name,
+                    add,
+                    remove,
+                    position,
+                    delete,
+                }) => {
+                    if *delete {
+                        db::delete_collection(conn, name).await?;
+                    } else {
+                        for app_id in add {
+                            if !db::app_exists(conn, app_id).await? {
+                                warn!("App {app_id} is not in the store yet");
+                            }
+                        }
+                        db::add_to_collection(conn, name, add).await?;
+                        db::remove_from_collection(conn, name, remove).await?;
+                        if let Some(position) = position {
+                            db::set_collection_position(conn, name, *position).await?;
+                        }
+                    }
+                }
+                None => {}
+            }
+
+            let apps = db::get_app_curations(conn).await?;
+            let collections = db::get_collections(conn).await?;
+            if apps.is_empty() && collections.is_empty() {
+                println!("No apps are curated");
+            }
+            for app in apps {
+                let featured = if app.featured { ", featured" } else { "" };
+                println!("{}: priority {}{featured}", app.app_id, app.priority);
+            }
+            for collection in collections {
+                println!(
+                    "Collection {}: {}",
+                    collection.name,
+                    collection.app_ids.join(", ")
+                );
+            }
+        }
+        BotActions::Invite { action } => {
+            let bot = open_bot(&root_dir, cli.store.as_deref(), &data_dir, settings).await?;
+            let context = bot.context();
+            let conn = &mut *bot.get_db_connection().await?;
+            match action {
+                Some(InviteActions::Create { name, app }) => {
+                    if let Some(app_id) = app {
+                        if !db::app_exists(conn, app_id).await? {
+                            warn!("App {app_id} is not in the store yet");
+                        }
+                    }
+                    invites::create_invite(conn, context, name, app.as_deref()).await?;
+                    println!("Created invite {name}");
+                }
+                Some(InviteActions::Rotate { name }) => {
+                    invites::rotate_invite(conn, context, name).await?;
+                    println!("Rotated invite {name}, export it again");
+                }
+                Some(InviteActions::Revoke { name }) => {
+                    invites::revoke_invite(conn, context, name).await?;
+                    println!("Revoked invite {name}");
+                }
+                Some(InviteActions::Export {
+                    name,
+                    format,
+                    output,
+                }) => {
+                    let invite = invites::get_existing_invite(conn, name).await?;
+                    if invite.revoked {
+                        bail!("Invite {name} is revoked, rotate it to use it again");
+                    }
+                    let qr = invites::invite_qr(context, &invite).await?;
+                    let dest_path = output.clone().unwrap_or_else(|| {
+                        data_dir.join(format!("invite_{name}.{}", format.extension()))
+                    });
+                    invites::export_qr(&qr, *format, &dest_path)?;
+                    println!("Exported invite {name} to {}", dest_path.display());
+                }
+                Some(InviteActions::Joins { name }) => {
+                    invites::get_existing_invite(conn, name).await?;
+                    let joins = db::get_invite_joins(conn, name).await?;
+                    if joins.is_empty() {
+                        println!("No joins");
+                    }
+                    for join in joins {
+                        println!(
+                            "{}, joined {}",
+                            join.addr,
+                            OffsetDateTime::from_unix_timestamp(join.joined)?.date()
+                        );
+                    }
+                }
+                None => {
+                    let counts = db::get_invite_join_counts(conn).await?;
+                    let joins = |invite: Option<&str>| {
+                        counts
+                            .iter()
+                            .find(|(name, _)| name.as_deref() == invite)
+                            .map_or(0, |(_, count)| *count)
+                    };
+                    println!("default: {} joins", joins(None));
+                    for invite in db::get_invites(conn).await? {
+                        let revoked = if invite.revoked { ", revoked" } else { "" };
+                        let app = invite
+                            .app_id
+                            .map(|app_id| format!(", opens {app_id}"))
+                            .unwrap_or_default();
+                        println!(
+                            "{}: {} joins, created {}{app}{revoked}",
+                            invite.name,
+                            joins(Some(&invite.name)),
+                            OffsetDateTime::from_unix_timestamp(invite.created)?.date()
+                        );
+                    }
+                }
+            }
+        }
+        BotActions::Log {
+            app_id,
+            since,
+            until,
+            source,
+        } => {
+            let bot = open_bot(&root_dir, cli.store.as_deref(), &data_dir, settings).await?;
+            let timestamp = |date: &time::Date| date.midnight().assume_utc().unix_timestamp();
+            let records = db::get_audit_records(
+                &mut *bot.get_db_connection().await?,
+                app_id.as_deref(),
+                since.as_ref().map(timestamp),
+                until.as_ref().map(timestamp),
+            )
+            .await?;
+            let records = records
+                .into_iter()
+                .filter(|record| source.map_or(true, |source| record.source == source))
+                .collect::<Vec<_>>();
+            if records.is_empty() {
+                println!("No changes");
+            }
+            for record in records {
+                let actor = Actor {
+                    source: record.source,
+                    user: record.user,
+                };
+                let channel = match record.channel {
+                    Some(Channel::Stable) => " (stable)",
+                    Some(Channel::Beta) => " (beta)",
+                    None => "",
+                };
+                let change = match (record.previous_tag_name, record.tag_name) {
+                    (Some(previous), Some(tag_name)) => format!(" {previous} -> {tag_name}"),
+                    (None, Some(tag_name)) => format!(" {tag_name}"),
+                    (Some(previous), None) => format!(" {previous}"),
+                    (None, None) => String::new(),
+                };
+                let file_hash = record
+                    .file_hash
+                    .map(|hash| format!(", sha256 {hash}"))
+                    .unwrap_or_default();
+                println!(
+                    "{} {} {}{change}{channel}, {actor}, serial {}{file_hash}",
+                    OffsetDateTime::from_unix_timestamp(record.time)?,
+                    record.action,
+                    record.app_id.as_deref().unwrap_or("index"),
+                    record.serial
+                );
+            }
+        }
+        BotActions::Status { check_connectivity } => {
+            let dc_db_path = stores::find_dc_db_path(&root_dir, cli.store.as_deref()).await?;
+            let status = status::get_status(
+                &data_dir,
+                dc_db_path.as_deref(),
+                &settings,
+                *check_connectivity,
+            )
+            .await?;
+            print!("{status}");
+            let problems = status.problems();
+            if !problems.is_empty() {
+                bail!("Store is unhealthy: {}", problems.join(", "));
+            }
+        }
         BotActions::ShowQr => {
-            let bot = Bot::new().await.context("Failed to create bot")?;
+            let bot = open_bot(&root_dir, cli.store.as_deref(), &data_dir, settings).await?;
             match db::get_config(&mut *bot.get_db_connection().await?).await {
                 Ok(config) => {
                     qr2term::print_qr(config.invite_qr)?;
@@ -77,11 +505,85 @@ async fn main() -> Result<()> {
             }
         }
         BotActions::Version => print!("{}", VERSION),
-        BotActions::Start => {
-            let mut bot = Bot::new().await.context("Failed to create bot")?;
+        BotActions::Start {
+            retention,
+            gc_interval_hours,
+            metrics_addr,
+        } => {
+            let metrics_addr = metrics_addr.or(settings.metrics_addr);
+            let context = stores::open_context(&root_dir, cli.store.as_deref()).await?;
+            let mut bot =
+                create_bot(context, &data_dir, settings, retention, *gc_interval_hours).await?;
             bot.start().await;
-            signal::ctrl_c().await?;
+            if let Some(addr) = metrics_addr {
+                let stores = vec![(String::new(), bot.state())];
+                tokio::spawn(metrics::serve(metrics::bind(addr).await?, stores));
+            }
+            wait_for_shutdown_signal().await?;
+            bot.stop(SHUTDOWN_TIMEOUT).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Waits for SIGINT or, on Unix, SIGTERM.
+async fn wait_for_shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            res = signal::ctrl_c() => res?,
+            _ = terminate.recv() => {}
         }
     }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await?;
+    info!("Received shutdown signal");
     Ok(())
 }
+
+/// Opens the bot of the store in `data_dir` without starting it,
+/// `store` is the name of the store if several stores are configured.
+async fn open_bot(
+    root_dir: &Path,
+    store: Option<&str>,
+    data_dir: &Path,
+    settings: Settings,
+) -> Result<Bot> {
+    let context = stores::open_context(root_dir, store).await?;
+    Bot::new(context, data_dir, settings)
+        .await
+        .context("Failed to create bot")
+}
+
+/// Creates the bot of the store in `data_dir` and schedules the garbage collection.
+///
+/// The retention policy and garbage collection interval
+/// given on the command line override the settings.
+async fn create_bot(
+    context: Context,
+    data_dir: &Path,
+    settings: Settings,
+    retention: &RetentionPolicy,
+    gc_interval_hours: Option<u64>,
+) -> Result<Bot> {
+    let retention = retention.or(&settings.retention);
+    let gc_interval_hours = gc_interval_hours.or(settings.gc_interval_hours);
+    let mut bot = Bot::new(context, data_dir, settings)
+        .await
+        .context("Failed to create bot")?;
+    if let Some(hours) = gc_interval_hours {
+        if retention.is_empty() {
+            warn!("Garbage collection interval set without a retention policy, ignoring");
+        } else {
+            let interval = hours
+                .checked_mul(60 * 60)
+                .map(Duration::from_secs)
+                .with_context(|| {
+                    format!("Garbage collection interval of {hours} hours is too long")
+                })?;
+            bot.schedule_gc(retention, interval);
+        }
+    }
+    Ok(bot)
+}
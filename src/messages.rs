@@ -1,6 +1,77 @@
 //! Chat messages sent by the bot.
 
+use crate::request_handlers::Channel;
+
 /// Text message sent by the bot together with the `store.xdc`.
 pub fn store_message() -> &'static str {
     r#"Welcome to the webxdc store!"#
 }
+
+/// Text of the message sent with the app of a deep-link invite.
+pub fn deep_link_app(name: &str) -> String {
+    format!("Here is {name}! You can find more apps in the store above.")
+}
+
+/// Usage of the `/downloads` group command.
+pub fn downloads_usage() -> &'static str {
+    r#"Use "/downloads off" to disable downloads in this group or "/downloads on" to enable them again."#
+}
+
+/// Reply to a group member who is not allowed to change the group settings.
+pub fn not_group_admin(admin_addr: &str) -> String {
+    format!("Only the group admin {admin_addr} can change who may download apps.")
+}
+
+/// Confirmation of the `/downloads` group command.
+pub fn downloads_changed(disabled: bool) -> &'static str {
+    if disabled {
+        "Apps can't be downloaded in this group anymore. Members can download them in a 1:1 chat with me."
+    } else {
+        "All group members can download apps now."
+    }
+}
+
+/// Error sent in response to a download request in a group with disabled downloads.
+pub fn group_downloads_disabled() -> &'static str {
+    "Downloads are disabled in this group, please download the app in a 1:1 chat with the bot."
+}
+
+/// Usage of the `/subscribe` and `/unsubscribe` commands.
+pub fn subscribe_usage() -> &'static str {
+    r#"Use "/subscribe <app_id>" to get notified about new versions of an app and "/unsubscribe <app_id>" to stop the notifications."#
+}
+
+/// Confirmation of the `/subscribe` and `/unsubscribe` commands.
+pub fn subscription_changed(app_id: &str, subscribed: bool) -> String {
+    if subscribed {
+        format!("You will be notified about new versions of {app_id}.")
+    } else {
+        format!("You will not be notified about new versions of {app_id} anymore.")
+    }
+}
+
+/// Text of the message sent with a new version of a subscribed app.
+pub fn update_notification(name: &str, tag_name: &str, changelog: Option<&str>) -> String {
+    match changelog {
+        Some(changelog) => format!("{name} {tag_name} is available.\n\n{changelog}"),
+        None => format!("{name} {tag_name} is available."),
+    }
+}
+
+/// Usage of the `/channel` command.
+pub fn channel_usage() -> &'static str {
+    r#"Use "/channel beta" to test new app versions before they are released to everyone or "/channel stable" to only get released versions."#
+}
+
+/// Confirmation of the `/channel` command.
+pub fn channel_changed(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Stable => "You will get the stable versions of the apps.",
+        Channel::Beta => "You will get the beta versions of the apps. Thank you for testing!",
+    }
+}
+
+/// Name of the groups whose QR codes are the named invites.
+pub fn invite_group_name() -> &'static str {
+    "Webxdc Store"
+}
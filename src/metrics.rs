@@ -0,0 +1,413 @@
+//! Counters of events handled by the bot
+//! and the HTTP endpoint exporting them in the OpenMetrics text format.
+//!
+//! The endpoint is enabled with the `metrics_addr` setting
+//! or the `--metrics-addr` option of the `start` command
+//! and serves the metrics of all stores of the process at `/metrics`.
+
+use anyhow::{Context as _, Result};
+use log::{debug, info, warn};
+use prometheus_client::{
+    encoding::{text::encode, EncodeMetric, MetricEncoder},
+    metrics::{counter::Counter, gauge::Gauge, histogram::Histogram, MetricType},
+    registry::Registry,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use crate::{bot::State, db};
+
+/// Upper bounds of the histogram buckets in seconds.
+const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 10.0];
+
+/// Maximum size of an HTTP request head read by the endpoint.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Time a client has to send its request and to receive the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Labels of a sample as names and values.
+type Labels = Vec<(&'static str, String)>;
+
+/// Event counters of a running bot.
+#[derive(Debug)]
+pub struct Metrics {
+    /// WebXDC updates from instances not sent by the store,
+    /// e.g. apps sent to the bot or apps sent by the bot to subscribers.
+    pub foreign_webxdc_updates: Counter,
+
+    /// WebXDC updates from store instances which could not be parsed.
+    pub invalid_webxdc_updates: Counter,
+
+    /// Incoming chat messages.
+    pub incoming_messages: Counter,
+
+    /// Requests from store instances by payload type.
+    pub status_updates: Labeled,
+
+    /// Apps sent to store instances by app ID.
+    pub downloads: Labeled,
+
+    /// Size of the .xdc files sent to store instances in bytes, without the base64 encoding.
+    pub sent_bytes: Counter,
+
+    /// `DownloadError` responses.
+    pub download_errors: Counter,
+
+    /// Time spent handling Delta Chat events in seconds.
+    pub event_handling: Histogram,
+
+    /// Time spent waiting for a connection from the database pool in seconds.
+    pub db_pool_waits: Histogram,
+
+    /// Delta Chat events waiting for a worker of the event dispatcher.
+    pub queued_events: Gauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            foreign_webxdc_updates: Counter::default(),
+            invalid_webxdc_updates: Counter::default(),
+            incoming_messages: Counter::default(),
+            status_updates: Labeled::default(),
+            downloads: Labeled::default(),
+            sent_bytes: Counter::default(),
+            download_errors: Counter::default(),
+            event_handling: Histogram::new(BUCKETS.into_iter()),
+            db_pool_waits: Histogram::new(BUCKETS.into_iter()),
+            queued_events: Gauge::default(),
+        }
+    }
+}
+
+/// Counters by label value.
+#[derive(Debug, Default)]
+pub struct Labeled(
+    /// Counters by label value.
+    Mutex<BTreeMap<String, Counter>>,
+);
+
+impl Labeled {
+    /// Increments the counter of a label value by one.
+    pub fn increment(&self, label: &str) {
+        if let Ok(mut counters) = self.0.lock() {
+            counters.entry(label.to_string()).or_default().inc();
+        }
+    }
+
+    /// Returns the counters by label value.
+    fn get(&self) -> BTreeMap<String, Counter> {
+        self.0
+            .lock()
+            .map(|counters| counters.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Metric family with the samples of several stores,
+/// so each metric is described only once however many stores are served.
+#[derive(Debug)]
+struct Samples<M> {
+    /// Type of the metrics.
+    metric_type: MetricType,
+
+    /// Metrics by their labels.
+    samples: Vec<(Labels, M)>,
+}
+
+impl<M> Samples<M> {
+    /// Creates a family without samples.
+    fn new(metric_type: MetricType) -> Self {
+        Self {
+            metric_type,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Adds a sample.
+    fn add(&mut self, labels: Labels, metric: M) {
+        self.samples.push((labels, metric));
+    }
+}
+
+impl<M: EncodeMetric + Debug> EncodeMetric for Samples<M> {
+    fn encode(&self, mut encoder: MetricEncoder) -> fmt::Result {
+        for (labels, metric) in &self.samples {
+            metric.encode(encoder.encode_family(labels)?)?;
+        }
+        Ok(())
+    }
+
+    fn metric_type(&self) -> MetricType {
+        self.metric_type
+    }
+}
+
+/// Returns a registry with the metrics of the given stores.
+///
+/// The samples of each store have a `store` label with the store name,
+/// unless the name is empty because a single store is served.
+async fn collect(stores: &[(String, Arc<State>)]) -> Result<Registry> {
+    let mut incoming_messages = Samples::new(MetricType::Counter);
+    let mut status_updates = Samples::new(MetricType::Counter);
+    let mut foreign_updates = Samples::new(MetricType::Counter);
+    let mut invalid_updates = Samples::new(MetricType::Counter);
+    let mut downloads = Samples::new(MetricType::Counter);
+    let mut sent_bytes = Samples::new(MetricType::Counter);
+    let mut download_errors = Samples::new(MetricType::Counter);
+    let mut event_handling = Samples::new(MetricType::Histogram);
+    let mut db_pool_waits = Samples::new(MetricType::Histogram);
+    let mut queued_events = Samples::new(MetricType::Gauge);
+    let mut catalog_apps = Samples::new(MetricType::Gauge);
+    let mut catalog_versions = Samples::new(MetricType::Gauge);
+
+    for (name, state) in stores {
+        let labels = if name.is_empty() {
+            vec![]
+        } else {
+            vec![("store", name.clone())]
+        };
+        let with_label = |label: &'static str, value: String| {
+            let mut labels = labels.clone();
+            labels.push((label, value));
+            labels
+        };
+        let metrics = &state.metrics;
+
+        incoming_messages.add(labels.clone(), metrics.incoming_messages.clone());
+        for (payload, counter) in metrics.status_updates.get() {
+            status_updates.add(with_label("type", payload), counter);
+        }
+        foreign_updates.add(labels.clone(), metrics.foreign_webxdc_updates.clone());
+        invalid_updates.add(labels.clone(), metrics.invalid_webxdc_updates.clone());
+        for (app_id, counter) in metrics.downloads.get() {
+            downloads.add(with_label("app_id", app_id), counter);
+        }
+        sent_bytes.add(labels.clone(), metrics.sent_bytes.clone());
+        download_errors.add(labels.clone(), metrics.download_errors.clone());
+        event_handling.add(labels.clone(), metrics.event_handling.clone());
+        db_pool_waits.add(labels.clone(), metrics.db_pool_waits.clone());
+        queued_events.add(labels.clone(), metrics.queued_events.clone());
+
+        let (apps, versions) = db::get_catalog_size(&mut *state.db_connection().await?).await?;
+        let gauge = |value: i64| {
+            let gauge: Gauge = Gauge::default();
+            gauge.set(value);
+            gauge
+        };
+        catalog_apps.add(labels.clone(), gauge(apps));
+        catalog_versions.add(labels, gauge(versions));
+    }
+
+    let mut registry = Registry::default();
+    registry.register(
+        "xdcstore_incoming_messages",
+        "Incoming chat messages",
+        incoming_messages,
+    );
+    registry.register(
+        "xdcstore_status_updates",
+        "Requests from store instances by payload type",
+        status_updates,
+    );
+    registry.register(
+        "xdcstore_foreign_webxdc_updates",
+        "WebXDC updates from instances not sent by the store",
+        foreign_updates,
+    );
+    registry.register(
+        "xdcstore_invalid_webxdc_updates",
+        "WebXDC updates from store instances which could not be parsed",
+        invalid_updates,
+    );
+    registry.register(
+        "xdcstore_downloads",
+        "Apps sent to store instances by app ID",
+        downloads,
+    );
+    registry.register(
+        "xdcstore_sent_bytes",
+        "Bytes of the apps sent to store instances",
+        sent_bytes,
+    );
+    registry.register(
+        "xdcstore_download_errors",
+        "DownloadError responses",
+        download_errors,
+    );
+    registry.register(
+        "xdcstore_event_handler_duration_seconds",
+        "Time spent handling Delta Chat events",
+        event_handling,
+    );
+    registry.register(
+        "xdcstore_db_pool_wait_seconds",
+        "Time spent waiting for a database connection",
+        db_pool_waits,
+    );
+    registry.register(
+        "xdcstore_event_queue_depth",
+        "Delta Chat events waiting to be handled",
+        queued_events,
+    );
+    registry.register("xdcstore_catalog_apps", "Apps in the catalog", catalog_apps);
+    registry.register(
+        "xdcstore_catalog_versions",
+        "App versions in the catalog",
+        catalog_versions,
+    );
+    Ok(registry)
+}
+
+/// Returns the metrics of the given stores in the OpenMetrics text format.
+pub async fn render(stores: &[(String, Arc<State>)]) -> Result<String> {
+    let mut out = String::new();
+    encode(&mut out, &collect(stores).await?)?;
+    Ok(out)
+}
+
+/// Binds the metrics endpoint to `addr`.
+pub async fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind metrics endpoint to {addr}"))?;
+    info!("Serving metrics at http://{addr}/metrics");
+    Ok(listener)
+}
+
+/// Serves the metrics of the given stores at `/metrics`.
+pub async fn serve(listener: TcpListener, stores: Vec<(String, Arc<State>)>) {
+    let stores = Arc::new(stores);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                warn!("Failed to accept metrics connection: {err}");
+                continue;
+            }
+        };
+        let stores = stores.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, &stores).await {
+                debug!("Failed to serve metrics to {peer}: {err:#}");
+            }
+        });
+    }
+}
+
+/// Reads the head of an HTTP request of at most [MAX_REQUEST_SIZE] bytes.
+async fn read_request_head(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 || request.len() > MAX_REQUEST_SIZE {
+            break;
+        }
+        request.extend_from_slice(buf.get(..read).unwrap_or_default());
+    }
+    Ok(request)
+}
+
+/// Answers a single HTTP request and closes the connection.
+///
+/// Clients which do not send their request or read the response
+/// within [REQUEST_TIMEOUT] are disconnected.
+async fn handle_connection(mut stream: TcpStream, stores: &[(String, Arc<State>)]) -> Result<()> {
+    let request = timeout(REQUEST_TIMEOUT, read_request_head(&mut stream))
+        .await
+        .context("timed out reading the request")??;
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => match render(stores).await {
+            Ok(body) => ("200 OK", body),
+            Err(err) => {
+                warn!("Failed to collect metrics: {err:#}");
+                ("500 Internal Server Error", String::new())
+            }
+        },
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    timeout(REQUEST_TIMEOUT, async {
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    })
+    .await
+    .context("timed out sending the response")??;
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    /// Encodes a family in the text format.
+    fn encode_family(
+        name: &str,
+        metric: impl EncodeMetric + Debug + Send + Sync + 'static,
+    ) -> String {
+        let mut registry = Registry::default();
+        registry.register(name, "Test", metric);
+        let mut out = String::new();
+        encode(&mut out, &registry).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new(BUCKETS.into_iter());
+        histogram.observe(0.003);
+        histogram.observe(0.003);
+        histogram.observe(60.0);
+
+        let mut samples = Samples::new(MetricType::Histogram);
+        samples.add(vec![("store", "games".to_string())], histogram);
+        let out = encode_family("latency_seconds", samples);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "# HELP latency_seconds Test.");
+        assert_eq!(lines[1], "# TYPE latency_seconds histogram");
+        assert!(lines.contains(&r#"latency_seconds_count{store="games"} 3"#));
+        assert!(lines.contains(&r#"latency_seconds_bucket{le="0.005",store="games"} 2"#));
+        assert!(lines.contains(&r#"latency_seconds_bucket{le="+Inf",store="games"} 3"#));
+    }
+
+    #[test]
+    fn test_labeled() {
+        let labeled = Labeled::default();
+        labeled.increment("poll");
+        labeled.increment("poll");
+        labeled.increment("chess");
+
+        let mut samples = Samples::new(MetricType::Counter);
+        for store in ["games", "tools"] {
+            for (app_id, counter) in labeled.get() {
+                samples.add(
+                    vec![("store", store.to_string()), ("app_id", app_id)],
+                    counter,
+                );
+            }
+        }
+        let out = encode_family("downloads", samples);
+        // The family is described once for all stores.
+        assert_eq!(out.matches("# TYPE downloads counter").count(), 1);
+        assert!(out.contains(r#"downloads_total{store="games",app_id="poll"} 2"#));
+        assert!(out.contains(r#"downloads_total{store="tools",app_id="chess"} 1"#));
+    }
+}
@@ -0,0 +1,76 @@
+//! Display name, status text and avatar of the bot.
+
+use anyhow::{Context as _, Result};
+use deltachat::{config::Config, context::Context};
+use log::info;
+use sha2::{Digest, Sha256};
+use sqlx::SqliteConnection;
+use std::path::Path;
+
+use crate::{db, settings::Settings};
+
+/// Returns the hex encoded SHA-256 hash of the file at `path`.
+pub fn file_hash(path: &Path) -> Result<String> {
+    let data = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(format!("{:x}", Sha256::digest(data)))
+}
+
+/// Sets the display name of the bot.
+pub async fn set_display_name(ctx: &Context, display_name: &str) -> Result<()> {
+    ctx.set_config(Config::Displayname, Some(display_name))
+        .await
+}
+
+/// Sets the status text of the bot.
+pub async fn set_status(ctx: &Context, status: &str) -> Result<()> {
+    ctx.set_config(Config::Selfstatus, Some(status)).await
+}
+
+/// Sets the avatar of the bot to the image at `path`.
+pub async fn set_avatar(ctx: &Context, path: &Path) -> Result<()> {
+    ctx.set_config(
+        Config::Selfavatar,
+        Some(path.to_str().context("Can't convert image file")?),
+    )
+    .await
+}
+
+/// Applies the display name, status text and avatar from the settings
+/// if they changed since they were applied last.
+///
+/// `changed` contains the names of the settings changed since the last start
+/// or is `None` if the settings are applied for the first time.
+/// The avatar is applied if the content of the avatar file changed.
+///
+/// Values changed with the `profile` command are kept
+/// until the corresponding setting is changed.
+pub async fn apply_profile(
+    ctx: &Context,
+    conn: &mut SqliteConnection,
+    settings: &Settings,
+    changed: Option<&[String]>,
+) -> Result<()> {
+    let is_changed = |name: &str| changed.map_or(true, |changed| changed.iter().any(|c| c == name));
+
+    if let Some(display_name) = &settings.display_name {
+        if is_changed("display_name") {
+            info!("Setting display name to {display_name:?}");
+            set_display_name(ctx, display_name).await?;
+        }
+    }
+    if let Some(status) = &settings.status {
+        if is_changed("status") {
+            info!("Setting status text");
+            set_status(ctx, status).await?;
+        }
+    }
+    if let Some(avatar) = &settings.avatar {
+        let hash = file_hash(avatar)?;
+        if db::get_avatar_hash(conn).await?.as_ref() != Some(&hash) {
+            info!("Setting avatar to {}", avatar.display());
+            set_avatar(ctx, avatar).await?;
+            db::set_avatar_hash(conn, &hash).await?;
+        }
+    }
+    Ok(())
+}
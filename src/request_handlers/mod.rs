@@ -1,20 +1,58 @@
 //! Handlers for the different messages the bot receives
 use crate::{
-    db::RecordId,
-    utils::{get_webxdc_manifest, read_vec},
+    db::RecordId, icons::read_icon, screenshots::read_screenshots, utils::get_webxdc_manifest,
 };
-use anyhow::{Context as _, Result};
+use anyhow::Result;
 use async_zip::tokio::read::fs::ZipFileReader;
-use base64::encode;
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tokio::fs::File;
 use ts_rs::TS;
 
 pub mod store;
 
+/// Version of the protocol between the store frontend and the bot.
+/// Increased whenever requests or responses change.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version of the frontend the bot is compatible with.
+/// Frontends with an older protocol version are blocked until they are upgraded.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// [PROTOCOL_VERSION] exported to the frontend as a literal type,
+/// so the frontend fails to build if its protocol version does not match.
+pub struct ProtocolVersion;
+
+impl TS for ProtocolVersion {
+    const EXPORT_TO: Option<&'static str> = Some("frontend/src/bindings/ProtocolVersion.ts");
+
+    fn decl() -> String {
+        format!("type {} = {};", Self::name(), Self::inline())
+    }
+
+    fn name() -> String {
+        "ProtocolVersion".to_string()
+    }
+
+    fn inline() -> String {
+        PROTOCOL_VERSION.to_string()
+    }
+
+    fn dependencies() -> Vec<ts_rs::Dependency> {
+        Vec::new()
+    }
+
+    fn transparent() -> bool {
+        false
+    }
+}
+
 /// `manifest.toml` structure.
 #[derive(Deserialize)]
 pub struct WebxdcManifest {
@@ -37,6 +75,38 @@ pub struct WebxdcManifest {
     pub date: String,
 }
 
+/// Release channel of an app version.
+///
+/// Contacts on a channel see the versions of their channel
+/// and of all channels before it, i.e. the beta channel includes all versions.
+#[derive(
+    TS,
+    Deserialize,
+    Serialize,
+    ValueEnum,
+    sqlx::Type,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+#[serde(rename_all = "lowercase")]
+#[repr(i32)]
+pub enum Channel {
+    /// Releases for everyone.
+    #[default]
+    Stable = 0,
+
+    /// Releases for testing before they are released on the stable channel.
+    Beta = 1,
+}
+
 /// Information about a single application in the store index.
 #[derive(TS, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
 #[ts(export)]
@@ -61,10 +131,25 @@ pub struct AppInfo {
     /// Source code URL, e.g. `https://codeberg.org/webxdc/checklist`.
     pub source_code_url: String,
 
+    /// Hash of the application icon.
+    /// The frontend requests the icon with a `GetIcons` request.
+    pub icon: String,
+
     /// Application icon encoded as a data URL,
     /// for example `data:image/png;base64,...`.
+    ///
+    /// Only set before the app is added to the database,
+    /// which stores the icon separately.
+    #[serde(skip)]
     pub image: String,
 
+    /// Screenshots encoded as data URLs.
+    ///
+    /// Only set before the app is added to the database,
+    /// the frontend requests them with a `GetScreenshots` request.
+    #[serde(skip)]
+    pub screenshots: Vec<String>,
+
     /// Human-readable application description.
     pub description: String,
 
@@ -78,6 +163,10 @@ pub struct AppInfo {
     /// True if the application has been removed.
     #[serde(skip)]
     pub removed: bool,
+
+    /// Release channel of this version.
+    #[serde(skip)]
+    pub channel: Channel,
 }
 
 impl AppInfo {
@@ -85,25 +174,9 @@ impl AppInfo {
     pub async fn from_xdc(file: &Path) -> Result<Self> {
         let size = i64::try_from(File::open(&file).await?.metadata().await?.len())?;
         let reader = ZipFileReader::new(&file).await?;
-        let entries = reader.file().entries();
         let manifest = get_webxdc_manifest(&reader).await?;
-
-        let image = entries
-            .iter()
-            .enumerate()
-            .map(|(index, entry)| (index, entry.entry().filename().as_str().unwrap_or_default()))
-            .find(|(_, name)| *name == "icon.png" || *name == "icon.jpg");
-        let image = if let Some((index, name)) = image {
-            let res = read_vec(&reader, index).await?;
-            let ending = name
-                .split('.')
-                .nth(1)
-                .context(format!("Can't extract file ending from {name}"))?;
-            let base64 = encode(&res);
-            Ok(format!("data:image/{ending};base64,{base64}"))
-        } else {
-            Err(anyhow::anyhow!("Could not find image"))
-        };
+        let image = read_icon(&reader).await?;
+        let screenshots = read_screenshots(&reader).await?;
 
         Ok(Self {
             size,
@@ -112,22 +185,99 @@ impl AppInfo {
             tag_name: manifest.tag_name,
             name: manifest.name,
             source_code_url: manifest.source_code_url,
-            image: image?,
+            icon: String::new(), // This will be set by the db on insert
+            image,
+            screenshots,
             description: manifest.description,
             xdc_blob_path: file.to_path_buf(),
             id: 0, // This will be updated by the db on insert
             removed: false,
+            channel: Channel::Stable,
         })
     }
 }
 
+/// Curated collection of apps.
+#[derive(TS, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+pub struct Collection {
+    /// Name of the collection, e.g. `Games`.
+    pub name: String,
+
+    /// IDs of the apps in the collection in the curated order.
+    pub app_ids: Vec<String>,
+}
+
+/// Curation of the store managed by the operator.
+#[derive(TS, Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+pub struct Curation {
+    /// IDs of the featured apps, highest priority first.
+    pub featured: Vec<String>,
+
+    /// Ordering priority of the apps, apps with a higher priority are shown first.
+    /// Apps without a priority have priority 0.
+    pub priorities: HashMap<String, i32>,
+
+    /// Curated collections in the order they are shown.
+    pub collections: Vec<Collection>,
+}
+
 /// WebXDC status update.
 #[derive(Serialize, Deserialize)]
 pub struct WebxdcStatusUpdate {
     /// `payload` field of the WebXDC update.
+    pub payload: Request,
+}
+
+/// Payload of a WebXDC status update together with
+/// the protocol version of the frontend that sent it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    /// Protocol version of the frontend.
+    /// Frontends from before the protocol versioning don't send it.
+    #[serde(default)]
+    pub protocol: u32,
+
+    #[allow(clippy::missing_docs_in_private_items)]
+    #[serde(flatten)]
     pub payload: WebxdcStatusUpdatePayload,
 }
 
+/// Compatibility of a store frontend with the bot.
+#[derive(Debug, PartialEq)]
+pub enum Compatibility {
+    /// The frontend is the current store xdc.
+    Current,
+
+    /// The frontend is outdated, but compatible with the bot.
+    /// Upgrading it is suggested.
+    Compatible,
+
+    /// The frontend is not compatible with the bot and has to be replaced
+    /// with the store xdc of the bot.
+    Incompatible,
+}
+
+impl Compatibility {
+    /// Returns the compatibility of a frontend with the given protocol version and `tag_name`
+    /// with the bot serving the store xdc with `current_tag_name`.
+    ///
+    /// Frontends with a newer protocol version than the bot are incompatible
+    /// because the bot can't answer their requests.
+    pub fn of(protocol: u32, tag_name: &str, current_tag_name: &str) -> Self {
+        if tag_name == current_tag_name {
+            Compatibility::Current
+        } else if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol) {
+            Compatibility::Compatible
+        } else {
+            Compatibility::Incompatible
+        }
+    }
+}
+
 /// WebXDC status update payload.
 #[derive(Debug, Serialize, Deserialize, TS)]
 #[ts(export)]
@@ -199,6 +349,75 @@ pub enum WebxdcStatusUpdatePayload {
         error: String,
     },
 
+    /// Request to get notified about new versions of an app
+    /// sent by the frontend to the bot.
+    Subscribe {
+        /// ID of the application.
+        app_id: String,
+    },
+
+    /// Request to stop the notifications about new versions of an app
+    /// sent by the frontend to the bot.
+    Unsubscribe {
+        /// ID of the application.
+        app_id: String,
+    },
+
+    /// Request to change the release channel of the user
+    /// sent by the frontend to the bot.
+    ///
+    /// The bot responds with an `Init` containing the index of the new channel.
+    SetChannel {
+        /// New release channel.
+        channel: Channel,
+    },
+
+    /// Request for the icons with the given hashes
+    /// sent by the frontend to the bot.
+    ///
+    /// The bot responds with one or more `Icons` responses.
+    GetIcons {
+        /// Hashes from the `icon` field of [AppInfo].
+        hashes: Vec<String>,
+    },
+
+    /// Response to the icons request.
+    Icons {
+        /// Icons encoded as data URLs by their hashes.
+        /// Unknown hashes are left out.
+        icons: HashMap<String, String>,
+    },
+
+    /// Request for the screenshots of an app
+    /// sent by the frontend to the bot.
+    GetScreenshots {
+        /// ID of the application.
+        app_id: String,
+    },
+
+    /// Response to the screenshots request.
+    /// Large sets of screenshots are sent in several responses.
+    Screenshots {
+        /// ID of the application.
+        app_id: String,
+
+        /// Position of the first screenshot of the response among all screenshots of the app.
+        offset: usize,
+
+        /// Screenshots encoded as data URLs.
+        /// Empty if the app has no screenshots.
+        screenshots: Vec<String>,
+    },
+
+    /// Response to the subscribe and unsubscribe requests.
+    Subscription {
+        /// ID of the application.
+        app_id: String,
+
+        /// True if the user is subscribed to the application now.
+        subscribed: bool,
+    },
+
     /// Index update response sent by the bot to the frontend.
     Update {
         /// List of new / updated app infos.
@@ -215,6 +434,9 @@ pub enum WebxdcStatusUpdatePayload {
         /// `app_id`s of apps that will receive an update.
         /// The frontend can use these to set the state to updating.
         updating: Vec<String>,
+
+        /// Current curation of the store.
+        curation: Curation,
     },
 
     /// First message send to the store xdc together containing all [AppInfo]s.
@@ -224,5 +446,99 @@ pub enum WebxdcStatusUpdatePayload {
 
         /// Last serial of the store.
         serial: u32,
+
+        /// Current curation of the store.
+        curation: Curation,
     },
 }
+
+impl WebxdcStatusUpdatePayload {
+    /// Returns the name of the payload type, e.g. `Download`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebxdcStatusUpdatePayload::UpdateWebxdc { .. } => "UpdateWebxdc",
+            WebxdcStatusUpdatePayload::Outdated { .. } => "Outdated",
+            WebxdcStatusUpdatePayload::UpdateSent => "UpdateSent",
+            WebxdcStatusUpdatePayload::UpdateRequest { .. } => "UpdateRequest",
+            WebxdcStatusUpdatePayload::Download { .. } => "Download",
+            WebxdcStatusUpdatePayload::DownloadOkay { .. } => "DownloadOkay",
+            WebxdcStatusUpdatePayload::DownloadError { .. } => "DownloadError",
+            WebxdcStatusUpdatePayload::Subscribe { .. } => "Subscribe",
+            WebxdcStatusUpdatePayload::Unsubscribe { .. } => "Unsubscribe",
+            WebxdcStatusUpdatePayload::SetChannel { .. } => "SetChannel",
+            WebxdcStatusUpdatePayload::GetIcons { .. } => "GetIcons",
+            WebxdcStatusUpdatePayload::Icons { .. } => "Icons",
+            WebxdcStatusUpdatePayload::GetScreenshots { .. } => "GetScreenshots",
+            WebxdcStatusUpdatePayload::Screenshots { .. } => "Screenshots",
+            WebxdcStatusUpdatePayload::Subscription { .. } => "Subscription",
+            WebxdcStatusUpdatePayload::Update { .. } => "Update",
+            WebxdcStatusUpdatePayload::Init { .. } => "Init",
+        }
+    }
+
+    /// Returns true if the payload is a request sent by the frontend
+    /// and not a response sent by the bot.
+    pub fn is_request(&self) -> bool {
+        matches!(
+            self,
+            WebxdcStatusUpdatePayload::UpdateWebxdc { .. }
+                | WebxdcStatusUpdatePayload::UpdateRequest { .. }
+                | WebxdcStatusUpdatePayload::Download { .. }
+                | WebxdcStatusUpdatePayload::Subscribe { .. }
+                | WebxdcStatusUpdatePayload::Unsubscribe { .. }
+                | WebxdcStatusUpdatePayload::SetChannel { .. }
+                | WebxdcStatusUpdatePayload::GetIcons { .. }
+                | WebxdcStatusUpdatePayload::GetScreenshots { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compatibility() {
+        assert_eq!(Compatibility::of(0, "v1", "v1"), Compatibility::Current);
+        assert_eq!(
+            Compatibility::of(MIN_PROTOCOL_VERSION, "v1", "v2"),
+            Compatibility::Compatible
+        );
+        assert_eq!(
+            Compatibility::of(PROTOCOL_VERSION, "v1", "v2"),
+            Compatibility::Compatible
+        );
+        assert_eq!(
+            Compatibility::of(MIN_PROTOCOL_VERSION - 1, "v1", "v2"),
+            Compatibility::Incompatible
+        );
+        assert_eq!(
+            Compatibility::of(PROTOCOL_VERSION + 1, "v2", "v1"),
+            Compatibility::Incompatible
+        );
+    }
+
+    #[test]
+    fn export_bindings_protocolversion() {
+        ProtocolVersion::export().unwrap();
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let update: WebxdcStatusUpdate =
+            serde_json::from_str(r#"{"payload":{"type":"Download","app_id":"poll","protocol":1}}"#)
+                .unwrap();
+        assert_eq!(update.payload.protocol, 1);
+        assert!(matches!(
+            update.payload.payload,
+            WebxdcStatusUpdatePayload::Download { app_id } if app_id == "poll"
+        ));
+
+        let update: WebxdcStatusUpdate =
+            serde_json::from_str(r#"{"payload":{"type":"UpdateRequest","serial":3}}"#).unwrap();
+        assert_eq!(update.payload.protocol, 0);
+        assert!(update.payload.payload.is_request());
+        assert_eq!(update.payload.payload.name(), "UpdateRequest");
+    }
+}
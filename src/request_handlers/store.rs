@@ -1,27 +1,237 @@
 //! Handling the WebXDC updates sent to the store frontend.
 
-use super::WebxdcStatusUpdatePayload;
+use super::{Channel, WebxdcStatusUpdatePayload};
 use crate::{
     bot::State,
+    curation::get_curation,
     db,
-    utils::{init_store, send_newest_updates, send_update_payload_only},
+    icons::get_icon_batches,
+    messages,
+    screenshots::into_screenshot_batches,
+    subscriptions,
+    utils::{get_chat_contact, init_store, send_newest_updates, send_update_payload_only},
+    visibility::{get_chat_viewer, Viewer},
 };
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
 use base64::encode;
+use clap::ValueEnum;
 use deltachat::{
     chat::{self, ChatId},
     constants,
+    contact::{Contact, ContactId},
     context::Context,
-    message::MsgId,
+    message::{Message, MsgId, Viewtype},
 };
 use log::{info, warn};
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use time::OffsetDateTime;
+
+/// Time window of [crate::settings::DownloadLimits::max_per_hour].
+const DOWNLOAD_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Time after sending the store to a contact who joined
+/// in which the first message of the contact does not send the store again.
+const WELCOME_WINDOW: Duration = Duration::from_secs(10 * 60);
 
 #[allow(clippy::missing_docs_in_private_items)]
-pub async fn handle_message(context: &Context, state: Arc<State>, chat_id: ChatId) -> Result<()> {
+pub async fn handle_message(context: &Context, state: Arc<State>, msg: &Message) -> Result<()> {
+    let chat_id = msg.get_chat_id();
+    if subscriptions::handle_command(context, &state, chat_id, msg.get_from_id(), &msg.get_text())
+        .await?
+    {
+        return Ok(());
+    }
+
+    if handle_channel_command(context, &state, msg).await? {
+        return Ok(());
+    }
+
     let chat = chat::Chat::load_from_db(context, chat_id).await?;
-    if let constants::Chattype::Single = chat.typ {
-        init_store(context, &state, chat_id).await?;
+    match chat.typ {
+        constants::Chattype::Single => {
+            let contact_id = msg.get_from_id();
+            if db::take_pending_welcome(
+                &mut *state.db_connection().await?,
+                contact_id,
+                welcome_window_start(),
+            )
+            .await?
+            {
+                info!("Store was already sent to {contact_id} after joining");
+            } else {
+                init_store(context, &state, chat_id).await?
+            }
+        }
+        constants::Chattype::Group => {
+            if db::is_invite_chat(&mut *state.db_connection().await?, chat_id).await? {
+                info!("Ignoring message in the group of an invite {chat_id}");
+            } else {
+                handle_group_message(context, &state, msg).await?
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Sends the store to a contact who completed a SecureJoin by scanning the invite QR code.
+/// If the invite is a deep link, the app `app_id` is sent after the store.
+///
+/// The store is sent only once within [WELCOME_WINDOW],
+/// the first message of the contact within the window does not send it again.
+pub async fn handle_joined(
+    context: &Context,
+    state: &State,
+    contact_id: ContactId,
+    app_id: Option<&str>,
+) -> Result<()> {
+    if db::has_pending_welcome(
+        &mut *state.db_connection().await?,
+        contact_id,
+        welcome_window_start(),
+    )
+    .await?
+    {
+        info!("Store was already sent to {contact_id} after joining");
+        return Ok(());
+    }
+    info!("{contact_id} joined, sending store");
+    let chat_id = ChatId::create_for_contact(context, contact_id).await?;
+    init_store(context, state, chat_id).await?;
+    if let Some(app_id) = app_id {
+        send_deep_link_app(context, state, chat_id, app_id).await?;
+    }
+    // Only recorded once the store was sent,
+    // so the next message of the contact sends it if sending failed.
+    db::add_pending_welcome(
+        &mut *state.db_connection().await?,
+        contact_id,
+        OffsetDateTime::now_utc().unix_timestamp(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Sends the app of a deep-link invite to the chat if it is visible to the contact.
+async fn send_deep_link_app(
+    context: &Context,
+    state: &State,
+    chat_id: ChatId,
+    app_id: &str,
+) -> Result<()> {
+    let conn = &mut *state.db_connection().await?;
+    let viewer = get_chat_viewer(context, conn, chat_id).await?;
+    if !viewer.can_see(app_id) {
+        warn!("Deep-link app {app_id} is not visible in {chat_id}");
+        return Ok(());
+    }
+    let app_info = match db::get_app_info_for_app_id(conn, app_id, viewer.channel).await {
+        Ok(app_info) if !app_info.removed => app_info,
+        _ => {
+            warn!("Deep-link app {app_id} is not in the store");
+            return Ok(());
+        }
+    };
+    let mut msg = Message::new(Viewtype::Webxdc);
+    msg.set_text(messages::deep_link_app(&app_info.name));
+    msg.set_file(app_info.xdc_blob_path.display(), None);
+    chat::send_msg(context, chat_id, &mut msg).await?;
+    Ok(())
+}
+
+/// Returns the oldest time of a welcome which is still within [WELCOME_WINDOW].
+fn welcome_window_start() -> i64 {
+    (OffsetDateTime::now_utc() - WELCOME_WINDOW).unix_timestamp()
+}
+
+/// Handles the `/channel` chat command changing the release channel of the sender.
+///
+/// In 1:1 chats a new store with the index of the channel is sent.
+/// Returns false if the message is not a channel command.
+async fn handle_channel_command(context: &Context, state: &State, msg: &Message) -> Result<bool> {
+    let text = msg.get_text();
+    let mut args = text.split_whitespace();
+    if args.next() != Some("/channel") {
+        return Ok(false);
+    }
+    let chat_id = msg.get_chat_id();
+    let Some(channel) = args
+        .next()
+        .and_then(|arg| Channel::from_str(arg, true).ok())
+    else {
+        chat::send_text_msg(context, chat_id, messages::channel_usage().to_string()).await?;
+        return Ok(true);
+    };
+
+    let contact_id = msg.get_from_id();
+    info!("Changing release channel of {contact_id} to {channel:?}");
+    db::set_contact_channel(&mut *state.db_connection().await?, contact_id, channel).await?;
+    chat::send_text_msg(
+        context,
+        chat_id,
+        messages::channel_changed(channel).to_string(),
+    )
+    .await?;
+    if get_chat_contact(context, chat_id).await?.is_some() {
+        init_store(context, state, chat_id).await?;
+    }
+    Ok(true)
+}
+
+/// Handles messages in group chats.
+///
+/// The store is posted when the bot receives the first message of a group,
+/// i.e. when it is added to the group, and on the `/store` command.
+/// The member who added the bot becomes the group admin
+/// and can disable the downloads with the `/downloads` command.
+async fn handle_group_message(context: &Context, state: &State, msg: &Message) -> Result<()> {
+    let chat_id = msg.get_chat_id();
+    let sender = Contact::load_from_db(context, msg.get_from_id()).await?;
+    let group = db::get_group_chat(&mut *state.db_connection().await?, chat_id).await?;
+    let Some(group) = group else {
+        info!(
+            "Added to group {chat_id} by {}, posting store",
+            sender.get_addr()
+        );
+        chat_id.accept(context).await?;
+        db::create_group_chat(
+            &mut *state.db_connection().await?,
+            chat_id,
+            sender.get_addr(),
+        )
+        .await?;
+        init_store(context, state, chat_id).await?;
+        return Ok(());
+    };
+
+    let text = msg.get_text();
+    let mut args = text.split_whitespace();
+    match (args.next(), args.next()) {
+        (Some("/store"), _) => init_store(context, state, chat_id).await?,
+        (Some("/downloads"), policy) => {
+            if sender.get_addr() != group.admin_addr {
+                let reply = messages::not_group_admin(&group.admin_addr);
+                chat::send_text_msg(context, chat_id, reply).await?;
+                return Ok(());
+            }
+            let disabled = match policy {
+                Some("off") => true,
+                Some("on") => false,
+                _ => {
+                    let reply = messages::downloads_usage().to_string();
+                    chat::send_text_msg(context, chat_id, reply).await?;
+                    return Ok(());
+                }
+            };
+            db::set_downloads_disabled(&mut *state.db_connection().await?, chat_id, disabled)
+                .await?;
+            let reply = messages::downloads_changed(disabled).to_string();
+            chat::send_text_msg(context, chat_id, reply).await?;
+        }
+        _ => {}
     }
     Ok(())
 }
@@ -36,52 +246,207 @@ pub async fn handle_status_update(
     match payload {
         WebxdcStatusUpdatePayload::UpdateRequest { serial, apps } => {
             info!("Handling store update request");
+            let chat_id = Message::load_from_db(context, msg_id).await?.get_chat_id();
+            if let Some(contact_id) = get_chat_contact(context, chat_id).await? {
+                let addr = Contact::load_from_db(context, contact_id)
+                    .await?
+                    .get_addr()
+                    .to_string();
+                let now = OffsetDateTime::now_utc().unix_timestamp();
+                db::set_installed_apps(
+                    &mut *state.db_connection().await?,
+                    contact_id,
+                    &addr,
+                    &apps,
+                    now,
+                )
+                .await?;
+            }
+
+            // Apps are only announced as updating if they are sent afterwards.
+            let allowed = check_download_permission(&state, chat_id).await;
+            if let Err(e) = &allowed {
+                info!("Not sending updates to {chat_id}: {e}");
+            }
 
             // Get all updating xdcs
             let mut updating = vec![];
-            let conn = &mut *state.db.acquire().await?;
+            let conn = &mut *state.db_connection().await?;
+            let viewer = get_chat_viewer(context, conn, chat_id).await?;
             for (app_id, ref tag_name) in apps {
-                if db::maybe_get_greater_tag_name(conn, &app_id, tag_name).await? {
+                if allowed.is_ok()
+                    && viewer.can_see(&app_id)
+                    && db::maybe_get_greater_tag_name(conn, &app_id, tag_name, viewer.channel)
+                        .await?
+                {
                     updating.push(app_id);
                 }
             }
 
             info!("Updating multiple client apps: {:?}", updating);
 
-            send_newest_updates(
-                context,
-                msg_id,
-                &mut *state.db.acquire().await?,
-                serial,
-                updating.clone(),
-            )
-            .await?;
+            send_newest_updates(context, msg_id, conn, serial, &viewer, updating.clone()).await?;
 
-            // Send updates
             for app_id in &updating {
                 let context = context.clone();
                 let state = state.clone();
                 let app_id = app_id.clone();
-                let resp = handle_download(&state, app_id).await;
+                let resp = handle_download(&state, app_id, &viewer).await;
                 send_update_payload_only(&context, msg_id, resp).await?;
             }
         }
         WebxdcStatusUpdatePayload::Download { app_id } => {
             info!("Handling store download for {app_id}");
-            let resp = handle_download(&state, app_id).await;
+            let chat_id = Message::load_from_db(context, msg_id).await?.get_chat_id();
+            let allowed = match check_download_permission(&state, chat_id).await {
+                Ok(()) => check_download_rate(&state, chat_id),
+                Err(e) => Err(e),
+            };
+            let resp = if let Err(e) = allowed {
+                warn!("Rejecting download request from {chat_id}: {e}");
+                state.metrics.download_errors.inc();
+                WebxdcStatusUpdatePayload::DownloadError {
+                    error: e.to_string(),
+                    app_id,
+                }
+            } else {
+                let viewer =
+                    get_chat_viewer(context, &mut *state.db_connection().await?, chat_id).await?;
+                handle_download(&state, app_id, &viewer).await
+            };
             send_update_payload_only(context, msg_id, resp).await?;
         }
+        WebxdcStatusUpdatePayload::GetIcons { hashes } => {
+            info!("Handling request for {} icons", hashes.len());
+            let batches = get_icon_batches(&mut *state.db_connection().await?, &hashes).await?;
+            for icons in batches {
+                send_update_payload_only(
+                    context,
+                    msg_id,
+                    WebxdcStatusUpdatePayload::Icons { icons },
+                )
+                .await?;
+            }
+        }
+        WebxdcStatusUpdatePayload::GetScreenshots { app_id } => {
+            info!("Handling screenshots request for {app_id}");
+            let chat_id = Message::load_from_db(context, msg_id).await?.get_chat_id();
+            let conn = &mut *state.db_connection().await?;
+            let viewer = get_chat_viewer(context, conn, chat_id).await?;
+            // Apps which are not available on the channel of the viewer have no screenshots.
+            let screenshots = match db::get_app_info_for_app_id(conn, &app_id, viewer.channel).await
+            {
+                Ok(app_info) if viewer.can_see(&app_id) => {
+                    db::get_screenshots(conn, app_info.id).await?
+                }
+                _ => Vec::new(),
+            };
+            let mut offset = 0;
+            for screenshots in into_screenshot_batches(screenshots) {
+                let len = screenshots.len();
+                send_update_payload_only(
+                    context,
+                    msg_id,
+                    WebxdcStatusUpdatePayload::Screenshots {
+                        app_id: app_id.clone(),
+                        offset,
+                        screenshots,
+                    },
+                )
+                .await?;
+                offset += len;
+            }
+        }
+        WebxdcStatusUpdatePayload::Subscribe { app_id } => {
+            handle_subscription(context, &state, msg_id, app_id, true).await?
+        }
+        WebxdcStatusUpdatePayload::Unsubscribe { app_id } => {
+            handle_subscription(context, &state, msg_id, app_id, false).await?
+        }
+        WebxdcStatusUpdatePayload::SetChannel { channel } => {
+            let chat_id = Message::load_from_db(context, msg_id).await?.get_chat_id();
+            let Some(contact_id) = get_chat_contact(context, chat_id).await? else {
+                info!("Ignoring channel change in chat {chat_id}");
+                return Ok(());
+            };
+            info!("Changing release channel of {contact_id} to {channel:?}");
+            let conn = &mut *state.db_connection().await?;
+            db::set_contact_channel(conn, contact_id, channel).await?;
+
+            // The index of the frontend does not match the new channel anymore,
+            // the frontend replaces it with the `Init` response.
+            let viewer = get_chat_viewer(context, conn, chat_id).await?;
+            let app_infos = viewer.filter(db::get_active_app_infos(conn, channel).await?);
+            let serial = db::get_last_serial(conn).await?;
+            let curation = get_curation(conn, &viewer).await?;
+            send_update_payload_only(
+                context,
+                msg_id,
+                WebxdcStatusUpdatePayload::Init {
+                    app_infos,
+                    serial,
+                    curation,
+                },
+            )
+            .await?;
+        }
         _ => {}
     }
     Ok(())
 }
 
-#[allow(clippy::missing_docs_in_private_items)]
-pub async fn handle_download(state: &State, app_id: String) -> WebxdcStatusUpdatePayload {
-    match get_webxdc_data(state, &app_id).await {
-        Ok((data, name)) => WebxdcStatusUpdatePayload::DownloadOkay { data, name, app_id },
+/// Subscribes or unsubscribes the contact of a 1:1 chat to an app.
+///
+/// Subscription requests from store instances in group chats are ignored
+/// because the requesting member is not known.
+async fn handle_subscription(
+    context: &Context,
+    state: &State,
+    msg_id: MsgId,
+    app_id: String,
+    subscribe: bool,
+) -> Result<()> {
+    let chat_id = Message::load_from_db(context, msg_id).await?.get_chat_id();
+    let Some(contact_id) = get_chat_contact(context, chat_id).await? else {
+        info!("Ignoring subscription request in chat {chat_id}");
+        return Ok(());
+    };
+
+    let conn = &mut *state.db_connection().await?;
+    let res = if subscribe {
+        subscriptions::subscribe(conn, contact_id, &app_id).await
+    } else {
+        subscriptions::unsubscribe(conn, contact_id, &app_id).await
+    };
+    if let Err(e) = res {
+        warn!("Failed to change subscription of {contact_id}: {e:#}");
+    }
+    let subscribed = db::get_subscribers(conn, &app_id)
+        .await?
+        .contains(&contact_id);
+    send_update_payload_only(
+        context,
+        msg_id,
+        WebxdcStatusUpdatePayload::Subscription { app_id, subscribed },
+    )
+    .await
+}
+
+/// Responds to a download request with the newest version of the app visible to the viewer.
+pub async fn handle_download(
+    state: &State,
+    app_id: String,
+    viewer: &Viewer,
+) -> WebxdcStatusUpdatePayload {
+    match get_webxdc_data(state, &app_id, viewer).await {
+        Ok((data, name, size)) => {
+            state.metrics.downloads.increment(&app_id);
+            state.metrics.sent_bytes.inc_by(size);
+            WebxdcStatusUpdatePayload::DownloadOkay { data, name, app_id }
+        }
         Err(e) => {
             warn!("Error while handling download request: {}", e);
+            state.metrics.download_errors.inc();
             WebxdcStatusUpdatePayload::DownloadError {
                 error: e.to_string(),
                 app_id,
@@ -90,18 +455,65 @@ pub async fn handle_download(state: &State, app_id: String) -> WebxdcStatusUpdat
     }
 }
 
-/// Returns the base64 encoded webxdc and the name of the app.
-async fn get_webxdc_data(state: &State, app_id: &str) -> Result<(String, String)> {
-    let app = db::get_app_info_for_app_id(&mut *state.db.acquire().await?, app_id).await?;
-    Ok((
-        encode(
-            &tokio::fs::read(
-                app.xdc_blob_path
-                    .to_str()
-                    .context("Can't covert file '{file:?}' to str")?,
-            )
-            .await?,
-        ),
-        app.name,
-    ))
+/// Returns an error if downloads are disabled in the given group chat.
+///
+/// Downloads can't be restricted to some members
+/// because the bot does not know which member sent a request
+/// and the apps are sent to all members anyway.
+async fn check_download_permission(state: &State, chat_id: ChatId) -> Result<()> {
+    let group = db::get_group_chat(&mut *state.db_connection().await?, chat_id).await?;
+    match group {
+        Some(group) if group.downloads_disabled => bail!(messages::group_downloads_disabled()),
+        _ => Ok(()),
+    }
+}
+
+/// Records a download request from the given chat
+/// and returns an error if the chat exceeded the download limit.
+fn check_download_rate(state: &State, chat_id: ChatId) -> Result<()> {
+    let Some(max_per_hour) = state.settings.downloads.max_per_hour else {
+        return Ok(());
+    };
+    let mut downloads = state
+        .downloads
+        .lock()
+        .map_err(|_| anyhow::anyhow!("download history is poisoned"))?;
+    let now = Instant::now();
+    // Forget chats without recent downloads, so the history does not grow with every chat.
+    downloads.retain(|_, history| {
+        history.retain(|time| now.duration_since(*time) < DOWNLOAD_WINDOW);
+        !history.is_empty()
+    });
+    let history = downloads.entry(chat_id).or_default();
+    if history.len() >= max_per_hour {
+        bail!("Too many downloads, please try again later");
+    }
+    history.push(now);
+    Ok(())
+}
+
+/// Returns the base64 encoded webxdc, the name of the app and the size of the .xdc file in bytes.
+async fn get_webxdc_data(
+    state: &State,
+    app_id: &str,
+    viewer: &Viewer,
+) -> Result<(String, String, u64)> {
+    if !viewer.can_see(app_id) {
+        bail!("Unknown app {app_id}");
+    }
+    let app =
+        db::get_app_info_for_app_id(&mut *state.db_connection().await?, app_id, viewer.channel)
+            .await?;
+    if let Some(max_size) = state.settings.downloads.max_size {
+        if app.size > max_size {
+            bail!("App is too large to be downloaded");
+        }
+    }
+    let xdc = tokio::fs::read(
+        app.xdc_blob_path
+            .to_str()
+            .context("Can't covert file '{file:?}' to str")?,
+    )
+    .await?;
+    Ok((encode(&xdc), app.name, xdc.len() as u64))
 }
@@ -0,0 +1,210 @@
+//! App screenshots.
+//!
+//! Apps can ship screenshots in a `screenshots/` folder of the .xdc file.
+//! Alternatively, the `xdcget.lock` can list screenshot files for an app,
+//! which take precedence over the screenshots in the .xdc file.
+//! Screenshots are downscaled and re-encoded as JPEG at import
+//! and sent to the frontend on demand with a `Screenshots` response.
+
+use anyhow::{bail, Context as _, Result};
+use async_zip::tokio::read::fs::ZipFileReader;
+use image::ImageOutputFormat;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+
+use crate::{icons::MAX_BATCH_SIZE, images::normalize_image, utils::read_vec};
+
+/// Maximum width and height of screenshots in pixels.
+pub const SCREENSHOT_SIZE: u32 = 720;
+
+/// Maximum number of screenshots per app.
+pub const MAX_SCREENSHOTS: usize = 5;
+
+/// Quality of the re-encoded JPEG screenshots.
+const JPEG_QUALITY: u8 = 80;
+
+/// Reads the images in the `screenshots/` folder of an .xdc file in the order of their names
+/// and returns them normalized as data URLs.
+pub async fn read_screenshots(reader: &ZipFileReader) -> Result<Vec<String>> {
+    let mut entries: Vec<(usize, &str)> = reader
+        .file()
+        .entries()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entry)| {
+            let name = entry.entry().filename().as_str().ok()?;
+            is_screenshot(name.strip_prefix("screenshots/")?).then_some((index, name))
+        })
+        .collect();
+    entries.sort_by_key(|(_, name)| *name);
+
+    let mut screenshots = Vec::new();
+    for (index, name) in entries.into_iter().take(MAX_SCREENSHOTS) {
+        let data = read_vec(reader, index).await?;
+        screenshots.push(
+            normalize_screenshot(&data).with_context(|| format!("Invalid screenshot {name}"))?,
+        );
+    }
+    Ok(screenshots)
+}
+
+/// Reads the screenshot files at `paths`, relative to `dir`,
+/// and returns them normalized as data URLs.
+///
+/// Fails for paths which are absolute or lead outside of `dir`,
+/// so an `xdcget.lock` can't make the bot publish arbitrary files.
+pub async fn read_screenshot_files(dir: &Path, paths: &[impl AsRef<Path>]) -> Result<Vec<String>> {
+    let dir = fs::canonicalize(dir)
+        .await
+        .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+    let mut screenshots = Vec::new();
+    for path in paths.iter().take(MAX_SCREENSHOTS) {
+        let path = resolve_screenshot_path(&dir, path.as_ref()).await?;
+        let data = fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        screenshots.push(
+            normalize_screenshot(&data)
+                .with_context(|| format!("Invalid screenshot {}", path.display()))?,
+        );
+    }
+    Ok(screenshots)
+}
+
+/// Returns the canonical path of the screenshot file at `path` relative to `dir`,
+/// which must be canonical.
+async fn resolve_screenshot_path(dir: &Path, path: &Path) -> Result<PathBuf> {
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        bail!(
+            "Screenshot path {} must be relative without ..",
+            path.display()
+        );
+    }
+    let resolved = fs::canonicalize(dir.join(path))
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if !resolved.starts_with(dir) {
+        bail!(
+            "Screenshot {} is outside of {}",
+            path.display(),
+            dir.display()
+        );
+    }
+    Ok(resolved)
+}
+
+/// Splits screenshots into batches of at most [MAX_BATCH_SIZE] bytes.
+/// Screenshots larger than [MAX_BATCH_SIZE] are sent in a batch of their own.
+pub fn into_screenshot_batches(screenshots: Vec<String>) -> Vec<Vec<String>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_size = 0;
+    for screenshot in screenshots {
+        if !batch.is_empty() && batch_size + screenshot.len() > MAX_BATCH_SIZE {
+            batches.push(std::mem::take(&mut batch));
+            batch_size = 0;
+        }
+        batch_size += screenshot.len();
+        batch.push(screenshot);
+    }
+    if !batch.is_empty() || batches.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Returns true if the file name in the `screenshots/` folder is a supported image.
+fn is_screenshot(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    !name.contains('/')
+        && (name.ends_with(".png") || name.ends_with(".jpg") || name.ends_with(".jpeg"))
+}
+
+/// Decodes a PNG or JPEG screenshot, downscales it to fit into [SCREENSHOT_SIZE] pixels
+/// and returns it encoded as a JPEG data URL.
+fn normalize_screenshot(data: &[u8]) -> Result<String> {
+    normalize_image(data, SCREENSHOT_SIZE, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+        .context("Failed to decode screenshot")
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_is_screenshot() {
+        assert!(is_screenshot("1.png"));
+        assert!(is_screenshot("Board.JPG"));
+        assert!(!is_screenshot("notes.txt"));
+        assert!(!is_screenshot("nested/1.png"));
+    }
+
+    #[test]
+    fn test_normalize_screenshot() {
+        let mut png = Vec::new();
+        DynamicImage::new_rgba8(1440, 2560)
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+        let screenshot = normalize_screenshot(&png).unwrap();
+        let jpeg = screenshot.strip_prefix("data:image/jpeg;base64,").unwrap();
+        let image = image::load_from_memory(&base64::decode(jpeg).unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (405, SCREENSHOT_SIZE));
+
+        assert!(normalize_screenshot(b"not an image").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_screenshot_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let dir = root.join("import");
+        std::fs::create_dir_all(dir.join("shots")).unwrap();
+        std::fs::write(dir.join("shots").join("1.png"), "").unwrap();
+        std::fs::write(root.join("secret.png"), "").unwrap();
+        let dir = std::fs::canonicalize(&dir).unwrap();
+
+        assert_eq!(
+            resolve_screenshot_path(&dir, Path::new("shots/1.png"))
+                .await
+                .unwrap(),
+            dir.join("shots").join("1.png")
+        );
+        assert!(resolve_screenshot_path(&dir, Path::new("../secret.png"))
+            .await
+            .is_err());
+        assert!(resolve_screenshot_path(&dir, &root.join("secret.png"))
+            .await
+            .is_err());
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("secret.png"), dir.join("link.png")).unwrap();
+            assert!(resolve_screenshot_path(&dir, Path::new("link.png"))
+                .await
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_into_screenshot_batches() {
+        // Apps without screenshots are answered with an empty batch.
+        assert_eq!(into_screenshot_batches(vec![]), vec![Vec::<String>::new()]);
+
+        let screenshot = |len: usize| "a".repeat(len);
+        let batches = into_screenshot_batches(vec![
+            screenshot(MAX_BATCH_SIZE / 2),
+            screenshot(MAX_BATCH_SIZE / 2),
+            screenshot(MAX_BATCH_SIZE * 2),
+            screenshot(10),
+        ]);
+        assert_eq!(
+            batches.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![2, 1, 1]
+        );
+    }
+}
@@ -0,0 +1,722 @@
+//! Bot settings loaded from the configuration file.
+//!
+//! The configuration file is a TOML file, by default `config.toml` in the data directory:
+//!
+//! ```toml
+//! display_name = "Webxdc Store"
+//! status = "Send me a message to get the store"
+//! welcome_message = "Welcome to the webxdc store!"
+//! avatar = "/srv/xdcstore/avatar.png"
+//! store_xdc = "/srv/xdcstore/store.xdc"
+//!
+//! [account]
+//! addr = "bot@example.org"
+//! mail_pw = "My_P4ssword"
+//! mail_server = "imap.example.org"
+//! mail_security = "ssl"
+//! send_server = "smtp.example.org"
+//! send_port = 587
+//! send_security = "starttls"
+//!
+//! [downloads]
+//! max_size = 10000000
+//! max_per_hour = 100
+//! ```
+//!
+//! All settings are optional.
+//! The `metrics_addr` setting is only read from the top level
+//! as all stores of the process share the metrics endpoint.
+//!
+//! To serve several stores from one bot process,
+//! the settings of each store are given in a `[stores.<name>]` table:
+//!
+//! ```toml
+//! welcome_message = "Welcome to the webxdc store!"
+//!
+//! [stores.games.account]
+//! addr = "games@example.org"
+//! mail_pw = "My_P4ssword"
+//!
+//! [stores.tools]
+//! welcome_message = "Welcome to the tools store!"
+//!
+//! [stores.tools.account]
+//! addr = "tools@example.org"
+//! mail_pw = "My_Other_P4ssword"
+//! ```
+//!
+//! Settings outside of the `stores` tables are defaults for all stores.
+
+use anyhow::{bail, Context as _, Result};
+use clap::{Args, ValueEnum};
+use deltachat::config::Config;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    env,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    gc::RetentionPolicy,
+    messages::store_message,
+    utils::{get_store_xdc_path, store_data_dir},
+};
+
+/// File name of the configuration file in the data directory.
+pub const SETTINGS_FILE: &str = "config.toml";
+
+/// Security of the connection to a mail server.
+#[derive(ValueEnum, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// Detect the security automatically.
+    Automatic,
+    /// Connect with SSL/TLS.
+    Ssl,
+    /// Connect unencrypted and upgrade with STARTTLS.
+    Starttls,
+    /// Unencrypted connection.
+    Plain,
+}
+
+impl Security {
+    /// Returns the value of the corresponding Delta Chat config key.
+    fn config_value(self) -> &'static str {
+        match self {
+            Security::Automatic => "0",
+            Security::Ssl => "1",
+            Security::Starttls => "2",
+            Security::Plain => "3",
+        }
+    }
+}
+
+/// Checking of the mail server TLS certificates.
+#[derive(ValueEnum, Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateChecks {
+    /// Accept invalid certificates only if the server was configured automatically.
+    Automatic,
+    /// Require valid certificates.
+    Strict,
+    /// Accept invalid certificates.
+    AcceptInvalid,
+}
+
+impl CertificateChecks {
+    /// Returns the value of the corresponding Delta Chat config key.
+    fn config_value(self) -> &'static str {
+        match self {
+            CertificateChecks::Automatic => "0",
+            CertificateChecks::Strict => "1",
+            CertificateChecks::AcceptInvalid => "2",
+        }
+    }
+}
+
+/// Credentials and server settings of the bot account.
+///
+/// Missing credentials are taken from the environment variables `addr` and `mail_pw`.
+/// Server settings which are not set are detected automatically.
+#[derive(Args, Deserialize, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountSettings {
+    /// E-mail address of the bot.
+    #[arg(long)]
+    pub addr: Option<String>,
+
+    /// Password of the e-mail account.
+    #[arg(long)]
+    pub mail_pw: Option<String>,
+
+    /// IMAP login name, if different from the e-mail address.
+    #[arg(long)]
+    pub mail_user: Option<String>,
+
+    /// IMAP server hostname.
+    #[arg(long)]
+    pub mail_server: Option<String>,
+
+    /// IMAP server port.
+    #[arg(long)]
+    pub mail_port: Option<u16>,
+
+    /// IMAP connection security.
+    #[arg(long, value_enum)]
+    pub mail_security: Option<Security>,
+
+    /// Checking of the IMAP server certificate.
+    #[arg(long, value_enum)]
+    pub imap_certificate_checks: Option<CertificateChecks>,
+
+    /// SMTP login name, if different from the IMAP login name.
+    #[arg(long)]
+    pub send_user: Option<String>,
+
+    /// SMTP password, if different from the IMAP password.
+    #[arg(long)]
+    pub send_pw: Option<String>,
+
+    /// SMTP server hostname.
+    #[arg(long)]
+    pub send_server: Option<String>,
+
+    /// SMTP server port.
+    #[arg(long)]
+    pub send_port: Option<u16>,
+
+    /// SMTP connection security.
+    #[arg(long, value_enum)]
+    pub send_security: Option<Security>,
+
+    /// Checking of the SMTP server certificate.
+    #[arg(long, value_enum)]
+    pub smtp_certificate_checks: Option<CertificateChecks>,
+
+    /// SOCKS5 proxy hostname. The proxy is used if the hostname is set.
+    #[arg(long)]
+    pub socks5_host: Option<String>,
+
+    /// SOCKS5 proxy port.
+    #[arg(long)]
+    pub socks5_port: Option<u16>,
+
+    /// SOCKS5 proxy user name.
+    #[arg(long)]
+    pub socks5_user: Option<String>,
+
+    /// SOCKS5 proxy password.
+    #[arg(long)]
+    pub socks5_password: Option<String>,
+}
+
+impl std::fmt::Debug for AccountSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        /// Hides the value of a password.
+        fn hidden(pw: &Option<String>) -> Option<&'static str> {
+            pw.as_ref().map(|_| "***")
+        }
+
+        f.debug_struct("AccountSettings")
+            .field("addr", &self.addr)
+            .field("mail_pw", &hidden(&self.mail_pw))
+            .field("mail_user", &self.mail_user)
+            .field("mail_server", &self.mail_server)
+            .field("mail_port", &self.mail_port)
+            .field("mail_security", &self.mail_security)
+            .field("imap_certificate_checks", &self.imap_certificate_checks)
+            .field("send_user", &self.send_user)
+            .field("send_pw", &hidden(&self.send_pw))
+            .field("send_server", &self.send_server)
+            .field("send_port", &self.send_port)
+            .field("send_security", &self.send_security)
+            .field("smtp_certificate_checks", &self.smtp_certificate_checks)
+            .field("socks5_host", &self.socks5_host)
+            .field("socks5_port", &self.socks5_port)
+            .field("socks5_user", &self.socks5_user)
+            .field("socks5_password", &hidden(&self.socks5_password))
+            .finish()
+    }
+}
+
+impl AccountSettings {
+    /// Returns the e-mail address of the bot.
+    pub fn addr(&self) -> Result<String> {
+        match &self.addr {
+            Some(addr) => Ok(addr.clone()),
+            None => env::var("addr")
+                .context("Missing account.addr setting or environment variable addr"),
+        }
+    }
+
+    /// Returns the password of the e-mail account.
+    pub fn mail_pw(&self) -> Result<String> {
+        match &self.mail_pw {
+            Some(pw) => Ok(pw.clone()),
+            None => env::var("mail_pw")
+                .context("Missing account.mail_pw setting or environment variable mail_pw"),
+        }
+    }
+
+    /// Returns the settings with the values of `other` used for all values not set in `self`.
+    pub fn or(&self, other: &AccountSettings) -> AccountSettings {
+        AccountSettings {
+            addr: self.addr.clone().or_else(|| other.addr.clone()),
+            mail_pw: self.mail_pw.clone().or_else(|| other.mail_pw.clone()),
+            mail_user: self.mail_user.clone().or_else(|| other.mail_user.clone()),
+            mail_server: self
+                .mail_server
+                .clone()
+                .or_else(|| other.mail_server.clone()),
+            mail_port: self.mail_port.or(other.mail_port),
+            mail_security: self.mail_security.or(other.mail_security),
+            imap_certificate_checks: self
+                .imap_certificate_checks
+                .or(other.imap_certificate_checks),
+            send_user: self.send_user.clone().or_else(|| other.send_user.clone()),
+            send_pw: self.send_pw.clone().or_else(|| other.send_pw.clone()),
+            send_server: self
+                .send_server
+                .clone()
+                .or_else(|| other.send_server.clone()),
+            send_port: self.send_port.or(other.send_port),
+            send_security: self.send_security.or(other.send_security),
+            smtp_certificate_checks: self
+                .smtp_certificate_checks
+                .or(other.smtp_certificate_checks),
+            socks5_host: self
+                .socks5_host
+                .clone()
+                .or_else(|| other.socks5_host.clone()),
+            socks5_port: self.socks5_port.or(other.socks5_port),
+            socks5_user: self
+                .socks5_user
+                .clone()
+                .or_else(|| other.socks5_user.clone()),
+            socks5_password: self
+                .socks5_password
+                .clone()
+                .or_else(|| other.socks5_password.clone()),
+        }
+    }
+
+    /// Returns the Delta Chat config values for the server settings.
+    ///
+    /// Settings which are not set are returned as `None`
+    /// so that values from a previous configuration are reset.
+    pub fn server_config(&self) -> Vec<(Config, Option<String>)> {
+        vec![
+            (Config::MailUser, self.mail_user.clone()),
+            (Config::MailServer, self.mail_server.clone()),
+            (
+                Config::MailPort,
+                self.mail_port.map(|port| port.to_string()),
+            ),
+            (
+                Config::MailSecurity,
+                self.mail_security
+                    .map(|security| security.config_value().to_string()),
+            ),
+            (
+                Config::ImapCertificateChecks,
+                self.imap_certificate_checks
+                    .map(|checks| checks.config_value().to_string()),
+            ),
+            (Config::SendUser, self.send_user.clone()),
+            (Config::SendPw, self.send_pw.clone()),
+            (Config::SendServer, self.send_server.clone()),
+            (
+                Config::SendPort,
+                self.send_port.map(|port| port.to_string()),
+            ),
+            (
+                Config::SendSecurity,
+                self.send_security
+                    .map(|security| security.config_value().to_string()),
+            ),
+            (
+                Config::SmtpCertificateChecks,
+                self.smtp_certificate_checks
+                    .map(|checks| checks.config_value().to_string()),
+            ),
+            (
+                Config::Socks5Enabled,
+                Some(if self.socks5_host.is_some() { "1" } else { "0" }.to_string()),
+            ),
+            (Config::Socks5Host, self.socks5_host.clone()),
+            (
+                Config::Socks5Port,
+                self.socks5_port.map(|port| port.to_string()),
+            ),
+            (Config::Socks5User, self.socks5_user.clone()),
+            (Config::Socks5Password, self.socks5_password.clone()),
+        ]
+    }
+}
+
+/// Limits for app downloads.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadLimits {
+    /// Maximum size of a downloadable app in bytes.
+    pub max_size: Option<i64>,
+
+    /// Maximum number of download requests per chat and hour.
+    pub max_per_hour: Option<usize>,
+}
+
+/// Bot settings.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Credentials of the bot account.
+    ///
+    /// Not stored in the bot database.
+    #[serde(skip_serializing)]
+    pub account: AccountSettings,
+
+    /// Display name of the bot.
+    pub display_name: Option<String>,
+
+    /// Status text, sent as the signature of the bot messages.
+    pub status: Option<String>,
+
+    /// Text message sent by the bot together with the `store.xdc`.
+    pub welcome_message: String,
+
+    /// Path to the avatar image of the bot.
+    /// If not set, the bundled icon is used when the bot is set up.
+    pub avatar: Option<PathBuf>,
+
+    /// Path to the store frontend .xdc file.
+    /// Defaults to `store.xdc` in the data directory.
+    pub store_xdc: PathBuf,
+
+    /// If true, the assets in the data directory are not overwritten
+    /// by the assets built into the bot binary on startup.
+    ///
+    /// Can also be set with the environment variable `XDCSTORE_KEEP_ASSETS`.
+    pub keep_assets: bool,
+
+    /// Limits for app downloads.
+    pub downloads: DownloadLimits,
+
+    /// Retention policy for old app versions,
+    /// used if the garbage collection runs periodically.
+    pub retention: RetentionPolicy,
+
+    /// Run the garbage collection every given number of hours.
+    pub gc_interval_hours: Option<u64>,
+
+    /// Address of the HTTP endpoint exporting the metrics, e.g. `127.0.0.1:9100`.
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Settings of the stores served by the bot, by store name.
+    ///
+    /// If stores are configured, the other settings in the configuration file
+    /// are used as defaults for all stores.
+    #[serde(skip)]
+    pub stores: BTreeMap<String, Settings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            account: AccountSettings::default(),
+            display_name: None,
+            status: None,
+            welcome_message: store_message().to_string(),
+            avatar: None,
+            store_xdc: PathBuf::new(),
+            keep_assets: false,
+            downloads: DownloadLimits::default(),
+            retention: RetentionPolicy::default(),
+            gc_interval_hours: None,
+            metrics_addr: None,
+            stores: BTreeMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the settings from the configuration file at `path`.
+    ///
+    /// If `path` is `None`, `config.toml` in the data directory is used if it exists.
+    /// The store frontend path defaults to the asset in the data directory.
+    pub fn load(path: Option<&Path>, data_dir: &Path) -> Result<Self> {
+        let default_path = data_dir.join(SETTINGS_FILE);
+        let path = match path {
+            Some(path) => Some(path),
+            None => Some(default_path.as_path()).filter(|path| path.exists()),
+        };
+        let Some(path) = path else {
+            return Settings::default().finish(None, data_dir, "");
+        };
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let parse_error = || format!("failed to parse config file {}", path.display());
+        let mut table: toml::Table = toml::from_str(&content).with_context(parse_error)?;
+        let Some(stores) = table.remove("stores") else {
+            let settings: Settings = toml::from_str(&content).with_context(parse_error)?;
+            return settings.finish(Some(path), data_dir, "");
+        };
+
+        let toml::Value::Table(stores) = stores else {
+            bail!(
+                "invalid config file {}:\n- stores: must be a table",
+                path.display()
+            );
+        };
+        let mut settings: Settings = toml::Value::Table(table.clone())
+            .try_into()
+            .with_context(parse_error)?;
+        for (name, store) in stores {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!(
+                    "invalid config file {}:\n- stores.{name}: store names may only contain letters, digits, '-' and '_'",
+                    path.display()
+                );
+            }
+            let toml::Value::Table(store) = store else {
+                bail!(
+                    "invalid config file {}:\n- stores.{name}: must be a table",
+                    path.display()
+                );
+            };
+            let store: Settings = toml::Value::Table(merge_tables(table.clone(), store))
+                .try_into()
+                .with_context(|| format!("failed to parse stores.{name} in {}", path.display()))?;
+            let store_dir = store_data_dir(data_dir, &name);
+            let store = store.finish(Some(path), &store_dir, &format!("stores.{name}."))?;
+            settings.stores.insert(name, store);
+        }
+        settings.finish(Some(path), data_dir, "")
+    }
+
+    /// Validates the settings loaded from `path`
+    /// and sets the default paths for the data directory.
+    ///
+    /// Errors are reported with the given prefix added to the setting names.
+    fn finish(mut self, path: Option<&Path>, data_dir: &Path, prefix: &str) -> Result<Self> {
+        let mut errors = self.validate();
+        for (name, file) in [
+            ("avatar", self.avatar.as_ref()),
+            ("store_xdc", Some(&self.store_xdc)),
+        ] {
+            let Some(file) = file else { continue };
+            if !file.as_os_str().is_empty() && !file.is_file() {
+                errors.push(format!("{name}: file {} does not exist", file.display()));
+            }
+        }
+        if !errors.is_empty() {
+            let errors = errors.join(&format!("\n- {prefix}"));
+            match path {
+                Some(path) => bail!(
+                    "invalid config file {}:\n- {prefix}{errors}",
+                    path.display()
+                ),
+                None => bail!("invalid configuration:\n- {prefix}{errors}"),
+            }
+        }
+
+        if self.store_xdc.as_os_str().is_empty() {
+            self.store_xdc = get_store_xdc_path(data_dir);
+        }
+        if !env::var("XDCSTORE_KEEP_ASSETS")
+            .unwrap_or_default()
+            .is_empty()
+        {
+            self.keep_assets = true;
+        }
+        Ok(self)
+    }
+
+    /// Checks the settings for invalid values and returns a list of all problems.
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if let Some(addr) = &self.account.addr {
+            if !addr.contains('@') {
+                errors.push(format!("account.addr: {addr:?} is not an e-mail address"));
+            }
+        }
+        for (name, port) in [
+            ("account.mail_port", self.account.mail_port),
+            ("account.send_port", self.account.send_port),
+            ("account.socks5_port", self.account.socks5_port),
+        ] {
+            if port == Some(0) {
+                errors.push(format!("{name}: must not be 0"));
+            }
+        }
+        if self.display_name.as_deref() == Some("") {
+            errors.push("display_name: must not be empty".to_string());
+        }
+        if self.welcome_message.trim().is_empty() {
+            errors.push("welcome_message: must not be empty".to_string());
+        }
+        if let Some(max_size) = self.downloads.max_size {
+            if max_size <= 0 {
+                errors.push(format!("downloads.max_size: {max_size} is not positive"));
+            }
+        }
+        if self.downloads.max_per_hour == Some(0) {
+            errors.push("downloads.max_per_hour: must not be 0".to_string());
+        }
+        match self.gc_interval_hours {
+            Some(0) => errors.push("gc_interval_hours: must not be 0".to_string()),
+            Some(hours) if hours.checked_mul(60 * 60).is_none() => {
+                errors.push(format!("gc_interval_hours: {hours} is too large"))
+            }
+            _ => {}
+        }
+        if self.gc_interval_hours.is_some() && self.retention.is_empty() {
+            errors.push("gc_interval_hours: requires a retention policy".to_string());
+        }
+        errors
+    }
+
+    /// Returns true if several stores are configured.
+    pub fn is_multi_store(&self) -> bool {
+        !self.stores.is_empty()
+    }
+
+    /// Returns the names of the settings which differ from `stored`,
+    /// the serialized settings stored in the bot database.
+    pub fn changed_since(&self, stored: &str) -> Result<Vec<String>> {
+        let Value::Object(current) = serde_json::to_value(self)? else {
+            bail!("settings are not serialized as an object");
+        };
+        let stored: serde_json::Map<String, Value> = serde_json::from_str(stored)?;
+        Ok(current
+            .into_iter()
+            .filter(|(key, value)| stored.get(key) != Some(value))
+            .map(|(key, _)| key)
+            .collect())
+    }
+}
+
+/// Merges the settings of a store into the default settings.
+/// Tables such as `account` are merged key by key.
+fn merge_tables(mut defaults: toml::Table, store: toml::Table) -> toml::Table {
+    for (key, value) in store {
+        let value = match (defaults.remove(&key), value) {
+            (Some(toml::Value::Table(mut default)), toml::Value::Table(table)) => {
+                for (key, value) in table {
+                    default.insert(key, value);
+                }
+                toml::Value::Table(default)
+            }
+            (_, value) => value,
+        };
+        defaults.insert(key, value);
+    }
+    defaults
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_settings() {
+        let settings: Settings = toml::from_str(
+            r#"
+display_name = "Store"
+welcome_message = "Hello!"
+metrics_addr = "127.0.0.1:9100"
+
+[account]
+addr = "bot@example.org"
+send_security = "starttls"
+imap_certificate_checks = "accept_invalid"
+
+[downloads]
+max_per_hour = 10
+"#,
+        )
+        .unwrap();
+        assert_eq!(settings.display_name.as_deref(), Some("Store"));
+        assert_eq!(settings.welcome_message, "Hello!");
+        assert_eq!(settings.account.addr.as_deref(), Some("bot@example.org"));
+        assert_eq!(settings.account.send_security, Some(Security::Starttls));
+        assert_eq!(
+            settings.account.imap_certificate_checks,
+            Some(CertificateChecks::AcceptInvalid)
+        );
+        assert_eq!(settings.downloads.max_per_hour, Some(10));
+        assert_eq!(settings.metrics_addr, Some(([127, 0, 0, 1], 9100).into()));
+        assert!(settings.validate().is_empty());
+
+        assert!(toml::from_str::<Settings>("unknown = 1").is_err());
+    }
+
+    #[test]
+    fn test_validate_settings() {
+        let settings = Settings {
+            account: AccountSettings {
+                addr: Some("example.org".to_string()),
+                ..Default::default()
+            },
+            welcome_message: String::new(),
+            ..Default::default()
+        };
+        let errors = settings.validate();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("account.addr"));
+        assert!(errors[1].starts_with("welcome_message"));
+    }
+
+    #[test]
+    fn test_load_stores() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join(SETTINGS_FILE);
+        std::fs::write(
+            &path,
+            r#"
+welcome_message = "Hello!"
+
+[account]
+mail_server = "imap.example.org"
+
+[stores.games.account]
+addr = "games@example.org"
+
+[stores.tools]
+welcome_message = "Tools!"
+"#,
+        )
+        .unwrap();
+        let settings = Settings::load(Some(&path), dir).unwrap();
+        assert!(settings.is_multi_store());
+
+        let games = &settings.stores["games"];
+        assert_eq!(games.welcome_message, "Hello!");
+        assert_eq!(games.account.addr.as_deref(), Some("games@example.org"));
+        assert_eq!(
+            games.account.mail_server.as_deref(),
+            Some("imap.example.org")
+        );
+        assert_eq!(
+            games.store_xdc,
+            get_store_xdc_path(&store_data_dir(dir, "games"))
+        );
+        assert_eq!(settings.stores["tools"].welcome_message, "Tools!");
+
+        std::fs::write(&path, "[stores.\"a/b\"]").unwrap();
+        assert!(Settings::load(Some(&path), dir).is_err());
+    }
+
+    #[test]
+    fn test_changed_settings() {
+        let settings = Settings::default();
+        let stored = serde_json::to_string(&settings).unwrap();
+        assert!(settings.changed_since(&stored).unwrap().is_empty());
+
+        let changed = Settings {
+            welcome_message: "Hi!".to_string(),
+            ..settings
+        };
+        assert_eq!(
+            changed.changed_since(&stored).unwrap(),
+            vec!["welcome_message".to_string()]
+        );
+        assert_eq!(
+            changed.changed_since("{}").unwrap().len(),
+            serde_json::to_value(&changed)
+                .unwrap()
+                .as_object()
+                .unwrap()
+                .len()
+        );
+    }
+}
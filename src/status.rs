@@ -0,0 +1,415 @@
+//! Health check of a store, shown by the `status` command.
+//!
+//! The command exits with an error status if a problem is found,
+//! so it can be used as a health check of a container or service manager.
+//!
+//! The databases are opened read-only and are not migrated,
+//! so the command can run next to the bot.
+//! The connectivity is reported by the running bot in a file in its data directory.
+//! The report is refreshed every [CONNECTIVITY_REPORT_INTERVAL],
+//! a report older than [MAX_CONNECTIVITY_REPORT_AGE] is left behind by a bot which crashed.
+
+use anyhow::{Context as _, Result};
+use deltachat::{config::Config, context::Connectivity};
+use sqlx::SqliteConnection;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use time::OffsetDateTime;
+use tokio::fs;
+
+use crate::{
+    db,
+    settings::Settings,
+    utils::{
+        get_connectivity_path, get_db_path, get_dc_config, get_webxdc_tag_name, get_xdcs_dir,
+        open_read_only,
+    },
+};
+
+/// Connectivity states in which the bot can receive messages.
+const CONNECTED: [&str; 2] = ["connected", "updating"];
+
+/// Interval in which the running bot refreshes its connectivity report.
+pub const CONNECTIVITY_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Age after which a connectivity report is considered left behind by a bot which is not running.
+const MAX_CONNECTIVITY_REPORT_AGE: Duration = Duration::from_secs(180);
+
+/// State of a store.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// True if the Delta Chat account is configured.
+    pub configured: bool,
+
+    /// E-mail address of the configured account.
+    pub addr: Option<String>,
+
+    /// Connectivity reported by the running bot, `None` if the bot is not running.
+    pub connectivity: Option<String>,
+
+    /// True if a bot which is not running or not connected is a problem.
+    pub require_connectivity: bool,
+
+    /// Number of apps in the catalog.
+    pub apps: i64,
+
+    /// Number of app versions in the catalog.
+    pub versions: i64,
+
+    /// Serial of the app index, `None` if the bot was not set up yet.
+    pub serial: Option<u32>,
+
+    /// `tag_name` of the store frontend or the error reading it.
+    pub store_tag_name: Result<String, String>,
+
+    /// Number of known store instances per `tag_name` of the store frontend.
+    pub store_instances: Vec<(String, i64)>,
+
+    /// Size of the bot database in bytes.
+    pub db_size: u64,
+
+    /// Size of the Delta Chat database in bytes.
+    pub dc_db_size: u64,
+
+    /// Size of the directory with the imported .xdc files in bytes.
+    pub xdcs_size: u64,
+
+    /// .xdc files referenced by the catalog which do not exist.
+    pub missing_blobs: Vec<PathBuf>,
+}
+
+impl Status {
+    /// Returns the problems found, empty if the store is healthy.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.configured {
+            problems.push("account is not configured".to_string());
+        }
+        if self.require_connectivity {
+            match &self.connectivity {
+                None => problems.push("bot is not running".to_string()),
+                Some(connectivity) if !CONNECTED.contains(&connectivity.as_str()) => {
+                    problems.push(format!("bot is {connectivity}"))
+                }
+                Some(_) => {}
+            }
+        }
+        if self.serial.is_none() {
+            problems.push("bot is not set up yet, start the bot first".to_string());
+        }
+        if let Err(err) = &self.store_tag_name {
+            problems.push(format!("store frontend is not readable: {err}"));
+        }
+        if !self.missing_blobs.is_empty() {
+            problems.push(format!(
+                "{} .xdc files are missing",
+                self.missing_blobs.len()
+            ));
+        }
+        problems
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Account: {}",
+            match (&self.addr, self.configured) {
+                (Some(addr), true) => addr.as_str(),
+                _ => "not configured",
+            }
+        )?;
+        writeln!(
+            f,
+            "Connectivity: {}",
+            self.connectivity.as_deref().unwrap_or("bot is not running")
+        )?;
+        writeln!(f, "Catalog: {} apps, {} versions", self.apps, self.versions)?;
+        match self.serial {
+            Some(serial) => writeln!(f, "Serial: {serial}")?,
+            None => writeln!(f, "Serial: not set up")?,
+        }
+        match &self.store_tag_name {
+            Ok(tag_name) => writeln!(f, "Store frontend: {tag_name}")?,
+            Err(err) => writeln!(f, "Store frontend: {err}")?,
+        }
+        let instances = self
+            .store_instances
+            .iter()
+            .map(|(tag_name, count)| format!("{tag_name}: {count}"))
+            .collect::<Vec<_>>();
+        writeln!(
+            f,
+            "Store instances: {} ({})",
+            self.store_instances
+                .iter()
+                .map(|(_, count)| count)
+                .sum::<i64>(),
+            instances.join(", ")
+        )?;
+        writeln!(
+            f,
+            "Disk usage: bot database {}, Delta Chat database {}, xdcs {}",
+            format_size(self.db_size),
+            format_size(self.dc_db_size),
+            format_size(self.xdcs_size)
+        )?;
+        if self.missing_blobs.is_empty() {
+            writeln!(f, "Missing files: None")?;
+        } else {
+            writeln!(f, "Missing files:")?;
+            for path in &self.missing_blobs {
+                writeln!(f, "- {}", path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the state of the store in `data_dir`
+/// whose Delta Chat account is kept in `dc_db_path`, `None` if it does not exist yet.
+///
+/// If `require_connectivity` is true, a bot which is not running or not connected is a problem.
+pub async fn get_status(
+    data_dir: &Path,
+    dc_db_path: Option<&Path>,
+    settings: &Settings,
+    require_connectivity: bool,
+) -> Result<Status> {
+    let mut status = Status {
+        connectivity: read_connectivity(data_dir).await,
+        require_connectivity,
+        store_tag_name: get_webxdc_tag_name(&settings.store_xdc)
+            .await
+            .map_err(|err| format!("{}: {err}", settings.store_xdc.display())),
+        db_size: file_size(&get_db_path(data_dir)),
+        dc_db_size: dc_db_path.map_or(0, file_size),
+        xdcs_size: dir_size(&get_xdcs_dir(data_dir)),
+        ..Default::default()
+    };
+
+    if let Some(dc_db_path) = dc_db_path {
+        let conn = &mut open_read_only(dc_db_path).await?;
+        status.configured = get_dc_config(conn, &Config::Configured.to_string())
+            .await?
+            .as_deref()
+            == Some("1");
+        status.addr = get_dc_config(conn, &Config::ConfiguredAddr.to_string()).await?;
+    }
+
+    let db_path = get_db_path(data_dir);
+    if db_path.exists() {
+        let conn = &mut open_read_only(&db_path).await?;
+        async {
+            (status.apps, status.versions) = db::get_catalog_size(conn).await?;
+            status.serial = db::get_last_serial(conn).await.ok();
+            status.store_instances = db::get_store_instances(conn).await?;
+            status.missing_blobs = get_missing_blobs(conn).await?;
+            anyhow::Ok(())
+        }
+        .await
+        .context("failed to read the bot database, start the bot to update it")?;
+    }
+    Ok(status)
+}
+
+/// Reports the connectivity of the running bot to the `status` command.
+///
+/// The report is written with the current time, see [MAX_CONNECTIVITY_REPORT_AGE].
+pub async fn report_connectivity(data_dir: &Path, connectivity: Connectivity) -> Result<()> {
+    let connectivity = match connectivity {
+        Connectivity::NotConnected => "not connected",
+        Connectivity::Connecting => "connecting",
+        Connectivity::Working => "updating",
+        Connectivity::Connected => "connected",
+    };
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    fs::write(
+        get_connectivity_path(data_dir),
+        format!("{now} {connectivity}"),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Removes the connectivity report when the bot stops.
+pub async fn clear_connectivity(data_dir: &Path) -> Result<()> {
+    let path = get_connectivity_path(data_dir);
+    if path.exists() {
+        fs::remove_file(path).await?;
+    }
+    Ok(())
+}
+
+/// Returns the connectivity reported by the running bot, `None` if the bot is not running.
+async fn read_connectivity(data_dir: &Path) -> Option<String> {
+    let report = fs::read_to_string(get_connectivity_path(data_dir))
+        .await
+        .ok()?;
+    parse_connectivity_report(&report, OffsetDateTime::now_utc().unix_timestamp())
+}
+
+/// Returns the connectivity of a report read at `now`, `None` if the report is outdated.
+fn parse_connectivity_report(report: &str, now: i64) -> Option<String> {
+    let (time, connectivity) = report.trim().split_once(' ')?;
+    let age = now.saturating_sub(time.parse().ok()?);
+    if age > MAX_CONNECTIVITY_REPORT_AGE.as_secs() as i64 {
+        return None;
+    }
+    Some(connectivity.to_string())
+}
+
+/// Returns the .xdc files referenced by the catalog which do not exist.
+async fn get_missing_blobs(conn: &mut SqliteConnection) -> Result<Vec<PathBuf>> {
+    Ok(db::get_xdc_blob_paths(conn)
+        .await?
+        .into_iter()
+        .filter(|path| !path.exists())
+        .collect())
+}
+
+/// Returns the size of a file in bytes, 0 if it does not exist.
+fn file_size(path: &Path) -> u64 {
+    path.metadata().map_or(0, |metadata| metadata.len())
+}
+
+/// Returns the total size of the files in a directory and its subdirectories in bytes.
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = path.read_dir() else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                dir_size(&path)
+            } else {
+                file_size(&path)
+            }
+        })
+        .sum()
+}
+
+/// Formats a size in bytes for humans.
+fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in ["KiB", "MiB", "GiB", "TiB"] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    if unit == "B" {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {unit}")
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::{audit::Actor, bot::BotConfig, db::MIGRATOR, request_handlers::AppInfo};
+    use sqlx::Connection;
+
+    #[tokio::test]
+    async fn test_missing_blobs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("present.xdc"), "12345").unwrap();
+        std::fs::write(dir.join("sub").join("other"), "123").unwrap();
+        assert_eq!(dir_size(dir), 8);
+
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        db::set_config(&mut conn, &BotConfig::default())
+            .await
+            .unwrap();
+        std::fs::write(dir.join("missing.xdc"), "").unwrap();
+        for (app_id, file) in [("present", "present.xdc"), ("missing", "missing.xdc")] {
+            let mut app_info = AppInfo {
+                app_id: app_id.to_string(),
+                xdc_blob_path: dir.join(file),
+                ..Default::default()
+            };
+            db::create_app_info(&mut conn, &mut app_info, &Actor::default())
+                .await
+                .unwrap();
+        }
+        std::fs::remove_file(dir.join("missing.xdc")).unwrap();
+        assert_eq!(
+            get_missing_blobs(&mut conn).await.unwrap(),
+            vec![dir.join("missing.xdc")]
+        );
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn test_problems() {
+        let status = Status {
+            configured: true,
+            serial: Some(1),
+            store_tag_name: Ok("v1".to_string()),
+            ..Default::default()
+        };
+        assert!(status.problems().is_empty());
+
+        let status = Status {
+            require_connectivity: true,
+            missing_blobs: vec![PathBuf::from("a.xdc")],
+            ..status
+        };
+        assert_eq!(
+            status.problems(),
+            vec!["bot is not running", "1 .xdc files are missing"]
+        );
+
+        let status = Status {
+            connectivity: Some("connecting".to_string()),
+            missing_blobs: Vec::new(),
+            ..status
+        };
+        assert_eq!(status.problems(), vec!["bot is connecting"]);
+    }
+
+    #[tokio::test]
+    async fn test_connectivity_report() {
+        let dir = tempfile::tempdir().unwrap();
+        report_connectivity(dir.path(), Connectivity::Working)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_connectivity(dir.path()).await.as_deref(),
+            Some("updating")
+        );
+
+        clear_connectivity(dir.path()).await.unwrap();
+        assert_eq!(read_connectivity(dir.path()).await, None);
+    }
+
+    #[test]
+    fn test_outdated_connectivity_report() {
+        assert_eq!(
+            parse_connectivity_report("1000 not connected\n", 1060).as_deref(),
+            Some("not connected")
+        );
+        // Left behind by a bot which was killed.
+        assert_eq!(parse_connectivity_report("1000 connected", 1181), None);
+        assert_eq!(parse_connectivity_report("connected", 1000), None);
+    }
+}
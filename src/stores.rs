@@ -0,0 +1,190 @@
+//! Several stores served from one process.
+//!
+//! The Delta Chat accounts of the stores are kept by the account manager
+//! in the `accounts` directory of the data directory,
+//! so all stores share a single event loop.
+//! Each account is tagged with the name of its store.
+//! The app index and the imported .xdc files of each store stay in `stores/<name>`.
+
+use anyhow::{bail, Context as _, Result};
+use deltachat::{accounts::Accounts, context::Context};
+use futures::future::join_all;
+use log::{info, warn};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{
+    bot::{spawn_event_loop, Bot, State},
+    utils::{get_accounts_dir, get_dc_config, get_dc_db_path, open_read_only, store_data_dir},
+};
+
+/// UI config key holding the name of the store an account belongs to.
+const STORE_CONFIG_KEY: &str = "ui.xdcstore.store";
+
+/// Name of the database file in the directory of an account of the account manager.
+const ACCOUNT_DB_FILE: &str = "dc.db";
+
+/// Returns the Delta Chat account of the store `name`,
+/// or of the single store in `data_dir` if no name is given.
+///
+/// Accounts of stores which are not known to the account manager yet are added to it,
+/// which is only possible while the stores are not running.
+pub async fn open_context(data_dir: &Path, name: Option<&str>) -> Result<Context> {
+    let Some(name) = name else {
+        return Bot::create_context(data_dir).await;
+    };
+    let accounts_dir = get_accounts_dir(data_dir);
+    if accounts_dir.exists() {
+        let accounts = Accounts::new(accounts_dir.clone(), false).await?;
+        if let Some(context) = find_store_account(&accounts, name).await? {
+            return Ok(context);
+        }
+    }
+    let mut accounts = Accounts::new(accounts_dir, true)
+        .await
+        .context("failed to open the account manager, stop the bot to add a new store")?;
+    get_store_account(&mut accounts, data_dir, name).await
+}
+
+/// Returns the path to the database of the Delta Chat account of the store `name`,
+/// or of the single store in `data_dir` if no name is given,
+/// `None` if the account does not exist yet.
+///
+/// The databases are only read, so this works while the stores are running.
+pub async fn find_dc_db_path(data_dir: &Path, name: Option<&str>) -> Result<Option<PathBuf>> {
+    let Some(name) = name else {
+        let path = get_dc_db_path(data_dir);
+        return Ok(path.exists().then_some(path));
+    };
+    if let Ok(entries) = get_accounts_dir(data_dir).read_dir() {
+        for entry in entries.flatten() {
+            let path = entry.path().join(ACCOUNT_DB_FILE);
+            if !path.exists() {
+                continue;
+            }
+            let conn = &mut open_read_only(&path).await?;
+            if get_dc_config(conn, STORE_CONFIG_KEY).await?.as_deref() == Some(name) {
+                return Ok(Some(path));
+            }
+        }
+    }
+    // Accounts of older versions are only moved to the account manager on start.
+    let path = get_dc_db_path(&store_data_dir(data_dir, name));
+    Ok(path.exists().then_some(path))
+}
+
+/// Opens the account manager in `data_dir`, creating it if it does not exist.
+pub async fn open_accounts(data_dir: &Path) -> Result<Accounts> {
+    Accounts::new(get_accounts_dir(data_dir), true)
+        .await
+        .context("failed to open the account manager, is the bot already running?")
+}
+
+/// Returns the account tagged with the store `name`.
+async fn find_store_account(accounts: &Accounts, name: &str) -> Result<Option<Context>> {
+    for id in accounts.get_all() {
+        let Some(context) = accounts.get_account(id) else {
+            continue;
+        };
+        if context.get_ui_config(STORE_CONFIG_KEY).await?.as_deref() == Some(name) {
+            return Ok(Some(context));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the account of the store `name`, adding it to the account manager if needed.
+///
+/// An account kept in the data directory of the store by older versions
+/// is moved to the account manager.
+pub async fn get_store_account(
+    accounts: &mut Accounts,
+    data_dir: &Path,
+    name: &str,
+) -> Result<Context> {
+    if let Some(context) = find_store_account(accounts, name).await? {
+        return Ok(context);
+    }
+    let dc_db = get_dc_db_path(&store_data_dir(data_dir, name));
+    let id = if dc_db.exists() {
+        info!("Moving the account of store {name} to the account manager");
+        accounts.migrate_account(dc_db).await?
+    } else {
+        accounts.add_account().await?
+    };
+    let context = accounts
+        .get_account(id)
+        .with_context(|| format!("account {id} of store {name} not found"))?;
+    context.set_ui_config(STORE_CONFIG_KEY, Some(name)).await?;
+    Ok(context)
+}
+
+/// Running bots of several stores.
+pub struct Stores {
+    /// Account manager with the accounts of all stores.
+    accounts: Accounts,
+
+    /// Bots by store name.
+    bots: Vec<(String, Bot)>,
+
+    /// Set to true to make the event loop stop.
+    shutdown: watch::Sender<bool>,
+
+    /// Event loop handling the events of all stores.
+    event_loop: JoinHandle<()>,
+}
+
+impl Stores {
+    /// Starts the bots, whose accounts must belong to `accounts`.
+    pub async fn start(accounts: Accounts, mut bots: Vec<(String, Bot)>) -> Self {
+        let targets = bots
+            .iter()
+            .map(|(_, bot)| (bot.account_id(), bot.event_target()))
+            .collect();
+        let (shutdown, receiver) = watch::channel(false);
+        let event_loop = spawn_event_loop(accounts.get_event_emitter(), targets, receiver);
+        for (name, bot) in &mut bots {
+            bot.schedule_notifications();
+            info!("Started store {name}");
+        }
+        accounts.start_io().await;
+        Self {
+            accounts,
+            bots,
+            shutdown,
+            event_loop,
+        }
+    }
+
+    /// Returns the state of each store by name, e.g. to export the metrics.
+    pub fn states(&self) -> Vec<(String, Arc<State>)> {
+        self.bots
+            .iter()
+            .map(|(name, bot)| (name.clone(), bot.state()))
+            .collect()
+    }
+
+    /// Stops the event loop and then the bots, see [Bot::stop].
+    pub async fn stop(self, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        self.shutdown.send_replace(true);
+        let abort = self.event_loop.abort_handle();
+        let finished = tokio::time::timeout(timeout, self.event_loop).await.is_ok();
+        if !finished {
+            warn!("Handlers did not finish within {timeout:?}, aborting them");
+            abort.abort();
+        }
+        let remaining = timeout.saturating_sub(start.elapsed());
+        let results = join_all(self.bots.into_iter().map(|(_, bot)| bot.stop(remaining))).await;
+        self.accounts.stop_io().await;
+        results.into_iter().collect::<Result<()>>()?;
+        if !finished {
+            bail!("Stores were stopped before all handlers finished");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+//! Subscriptions to app updates.
+//!
+//! Contacts subscribe to apps from the store frontend
+//! or with the `/subscribe <app_id>` chat command.
+//! When an import makes a new version of an app available,
+//! the running bot sends the new version to all subscribers of the app.
+
+use anyhow::{bail, Result};
+use async_zip::tokio::read::fs::ZipFileReader;
+use deltachat::{
+    chat::{self, ChatId},
+    contact::ContactId,
+    context::Context,
+    message::{Message, Viewtype},
+};
+use log::{info, warn};
+use sqlx::SqliteConnection;
+use std::{path::Path, time::Duration};
+
+use crate::{
+    bot::State, db, messages, request_handlers::AppInfo, utils::read_string,
+    visibility::get_chat_viewer,
+};
+
+/// Interval in which the bot checks for imported app versions.
+pub const NOTIFY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum length of the changelog sent with a notification.
+const MAX_CHANGELOG_LEN: usize = 2000;
+
+/// Subscribes the contact to new versions of the app.
+pub async fn subscribe(
+    conn: &mut SqliteConnection,
+    contact_id: ContactId,
+    app_id: &str,
+) -> Result<()> {
+    if !db::app_exists(conn, app_id).await? {
+        bail!("Unknown app {app_id}");
+    }
+    db::add_subscription(conn, contact_id, app_id).await?;
+    Ok(())
+}
+
+/// Unsubscribes the contact from new versions of the app.
+pub async fn unsubscribe(
+    conn: &mut SqliteConnection,
+    contact_id: ContactId,
+    app_id: &str,
+) -> Result<()> {
+    db::remove_subscription(conn, contact_id, app_id).await?;
+    Ok(())
+}
+
+/// Handles the `/subscribe` and `/unsubscribe` chat commands.
+///
+/// Returns false if the text is not a subscription command.
+pub async fn handle_command(
+    context: &Context,
+    state: &State,
+    chat_id: ChatId,
+    contact_id: ContactId,
+    text: &str,
+) -> Result<bool> {
+    let mut args = text.split_whitespace();
+    let subscribed = match args.next() {
+        Some("/subscribe") => true,
+        Some("/unsubscribe") => false,
+        _ => return Ok(false),
+    };
+    let reply = match args.next() {
+        Some(app_id) => {
+            let conn = &mut *state.db_connection().await?;
+            let res = if subscribed {
+                subscribe(conn, contact_id, app_id).await
+            } else {
+                unsubscribe(conn, contact_id, app_id).await
+            };
+            match res {
+                Ok(()) => messages::subscription_changed(app_id, subscribed),
+                Err(e) => e.to_string(),
+            }
+        }
+        None => messages::subscribe_usage().to_string(),
+    };
+    chat::send_text_msg(context, chat_id, reply).await?;
+    Ok(true)
+}
+
+/// Sends all app versions imported since the last notification
+/// to the subscribers of the apps on the release channel of the version.
+pub async fn notify_subscribers(context: &Context, state: &State) -> Result<()> {
+    let conn = &mut *state.db_connection().await?;
+    for (channel, app_infos) in db::take_unnotified_app_infos(conn).await? {
+        for app_info in app_infos {
+            if app_info.removed {
+                continue;
+            }
+            let mut subscribers = Vec::new();
+            for contact_id in db::get_subscribers(conn, &app_info.app_id).await? {
+                let chat_id = ChatId::create_for_contact(context, contact_id).await?;
+                let viewer = get_chat_viewer(context, conn, chat_id).await?;
+                if viewer.channel == channel && viewer.can_see(&app_info.app_id) {
+                    subscribers.push(chat_id);
+                }
+            }
+            if subscribers.is_empty() {
+                continue;
+            }
+            info!(
+                "Notifying {} subscribers about {} {}",
+                subscribers.len(),
+                app_info.app_id,
+                app_info.tag_name
+            );
+            let changelog = get_changelog(&app_info.xdc_blob_path)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to read changelog of {}: {e:#}", app_info.app_id);
+                    None
+                });
+            for chat_id in subscribers {
+                if let Err(e) =
+                    send_notification(context, chat_id, &app_info, changelog.as_deref()).await
+                {
+                    warn!(
+                        "Failed to notify {chat_id} about {}: {e:#}",
+                        app_info.app_id
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends the new version of an app to the 1:1 chat with a subscriber.
+async fn send_notification(
+    context: &Context,
+    chat_id: ChatId,
+    app_info: &AppInfo,
+    changelog: Option<&str>,
+) -> Result<()> {
+    let mut msg = Message::new(Viewtype::Webxdc);
+    msg.set_text(messages::update_notification(
+        &app_info.name,
+        &app_info.tag_name,
+        changelog,
+    ));
+    msg.set_file(app_info.xdc_blob_path.display(), None);
+    chat::send_msg(context, chat_id, &mut msg).await?;
+    Ok(())
+}
+
+/// Returns the newest section of the `CHANGELOG.md` in the .xdc file, if any.
+async fn get_changelog(file: &Path) -> Result<Option<String>> {
+    let reader = ZipFileReader::new(file).await?;
+    let index = reader.file().entries().iter().position(|entry| {
+        entry
+            .entry()
+            .filename()
+            .as_str()
+            .map_or(false, |name| name.eq_ignore_ascii_case("changelog.md"))
+    });
+    let Some(index) = index else {
+        return Ok(None);
+    };
+    let changelog = read_string(&reader, index).await?;
+    Ok(Some(newest_section(&changelog)))
+}
+
+/// Returns the first `## ` section of a markdown changelog,
+/// or the whole changelog if it has no sections,
+/// truncated to [MAX_CHANGELOG_LEN] characters.
+fn newest_section(changelog: &str) -> String {
+    let section = match changelog.split_once("## ") {
+        Some((_, rest)) => {
+            let end = rest.find("\n## ").unwrap_or(rest.len());
+            format!("## {}", rest.get(..end).unwrap_or(rest))
+        }
+        None => changelog.to_string(),
+    };
+    let section = section.trim();
+    match section.char_indices().nth(MAX_CHANGELOG_LEN) {
+        Some((end, _)) => format!("{}…", section.get(..end).unwrap_or(section)),
+        None => section.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_newest_section() {
+        let changelog = "# Changelog\n\n## v1.1.0\n- Fix bugs\n\n## v1.0.0\n- Initial release\n";
+        assert_eq!(newest_section(changelog), "## v1.1.0\n- Fix bugs");
+        assert_eq!(newest_section("- Fix bugs\n"), "- Fix bugs");
+        assert_eq!(
+            newest_section(&"a".repeat(MAX_CHANGELOG_LEN + 1)),
+            format!("{}…", "a".repeat(MAX_CHANGELOG_LEN))
+        );
+    }
+}
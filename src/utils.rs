@@ -8,39 +8,59 @@ use async_zip::tokio::read::fs::ZipFileReader;
 use deltachat::{
     chat::{self, ChatId},
     config::Config,
-    context::Context,
+    constants::Chattype,
+    contact::ContactId,
+    context::{Connectivity, Context},
     message::{Message, MsgId, Viewtype},
 };
 use directories::ProjectDirs;
 use itertools::Itertools;
+use log::info;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::{json, Value};
-use sqlx::SqliteConnection;
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, SqliteConnection};
 use std::{
-    env,
     path::{Path, PathBuf},
+    time::Duration,
 };
 use tokio::fs;
 
 use crate::{
+    audit::Actor,
     bot::State,
+    curation::get_curation,
     db,
-    messages::store_message,
     request_handlers::{AppInfo, WebxdcManifest, WebxdcStatusUpdatePayload},
+    settings::AccountSettings,
+    visibility::{get_chat_viewer, Viewer},
 };
 
-#[allow(clippy::missing_docs_in_private_items)]
-pub(crate) fn project_dirs() -> Result<ProjectDirs> {
-    ProjectDirs::from("", "", "XDC Store").context("cannot determine home directory")
+/// Returns the directory where the bot stores its data.
+///
+/// Defaults to the platform-specific configuration directory
+/// if no directory is given.
+pub(crate) fn data_dir(dir: Option<&Path>) -> Result<PathBuf> {
+    match dir {
+        Some(dir) => Ok(dir.to_path_buf()),
+        None => Ok(ProjectDirs::from("", "", "XDC Store")
+            .context("cannot determine home directory")?
+            .config_dir()
+            .to_path_buf()),
+    }
 }
 
-/// Configures the bot account according to the environment variables `addr` and `mail_pw`.
-pub async fn configure_from_env(ctx: &Context) -> Result<()> {
-    let addr = env::var("addr").context("Missing environment variable addr")?;
-    ctx.set_config(Config::Addr, Some(&addr)).await?;
-    let pw = env::var("mail_pw").context("Missing environment variable mail_pw")?;
-    ctx.set_config(Config::MailPw, Some(&pw)).await?;
+/// Configures the bot account with the given credentials and server settings.
+/// Missing credentials are taken from the environment variables `addr` and `mail_pw`.
+///
+/// Can also be used to reconfigure an already configured account.
+pub async fn configure_account(ctx: &Context, account: &AccountSettings) -> Result<()> {
+    ctx.set_config(Config::Addr, Some(&account.addr()?)).await?;
+    ctx.set_config(Config::MailPw, Some(&account.mail_pw()?))
+        .await?;
+    for (key, value) in account.server_config() {
+        ctx.set_config(key, value.as_deref()).await?;
+    }
     ctx.set_config(Config::Bot, Some("1")).await?;
     ctx.set_config(Config::E2eeEnabled, Some("1")).await?;
     ctx.configure()
@@ -49,24 +69,54 @@ pub async fn configure_from_env(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
-/// Unpacks the assets built into the bot binary into the configuration directory.
-pub(crate) fn unpack_assets() -> Result<()> {
-    std::fs::create_dir_all(project_dirs()?.config_dir())?;
+/// Checks that the configured account connects to the IMAP server within `timeout`.
+pub async fn check_connectivity(ctx: &Context, timeout: Duration) -> Result<()> {
+    ctx.start_io().await;
+    let connected = tokio::time::timeout(timeout, async {
+        while ctx.get_connectivity().await < Connectivity::Connected {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    })
+    .await;
+    ctx.stop_io().await;
+    connected.map_err(|_| {
+        anyhow::anyhow!(
+            "not connected to the IMAP server after {} seconds",
+            timeout.as_secs()
+        )
+    })
+}
+
+/// Unpacks the assets built into the bot binary into the data directory.
+pub(crate) fn unpack_assets(data_dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(data_dir)?;
 
     let store_bytes = include_bytes!("../assets/store.xdc");
-    let store_path = get_store_xdc_path()?;
+    let store_path = get_store_xdc_path(data_dir);
     let mut file = File::create(&store_path)
         .with_context(|| format!("failed to create {}", store_path.display()))?;
     file.write_all(store_bytes)?;
 
     let icon_bytes = include_bytes!("../assets/icon.png");
-    let icon_path = get_icon_path()?;
+    let icon_path = get_icon_path(data_dir);
     let mut file = File::create(&icon_path)
         .with_context(|| format!("failed to create {}", icon_path.display()))?;
     file.write_all(icon_bytes)?;
     Ok(())
 }
 
+/// Returns the contact of a 1:1 chat.
+///
+/// Returns `None` for group chats because
+/// the member sending a webxdc update is not known.
+pub async fn get_chat_contact(context: &Context, chat_id: ChatId) -> Result<Option<ContactId>> {
+    let chat = chat::Chat::load_from_db(context, chat_id).await?;
+    match chat.typ {
+        Chattype::Single => Ok(chat::get_chat_contacts(context, chat_id).await?.pop()),
+        _ => Ok(None),
+    }
+}
+
 /// Send newest version to chat together with all [AppInfo]s.
 pub async fn init_store(context: &Context, state: &State, chat_id: ChatId) -> Result<()> {
     update_store(context, state, chat_id, 0).await?;
@@ -87,19 +137,25 @@ pub async fn update_store(
     _serial: u32,
 ) -> Result<()> {
     let mut webxdc_msg = Message::new(Viewtype::Webxdc);
-    webxdc_msg.set_text(store_message().to_string());
-    webxdc_msg.set_file(get_store_xdc_path()?.display(), None);
+    webxdc_msg.set_text(state.settings.welcome_message.clone());
+    webxdc_msg.set_file(state.settings.store_xdc.display(), None);
     chat_id.set_draft(context, Some(&mut webxdc_msg)).await?;
 
-    let conn = &mut *state.db.acquire().await?;
+    let conn = &mut *state.db_connection().await?;
+    let viewer = get_chat_viewer(context, conn, chat_id).await?;
     let serial = 0;
     if serial == 0 {
-        let app_infos = db::get_active_app_infos(conn).await?;
+        let app_infos = viewer.filter(db::get_active_app_infos(conn, viewer.channel).await?);
         let serial = db::get_last_serial(conn).await?;
+        let curation = get_curation(conn, &viewer).await?;
         send_update_payload_only(
             context,
             webxdc_msg.get_id(),
-            WebxdcStatusUpdatePayload::Init { app_infos, serial },
+            WebxdcStatusUpdatePayload::Init {
+                app_infos,
+                serial,
+                curation,
+            },
         )
         .await?;
     } else {
@@ -110,8 +166,9 @@ pub async fn update_store(
         send_newest_updates(
             context,
             webxdc_msg.get_id(),
-            &mut *state.db.acquire().await?,
+            &mut *state.db_connection().await?,
             serial,
+            &viewer,
             vec![],
         )
         .await?;
@@ -129,16 +186,28 @@ pub fn to_hashmap<T: Serialize + for<'a> Deserialize<'a>>(
     serde_json::from_value(serde_json::to_value(a)?)
 }
 
-/// Sends a [deltachat::webxdc::StatusUpdateItem] with all [AppInfo]s greater than the given serial.
+/// Sends a [deltachat::webxdc::StatusUpdateItem] with all [AppInfo]s greater than the given serial
+/// visible to the viewer.
 /// `updating` tells the frontend which apps are going to receive an updated.
+///
+/// If the changes since `serial` have been partially removed by the garbage collection
+/// or the index was reset, e.g. because the visibility of an app changed,
+/// the full index is sent instead, see [send_full_update].
 pub async fn send_newest_updates(
     context: &Context,
     msg_id: MsgId,
     db: &mut SqliteConnection,
     serial: u32,
+    viewer: &Viewer,
     updating: Vec<String>,
 ) -> Result<()> {
-    let app_infos: Vec<_> = db::get_changed_app_infos_since(db, serial).await?;
+    if serial != 0 && serial < db::get_resync_serial(db).await? {
+        info!("Serial {serial} predates a reset of the index, sending the full index");
+        return send_full_update(context, msg_id, db, serial, viewer, updating).await;
+    }
+
+    let app_infos: Vec<_> =
+        viewer.filter(db::get_changed_app_infos_since(db, serial, viewer.channel).await?);
     let old_app_infos = db::get_app_infos_for(
         db,
         &app_infos
@@ -146,6 +215,7 @@ pub async fn send_newest_updates(
             .map(|app_info| app_info.app_id.as_str())
             .collect::<Vec<_>>(),
         serial,
+        viewer.channel,
     )
     .await?;
 
@@ -207,6 +277,39 @@ pub async fn send_newest_updates(
         serial: new_serial,
         old_serial: serial,
         updating,
+        curation: get_curation(db, viewer).await?,
+    };
+    send_update_payload_only(context, msg_id, resp).await?;
+    Ok(())
+}
+
+/// Sends an `Update` response with the full index visible to the viewer.
+///
+/// Unlike an `Init` response, an `Update` keeps the state of the apps the frontend knows,
+/// all other apps the frontend may know are sent as removals.
+async fn send_full_update(
+    context: &Context,
+    msg_id: MsgId,
+    db: &mut SqliteConnection,
+    serial: u32,
+    viewer: &Viewer,
+    updating: Vec<String>,
+) -> Result<()> {
+    let mut app_infos = serde_json::Map::new();
+    for app_id in db::get_known_app_ids(db).await? {
+        app_infos.insert(app_id, Value::Null);
+    }
+    for app_info in viewer.filter(db::get_active_app_infos(db, viewer.channel).await?) {
+        if !app_info.removed {
+            app_infos.insert(app_info.app_id.clone(), serde_json::to_value(app_info)?);
+        }
+    }
+    let resp = WebxdcStatusUpdatePayload::Update {
+        app_infos: Value::Object(app_infos),
+        serial: db::get_last_serial(db).await?,
+        old_serial: serial,
+        updating,
+        curation: get_curation(db, viewer).await?,
     };
     send_update_payload_only(context, msg_id, resp).await?;
     Ok(())
@@ -290,14 +393,18 @@ pub async fn maybe_upgrade_xdc(
     app_info: &mut AppInfo,
     conn: &mut SqliteConnection,
     dest: &Path,
+    actor: &Actor,
 ) -> Result<AddType> {
-    let add_type = if db::app_tag_name_exists(conn, &app_info.app_id, &app_info.tag_name).await? {
-        AddType::Ignored
-    } else if db::app_exists(conn, &app_info.app_id).await? {
-        AddType::Updated
-    } else {
-        AddType::Added
-    };
+    let add_type =
+        if db::app_tag_name_exists(conn, &app_info.app_id, &app_info.tag_name, app_info.channel)
+            .await?
+        {
+            AddType::Ignored
+        } else if db::app_exists(conn, &app_info.app_id).await? {
+            AddType::Updated
+        } else {
+            AddType::Added
+        };
 
     match add_type {
         AddType::Added | AddType::Updated => {
@@ -324,7 +431,7 @@ pub async fn maybe_upgrade_xdc(
                     .file_name()
                     .context("Can't get file name from xdc_blob_dir")?,
             );
-            db::create_app_info(conn, app_info).await?;
+            db::create_app_info(conn, app_info, actor).await?;
         }
         AddType::Ignored => (),
     }
@@ -332,11 +439,62 @@ pub async fn maybe_upgrade_xdc(
 }
 
 /// Returns the file path to the store frontend .xdc file.
-pub fn get_store_xdc_path() -> Result<PathBuf> {
-    Ok(project_dirs()?.config_dir().to_path_buf().join("store.xdc"))
+pub fn get_store_xdc_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("store.xdc")
+}
+
+/// Returns the directory where imported .xdc files are stored.
+pub fn get_xdcs_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("xdcs")
+}
+
+/// Returns the path to the bot database.
+pub fn get_db_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("bot.db")
+}
+
+/// Returns the path to the file in which the running bot reports its connectivity.
+pub fn get_connectivity_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("connectivity")
+}
+
+/// Opens an SQLite database read-only, without creating or migrating it,
+/// so it can be inspected while the bot is running.
+pub async fn open_read_only(path: &Path) -> Result<SqliteConnection> {
+    SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        .connect()
+        .await
+        .with_context(|| format!("failed to open {} read-only", path.display()))
+}
+
+/// Reads a config value from the database of a Delta Chat account opened with [open_read_only].
+pub async fn get_dc_config(conn: &mut SqliteConnection, key: &str) -> Result<Option<String>> {
+    Ok(
+        sqlx::query_scalar("SELECT value FROM config WHERE keyname = ?")
+            .bind(key)
+            .fetch_optional(conn)
+            .await?,
+    )
+}
+
+/// Returns the directory of the account manager keeping the accounts of several stores.
+pub fn get_accounts_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("accounts")
+}
+
+/// Returns the path to the database of the Delta Chat account.
+pub fn get_dc_db_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("deltachat.db")
+}
+
+/// Returns the data directory of the store `name` when several stores are configured.
+pub fn store_data_dir(data_dir: &Path, name: &str) -> PathBuf {
+    data_dir.join("stores").join(name)
 }
 
 /// Returns the file path to the store avatar.
-pub fn get_icon_path() -> Result<PathBuf> {
-    Ok(project_dirs()?.config_dir().to_path_buf().join("icon.png"))
+pub fn get_icon_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("icon.png")
 }
@@ -0,0 +1,139 @@
+//! Visibility of apps restricted to selected contacts.
+//!
+//! Apps are public by default. An app can be restricted
+//! to a list of contacts or to the members of a group chat.
+//! Restricted apps are only sent to the store instances of the allowed users
+//! and can only be downloaded by them.
+
+use anyhow::Result;
+use deltachat::{
+    chat::{self, ChatId},
+    contact::{Contact, ContactId},
+    context::Context,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::{collections::HashSet, fmt};
+
+use crate::{
+    audit::Actor,
+    db,
+    request_handlers::{AppInfo, Channel},
+    utils::get_chat_contact,
+};
+
+/// Users an app is visible to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Visible to everyone.
+    Public,
+
+    /// Visible to the contacts with the given addresses.
+    Contacts(Vec<String>),
+
+    /// Visible to the members of the group chat with the given ID.
+    Group(u32),
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Visibility::Public => write!(f, "public"),
+            Visibility::Contacts(addrs) => write!(f, "contacts {}", addrs.join(", ")),
+            Visibility::Group(chat_id) => write!(f, "members of group {chat_id}"),
+        }
+    }
+}
+
+impl Visibility {
+    /// Returns true if the app is visible in the chat.
+    ///
+    /// `contact_id` is the contact of a 1:1 chat.
+    /// In group chats only public apps and apps restricted to the group are visible.
+    async fn is_visible(
+        &self,
+        context: &Context,
+        chat_id: ChatId,
+        contact_id: Option<ContactId>,
+    ) -> Result<bool> {
+        match (self, contact_id) {
+            (Visibility::Public, _) => Ok(true),
+            (Visibility::Group(group_id), _) if chat_id.to_u32() == *group_id => Ok(true),
+            (Visibility::Group(group_id), Some(contact_id)) => {
+                chat::is_contact_in_chat(context, ChatId::new(*group_id), contact_id).await
+            }
+            (Visibility::Contacts(addrs), Some(contact_id)) => {
+                let contact = Contact::load_from_db(context, contact_id).await?;
+                Ok(addrs
+                    .iter()
+                    .any(|addr| addr.eq_ignore_ascii_case(contact.get_addr())))
+            }
+            (_, None) => Ok(false),
+        }
+    }
+}
+
+/// The user or group chat a store instance is sent to,
+/// determining which apps and versions are visible.
+#[derive(Debug, Default)]
+pub struct Viewer {
+    /// Release channel of the viewer.
+    pub channel: Channel,
+
+    /// IDs of the apps not visible to the viewer.
+    pub hidden: HashSet<String>,
+}
+
+impl Viewer {
+    /// Returns true if the app is visible to the viewer.
+    pub fn can_see(&self, app_id: &str) -> bool {
+        !self.hidden.contains(app_id)
+    }
+
+    /// Removes the apps not visible to the viewer.
+    pub fn filter(&self, app_infos: Vec<AppInfo>) -> Vec<AppInfo> {
+        app_infos
+            .into_iter()
+            .filter(|app_info| self.can_see(&app_info.app_id))
+            .collect()
+    }
+}
+
+/// Returns the viewer of the store in a chat.
+///
+/// Group chats always use the stable channel.
+pub async fn get_chat_viewer(
+    context: &Context,
+    conn: &mut SqliteConnection,
+    chat_id: ChatId,
+) -> Result<Viewer> {
+    let contact_id = get_chat_contact(context, chat_id).await?;
+    let channel = match contact_id {
+        Some(contact_id) => db::get_contact_channel(conn, contact_id).await?,
+        None => Channel::Stable,
+    };
+    let mut hidden = HashSet::new();
+    for (app_id, visibility) in db::get_app_visibilities(conn).await? {
+        if !visibility.is_visible(context, chat_id, contact_id).await? {
+            hidden.insert(app_id);
+        }
+    }
+    Ok(Viewer { channel, hidden })
+}
+
+/// Changes the visibility of an app.
+///
+/// All store frontends receive the full index on their next update,
+/// including removals of the apps they can't see anymore,
+/// so that apps are added or removed according to the new visibility.
+pub async fn set_visibility(
+    conn: &mut SqliteConnection,
+    app_id: &str,
+    visibility: &Visibility,
+    actor: &Actor,
+) -> Result<()> {
+    db::set_app_visibility(conn, app_id, visibility).await?;
+    db::reset_index(conn, actor).await?;
+    Ok(())
+}
@@ -6,6 +6,9 @@ from subprocess import Popen
 
 import pytest
 
+# Curation sent by a store without featured apps, priorities or collections.
+EMPTY_CURATION = {"featured": [], "priorities": {}, "collections": []}
+
 
 def bot_binary_path():
     for path in [
@@ -136,6 +139,7 @@ def test_update(acfactory, storebot):
         "type": "Init",
         "serial": 0,
         "app_infos": [],
+        "curation": EMPTY_CURATION,
     }
 
     # Request updates.
@@ -155,6 +159,7 @@ def test_update(acfactory, storebot):
         "serial": 0,
         "old_serial": 0,
         "updating": [],
+        "curation": EMPTY_CURATION,
     }
 
 
@@ -298,6 +303,7 @@ def test_online_import(acfactory, storebot):
         "type": "Init",
         "serial": 0,
         "app_infos": [],
+        "curation": EMPTY_CURATION,
     }
 
     # Import apps into the running bot.
@@ -496,3 +502,22 @@ def test_remove(acfactory, storebot_example):
     assert len(status_updates) == 3
     payload = status_updates[-1]["payload"]
     assert payload["app_infos"] == {"webxdc-tower-builder": None}
+
+
+def test_data_dir(acfactory, storebot_stopped, tmp_path):
+    """Test that the bot stores its data in the configured data directory."""
+    data_dir = tmp_path / "store"
+    storebot_stopped.start(XDCSTORE_DATA_DIR=str(data_dir))
+    (ac1,) = acfactory.get_online_accounts(1)
+
+    bot_contact = ac1.create_contact(storebot_stopped.addr)
+    bot_chat = bot_contact.create_chat()
+    bot_chat.send_text("hi!")
+
+    msg_in = ac1.wait_next_incoming_message()
+    assert "Welcome to the webxdc store!" in msg_in.text
+
+    assert (data_dir / "deltachat.db").exists()
+    assert (data_dir / "bot.db").exists()
+    assert (data_dir / "store.xdc").exists()
+    assert not (storebot_stopped.home_path / ".config" / "xdcstore").exists()